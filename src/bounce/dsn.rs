@@ -0,0 +1,319 @@
+use crate::mailer::mime::{parse_headers, split_message};
+use openssl::base64::decode_block;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Headers a bounce can arrive under, the first ones carry the envelope sender (VERP) address.
+const RETURN_PATH_HEADERS: [&str; 3] = ["Delivered-To", "X-Original-To", "To"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecipientStatus {
+    pub final_recipient: String,
+    pub action: String,
+    pub status: String,
+    pub diagnostic_code: Option<String>,
+}
+
+/// Delivery status notification (RFC 3464), a `multipart/report` with a
+/// `message/delivery-status` part and usually the returned message or its headers.
+#[derive(Debug, Default)]
+pub struct DeliveryReport {
+    pub recipients: Vec<RecipientStatus>,
+    pub envelope_id: Option<String>,
+    pub return_paths: Vec<String>,
+    pub original_message_id: Option<String>,
+}
+
+impl DeliveryReport {
+    /// Returns `None` for anything that is not a delivery status notification.
+    pub fn parse(raw_email: &[u8]) -> Option<Self> {
+        let (header_block, body) = split_message(raw_email);
+        let headers = parse_headers(header_block);
+        let (mime_type, params) = parse_content_type(&headers);
+        let is_delivery_report = mime_type == "multipart/report"
+            && params
+                .get("report-type")
+                .map_or(false, |t| t.eq_ignore_ascii_case("delivery-status"));
+
+        if !is_delivery_report {
+            return None;
+        }
+
+        let mut delivery_report = Self::default();
+
+        for header_name in RETURN_PATH_HEADERS.iter() {
+            if let Some(address) =
+                get_header(&headers, header_name).map(|v| strip_angle_brackets(&v))
+            {
+                delivery_report.return_paths.push(address);
+            }
+        }
+
+        for part in split_multipart(body, params.get("boundary")?) {
+            let (part_header_block, part_body) = split_message(&part);
+            let part_headers = parse_headers(part_header_block);
+            let part_body = decode_body(&part_headers, part_body);
+
+            match parse_content_type(&part_headers).0.as_str() {
+                "message/delivery-status" | "message/global-delivery-status" => {
+                    delivery_report.parse_delivery_status(&part_body)
+                }
+                "message/rfc822" | "text/rfc822-headers" | "message/global" => {
+                    let (returned_header_block, _) = split_message(&part_body);
+                    let returned_headers = parse_headers(returned_header_block);
+
+                    delivery_report.original_message_id =
+                        get_header(&returned_headers, "Message-ID");
+                }
+                _ => {}
+            }
+        }
+
+        if delivery_report.recipients.is_empty() {
+            return None;
+        }
+
+        Some(delivery_report)
+    }
+
+    /// The first group of fields is about the message, every following group is about one
+    /// recipient.
+    fn parse_delivery_status(&mut self, delivery_status: &[u8]) {
+        let delivery_status = String::from_utf8_lossy(delivery_status).replace("\r\n", "\n");
+        let mut field_groups =
+            delivery_status.split("\n\n").filter(|group| !group.trim().is_empty());
+
+        if let Some(message_fields) = field_groups.next() {
+            let message_fields = parse_headers(message_fields.replace('\n', "\r\n").as_bytes());
+
+            self.envelope_id = get_header(&message_fields, "Original-Envelope-Id");
+        }
+
+        for recipient_fields in field_groups {
+            let recipient_fields = parse_headers(recipient_fields.replace('\n', "\r\n").as_bytes());
+            let final_recipient = match get_header(&recipient_fields, "Final-Recipient") {
+                None => continue,
+                Some(final_recipient) => strip_address_type(&final_recipient),
+            };
+
+            self.recipients.push(RecipientStatus {
+                final_recipient,
+                action: get_header(&recipient_fields, "Action").unwrap_or_default().to_lowercase(),
+                status: get_header(&recipient_fields, "Status")
+                    .and_then(|status| status.split_whitespace().next().map(String::from))
+                    .unwrap_or_default(),
+                diagnostic_code: get_header(&recipient_fields, "Diagnostic-Code")
+                    .map(|diagnostic_code| strip_address_type(&diagnostic_code)),
+            });
+        }
+    }
+
+    /// Matches the report back to the draft through the envelope ID, a VERP return path like
+    /// `bounce+<draft id>@example.com`, or a Message-ID like `<draft id>@example.com`.
+    pub fn draft_id(&self) -> Option<Uuid> {
+        if let Some(draft_id) = self.envelope_id.as_ref().and_then(|id| Uuid::parse_str(id).ok()) {
+            return Some(draft_id);
+        }
+
+        for return_path in self.return_paths.iter() {
+            let local_part = return_path.rsplitn(2, '@').nth(1).unwrap_or_default();
+
+            if let Some(tag_index) = local_part.rfind('+') {
+                if let Ok(draft_id) = Uuid::parse_str(&local_part[tag_index + 1..]) {
+                    return Some(draft_id);
+                }
+            }
+        }
+
+        self.original_message_id
+            .as_ref()
+            .map(|message_id| strip_angle_brackets(message_id))
+            .and_then(|message_id| {
+                Uuid::parse_str(message_id.splitn(2, '@').next().unwrap_or_default()).ok()
+            })
+    }
+}
+
+/// Unfolded and trimmed value of the first header called `name`.
+fn get_header(headers: &[(String, String)], name: &str) -> Option<String> {
+    headers
+        .iter()
+        .find(|(header_name, _)| header_name.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.replace("\r\n", "").trim().to_string())
+}
+
+/// Returns the lowercased MIME type and the parameters, with lowercased names.
+fn parse_content_type(headers: &[(String, String)]) -> (String, HashMap<String, String>) {
+    let content_type = get_header(headers, "Content-Type").unwrap_or_default();
+    let mut segments = Vec::new();
+    let mut segment = String::new();
+    let mut in_quotes = false;
+
+    for c in content_type.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => segments.push(std::mem::take(&mut segment)),
+            _ => segment.push(c),
+        }
+    }
+
+    segments.push(segment);
+
+    let mime_type = segments[0].trim().to_lowercase();
+    let params = segments[1..]
+        .iter()
+        .filter_map(|param| {
+            let equal_index = param.find('=')?;
+
+            Some((
+                param[..equal_index].trim().to_lowercase(),
+                param[equal_index + 1..].trim().into(),
+            ))
+        })
+        .collect();
+
+    (mime_type, params)
+}
+
+/// Returns the body of every part, with its headers.
+fn split_multipart(body: &[u8], boundary: &str) -> Vec<Vec<u8>> {
+    let body = format!("\r\n{}", String::from_utf8_lossy(body));
+    let delimiter = format!("\r\n--{}", boundary);
+
+    body.split(delimiter.as_str())
+        .skip(1)
+        .take_while(|part| !part.starts_with("--"))
+        .filter_map(|part| part.find("\r\n").map(|line_end| part[line_end + 2..].into()))
+        .collect()
+}
+
+fn decode_body(headers: &[(String, String)], body: &[u8]) -> Vec<u8> {
+    let transfer_encoding = get_header(headers, "Content-Transfer-Encoding").unwrap_or_default();
+
+    if transfer_encoding.eq_ignore_ascii_case("base64") {
+        let encoded: String =
+            String::from_utf8_lossy(body).chars().filter(|c| !c.is_whitespace()).collect();
+
+        if let Ok(decoded) = decode_block(&encoded) {
+            return decoded;
+        }
+    }
+
+    body.to_vec()
+}
+
+/// Strips the type of `rfc822; admin@example.com` or `smtp; 550 5.1.1 User unknown`.
+fn strip_address_type(value: &str) -> String {
+    match value.find(';') {
+        Some(semicolon_index) => value[semicolon_index + 1..].trim().into(),
+        None => value.trim().into(),
+    }
+}
+
+fn strip_angle_brackets(value: &str) -> String {
+    match (value.rfind('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => value[start + 1..end].trim().into(),
+        _ => value.trim().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRAFT_ID: &str = "320b0555-4c73-4abf-aaf0-461b84860046";
+
+    fn create_report(return_path: &str, message_id: &str) -> String {
+        format!(
+            "From: Mail Delivery System <MAILER-DAEMON@mx.example.net>\r\n\
+            To: {}\r\n\
+            Subject: Undelivered Mail Returned to Sender\r\n\
+            MIME-Version: 1.0\r\n\
+            Content-Type: multipart/report; report-type=delivery-status;\r\n\
+            \tboundary=\"B0UNDARY; 1\"\r\n\
+            \r\n\
+            This is a MIME-encapsulated message.\r\n\
+            \r\n\
+            --B0UNDARY; 1\r\n\
+            Content-Type: text/plain; charset=us-ascii\r\n\
+            \r\n\
+            I'm sorry to have to inform you that your message could not be delivered.\r\n\
+            \r\n\
+            --B0UNDARY; 1\r\n\
+            Content-Type: message/delivery-status\r\n\
+            \r\n\
+            Reporting-MTA: dns; mx.example.net\r\n\
+            Arrival-Date: Mon, 22 Feb 2021 10:45:22 +0000 (UTC)\r\n\
+            \r\n\
+            Final-Recipient: rfc822; nobody@example.net\r\n\
+            Original-Recipient: rfc822;nobody@example.net\r\n\
+            Action: failed\r\n\
+            Status: 5.1.1\r\n\
+            Diagnostic-Code: smtp; 550 5.1.1 <nobody@example.net>:\r\n\
+            \x20   Recipient address rejected: User unknown\r\n\
+            \r\n\
+            Final-Recipient: rfc822; busy@example.net\r\n\
+            Action: delayed\r\n\
+            Status: 4.2.2 (mailbox full)\r\n\
+            \r\n\
+            --B0UNDARY; 1\r\n\
+            Content-Type: text/rfc822-headers\r\n\
+            \r\n\
+            From: noreply@example.com\r\n\
+            To: nobody@example.net\r\n\
+            Message-ID: {}\r\n\
+            \r\n\
+            --B0UNDARY; 1--\r\n",
+            return_path, message_id
+        )
+    }
+
+    #[test]
+    fn test_parse_delivery_report() {
+        let raw_email = create_report("bounce@example.com", "<abc@example.com>");
+        let delivery_report = DeliveryReport::parse(raw_email.as_bytes()).unwrap();
+
+        assert_eq!(
+            delivery_report.recipients,
+            vec![
+                RecipientStatus {
+                    final_recipient: "nobody@example.net".into(),
+                    action: "failed".into(),
+                    status: "5.1.1".into(),
+                    diagnostic_code: Some(
+                        "550 5.1.1 <nobody@example.net>:    Recipient address rejected: User \
+                        unknown"
+                            .into()
+                    ),
+                },
+                RecipientStatus {
+                    final_recipient: "busy@example.net".into(),
+                    action: "delayed".into(),
+                    status: "4.2.2".into(),
+                    diagnostic_code: None,
+                },
+            ]
+        );
+        assert_eq!(delivery_report.original_message_id.as_deref(), Some("<abc@example.com>"));
+        assert_eq!(delivery_report.draft_id(), None);
+        assert!(DeliveryReport::parse(b"From: admin@example.com\r\n\r\nHello!!\r\n").is_none());
+    }
+
+    #[test]
+    fn test_match_draft_id() {
+        let draft_id = Uuid::parse_str(DRAFT_ID).unwrap();
+        let verp_return_path = format!("<bounce+{}@example.com>", DRAFT_ID);
+        let message_id = format!("<{}@example.com>", DRAFT_ID);
+        let by_return_path = create_report(&verp_return_path, "<abc@example.com>");
+        let by_message_id = create_report("bounce@example.com", &message_id);
+
+        assert_eq!(
+            DeliveryReport::parse(by_return_path.as_bytes()).unwrap().draft_id(),
+            Some(draft_id)
+        );
+        assert_eq!(
+            DeliveryReport::parse(by_message_id.as_bytes()).unwrap().draft_id(),
+            Some(draft_id)
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use crate::{anyerror, AnyResult};
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// Untagged response line, with the literals (`{size}` followed by raw bytes) taken out.
+pub struct ImapResponse {
+    pub line: String,
+    pub literals: Vec<Vec<u8>>,
+}
+
+/// Minimal IMAP4rev1 client (RFC 3501), only what polling a bounce mailbox needs.
+pub struct ImapClient<S: Read + Write> {
+    reader: BufReader<S>,
+    tag_counter: usize,
+}
+
+impl<S: Read + Write> ImapClient<S> {
+    pub fn new(stream: S) -> AnyResult<Self> {
+        let mut imap_client = Self { reader: BufReader::new(stream), tag_counter: 0 };
+        let greeting = imap_client.read_line()?;
+
+        if !greeting.starts_with("* OK") {
+            return Err(anyerror!("Unexpected IMAP greeting: {}", greeting.trim_end()));
+        }
+
+        Ok(imap_client)
+    }
+
+    pub fn login(&mut self, user: &str, pass: &str) -> AnyResult<()> {
+        self.command(&format!("LOGIN {} {}", quote(user), quote(pass))).map(|_| ())
+    }
+
+    /// Returns the `UIDVALIDITY` of the mailbox, UIDs seen before are void once it changes.
+    pub fn select(&mut self, mailbox: &str) -> AnyResult<Option<u32>> {
+        let responses = self.command(&format!("SELECT {}", quote(mailbox)))?;
+
+        Ok(responses.iter().find_map(|response| {
+            let uid_validity = response.line.strip_prefix("* OK [UIDVALIDITY ")?;
+
+            uid_validity[..uid_validity.find(']')?].parse().ok()
+        }))
+    }
+
+    pub fn search_unseen(&mut self) -> AnyResult<Vec<u32>> {
+        let mut uids = Vec::new();
+
+        for response in self.command("UID SEARCH UNSEEN")? {
+            if let Some(search_results) = response.line.strip_prefix("* SEARCH") {
+                uids.extend(
+                    search_results.split_whitespace().filter_map(|uid| uid.parse::<u32>().ok()),
+                );
+            }
+        }
+
+        Ok(uids)
+    }
+
+    /// Fetches the whole message without setting its `\Seen` flag.
+    pub fn fetch(&mut self, uid: u32) -> AnyResult<Option<Vec<u8>>> {
+        let responses = self.command(&format!("UID FETCH {} BODY.PEEK[]", uid))?;
+
+        Ok(responses
+            .into_iter()
+            .find(|response| response.line.contains(" FETCH "))
+            .and_then(|response| response.literals.into_iter().next()))
+    }
+
+    pub fn mark_seen(&mut self, uid: u32) -> AnyResult<()> {
+        self.command(&format!("UID STORE {} +FLAGS.SILENT (\\Seen)", uid)).map(|_| ())
+    }
+
+    pub fn logout(&mut self) -> AnyResult<()> {
+        self.command("LOGOUT").map(|_| ())
+    }
+
+    /// Returns the untagged responses once the command completes with `OK`.
+    fn command(&mut self, command: &str) -> AnyResult<Vec<ImapResponse>> {
+        self.tag_counter += 1;
+        let tag = format!("A{} ", self.tag_counter);
+        let stream = self.reader.get_mut();
+
+        stream.write_all(format!("{}{}\r\n", tag, command).as_bytes())?;
+        stream.flush()?;
+
+        let mut responses = Vec::new();
+
+        loop {
+            let response = self.read_response()?;
+
+            if let Some(status) = response.line.strip_prefix(&tag) {
+                if status.starts_with("OK") {
+                    return Ok(responses);
+                }
+
+                let command_name = command.split_whitespace().next().unwrap_or_default();
+                return Err(anyerror!("IMAP {} failed: {}", command_name, status));
+            }
+
+            responses.push(response);
+        }
+    }
+
+    fn read_response(&mut self) -> AnyResult<ImapResponse> {
+        let mut response = ImapResponse { line: String::new(), literals: Vec::new() };
+
+        loop {
+            let line = self.read_line()?;
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+
+            response.line.push_str(line);
+
+            match literal_size(line) {
+                None => return Ok(response),
+                Some(size) => {
+                    let mut literal = vec![0; size];
+                    self.reader.read_exact(&mut literal)?;
+                    response.literals.push(literal);
+                }
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> AnyResult<String> {
+        let mut line = Vec::new();
+
+        if self.reader.read_until(b'\n', &mut line)? == 0 {
+            return Err(anyerror!("IMAP connection closed!"));
+        }
+
+        Ok(String::from_utf8_lossy(&line).into())
+    }
+}
+
+/// Size of the literal announced at the end of `line`, like `* 1 FETCH (BODY[] {1024}`.
+fn literal_size(line: &str) -> Option<usize> {
+    let line = line.strip_suffix('}')?;
+
+    line[line.rfind('{')? + 1..].parse().ok()
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::{spawn, JoinHandle};
+
+    const RAW_EMAIL: &str = "From: admin@example.com\r\nSubject: Test\r\n\r\nHello!!\r\n";
+
+    /// Answers the commands of one session with a canned mailbox of a single message.
+    fn spawn_imap_stub() -> (TcpStream, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket_addr = listener.local_addr().unwrap();
+        let server = spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut commands = Vec::new();
+
+            writer.write_all(b"* OK IMAP4rev1 ready\r\n").unwrap();
+
+            loop {
+                let mut line = String::new();
+
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    return commands;
+                }
+
+                let mut parts = line.trim_end().splitn(2, ' ');
+                let tag = parts.next().unwrap().to_string();
+                let command = parts.next().unwrap_or_default().to_string();
+                let response = match command.as_str() {
+                    "LOGIN \"bounce\" \"wrong\"" => format!("{} NO Invalid credentials\r\n", tag),
+                    "SELECT \"INBOX\"" => format!(
+                        "* OK [UIDVALIDITY 3857529045] UIDs valid\r\n{} OK SELECT done\r\n",
+                        tag
+                    ),
+                    "UID SEARCH UNSEEN" => format!("* SEARCH 7 9\r\n{} OK SEARCH done\r\n", tag),
+                    "UID FETCH 7 BODY.PEEK[]" => format!(
+                        "* 1 FETCH (UID 7 BODY[] {{{}}}\r\n{})\r\n{} OK FETCH done\r\n",
+                        RAW_EMAIL.len(),
+                        RAW_EMAIL,
+                        tag
+                    ),
+                    "LOGOUT" => format!("* BYE Logging out\r\n{} OK LOGOUT done\r\n", tag),
+                    _ => format!("{} OK done\r\n", tag),
+                };
+
+                commands.push(command);
+                writer.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (TcpStream::connect(socket_addr).unwrap(), server)
+    }
+
+    #[test]
+    fn test_poll_mailbox() {
+        let (stream, server) = spawn_imap_stub();
+        let mut imap_client = ImapClient::new(stream).unwrap();
+
+        imap_client.login("bounce", "pa\"ss").unwrap();
+        assert_eq!(imap_client.select("INBOX").unwrap(), Some(3857529045));
+        assert_eq!(imap_client.search_unseen().unwrap(), vec![7, 9]);
+        assert_eq!(imap_client.fetch(7).unwrap(), Some(RAW_EMAIL.as_bytes().to_vec()));
+        imap_client.mark_seen(7).unwrap();
+        imap_client.logout().unwrap();
+        drop(imap_client);
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "LOGIN \"bounce\" \"pa\\\"ss\"",
+                "SELECT \"INBOX\"",
+                "UID SEARCH UNSEEN",
+                "UID FETCH 7 BODY.PEEK[]",
+                "UID STORE 7 +FLAGS.SILENT (\\Seen)",
+                "LOGOUT",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reject_failed_command() {
+        let (stream, _) = spawn_imap_stub();
+        let mut imap_client = ImapClient::new(stream).unwrap();
+
+        assert_eq!(
+            imap_client.login("bounce", "wrong").unwrap_err().to_string(),
+            "IMAP LOGIN failed: NO Invalid credentials"
+        );
+    }
+}
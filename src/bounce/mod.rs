@@ -0,0 +1,159 @@
+mod dsn;
+mod imap_client;
+
+use crate::config::BounceConfig;
+use crate::email_address::EmailAddress;
+use crate::messages::{MessageBounce, MessageBounceType, SuppressionReason};
+use crate::suppression_list::{is_recipient_rejection, SuppressionList};
+use crate::{debug, info, warn, AnyResult};
+use dsn::DeliveryReport;
+use imap_client::ImapClient;
+use openssl::ssl::{SslConnector, SslMethod};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const IMAP_TIMEOUT: Duration = Duration::from_secs(30);
+
+trait ImapStream: Read + Write {}
+
+impl<T: Read + Write> ImapStream for T {}
+
+/// Polls the bounce mailbox for delivery status notifications, turning every failed or delayed
+/// recipient into a `MessageBounce`. Permanent mailbox failures are also suppressed.
+pub struct BounceProcessor {
+    bounce_config: BounceConfig,
+    suppression_list: SuppressionList,
+    service_instance_name: String,
+    uid_validity: Option<u32>,
+    last_seen_uid: u32,
+}
+
+impl BounceProcessor {
+    pub fn new(
+        bounce_config: BounceConfig,
+        suppression_list: SuppressionList,
+        service_instance_name: &str,
+    ) -> Self {
+        Self {
+            bounce_config,
+            suppression_list,
+            service_instance_name: service_instance_name.into(),
+            uid_validity: None,
+            last_seen_uid: 0,
+        }
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        self.bounce_config.poll_interval
+    }
+
+    /// Reports are marked as seen once `publish` succeeds. Other messages are left unseen for
+    /// humans and only skipped until the next restart.
+    pub fn poll<F>(&mut self, mut publish: F) -> AnyResult<()>
+    where
+        F: FnMut(&MessageBounce) -> AnyResult<()>,
+    {
+        let mut imap_client = self.connect()?;
+
+        imap_client
+            .login(&self.bounce_config.imap_user, self.bounce_config.imap_pass.get().unsecure())?;
+
+        let uid_validity = imap_client.select(&self.bounce_config.imap_mailbox)?;
+
+        // A recreated mailbox numbers its messages from scratch
+        if uid_validity != self.uid_validity {
+            self.uid_validity = uid_validity;
+            self.last_seen_uid = 0;
+        }
+
+        for uid in imap_client.search_unseen()? {
+            if uid <= self.last_seen_uid {
+                continue;
+            }
+
+            let delivery_report = match imap_client.fetch(uid)? {
+                None => None,
+                Some(raw_email) => DeliveryReport::parse(&raw_email),
+            };
+
+            if let Some(delivery_report) = delivery_report {
+                for message_bounce in self.create_bounces(&delivery_report) {
+                    publish(&message_bounce)?;
+                }
+
+                imap_client.mark_seen(uid)?;
+            } else {
+                debug!("Skipped message {} of the bounce mailbox, not a delivery report", uid);
+            }
+
+            self.last_seen_uid = uid;
+        }
+
+        imap_client.logout()
+    }
+
+    fn connect(&self) -> AnyResult<ImapClient<Box<dyn ImapStream>>> {
+        let imap_host = self.bounce_config.imap_host.as_str();
+        let stream = TcpStream::connect((imap_host, self.bounce_config.imap_port))?;
+
+        stream.set_read_timeout(Some(IMAP_TIMEOUT))?;
+        stream.set_write_timeout(Some(IMAP_TIMEOUT))?;
+
+        let stream: Box<dyn ImapStream> = if self.bounce_config.imap_use_tls {
+            let connector = SslConnector::builder(SslMethod::tls())?.build();
+
+            Box::new(connector.connect(imap_host, stream)?)
+        } else {
+            Box::new(stream)
+        };
+
+        ImapClient::new(stream)
+    }
+
+    fn create_bounces(&self, delivery_report: &DeliveryReport) -> Vec<MessageBounce> {
+        let draft_id = delivery_report.draft_id();
+        let mut message_bounces = Vec::new();
+
+        for recipient in delivery_report.recipients.iter() {
+            let bounce_type = match recipient.action.as_str() {
+                "failed" if recipient.status.starts_with('5') => MessageBounceType::Permanent,
+                "failed" | "delayed" => MessageBounceType::Transient,
+                _ => continue,
+            };
+
+            if bounce_type == MessageBounceType::Permanent
+                && is_recipient_rejection("", &recipient.status)
+            {
+                self.suppress(&recipient.final_recipient, recipient.diagnostic_code.clone());
+            }
+
+            message_bounces.push(MessageBounce::new(
+                &self.service_instance_name,
+                draft_id,
+                recipient.final_recipient.clone(),
+                bounce_type,
+                recipient.status.clone(),
+                recipient.diagnostic_code.clone(),
+            ));
+        }
+
+        message_bounces
+    }
+
+    fn suppress(&self, final_recipient: &str, diagnostic_code: Option<String>) {
+        match EmailAddress::parse(final_recipient) {
+            Err(reason) => warn!("Cannot suppress bounced {}: {}", final_recipient, reason),
+            Ok(email_address) => {
+                match self.suppression_list.add(
+                    &email_address,
+                    SuppressionReason::HardBounce,
+                    diagnostic_code,
+                ) {
+                    Err(e) => warn!("Cannot suppress bounced {}: {}", email_address, e),
+                    Ok(_) => info!("Suppressed bounced {}", email_address),
+                }
+            }
+        }
+    }
+}
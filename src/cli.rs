@@ -0,0 +1,213 @@
+use crate::config::{ConfigSource, DraftLimits, MailerConfig, SmtpConfig};
+use crate::mailer::{test_smtp_relay, DraftValidator, EmailSendingResult, Mailer};
+use crate::messages::MessageDraft;
+use crate::metrics::Metrics;
+use crate::suppression_list::SuppressionList;
+use crate::telemetry::Tracer;
+use crate::tracking::EngagementTracker;
+use crate::{anyerror, AnyResult};
+use std::fs::read;
+use std::iter::once;
+use tapa_trait_serde::IJsonSerializable;
+
+pub const USAGE: &str = "\
+Usage: tapa-micro-mailer [COMMAND]
+
+Commands:
+    run                         Consume drafts from NATS and send them (default)
+    send <DRAFT_FILE>           Send a single draft, bypassing NATS
+    validate <DRAFT_FILE>...    Check drafts against the rules of sending
+    check-config                Load and validate the config without connecting
+    smtp-test                   Connect and authenticate to the SMTP relays
+    help                        Print this message";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Send(String),
+    Validate(Vec<String>),
+    CheckConfig,
+    SmtpTest,
+    Help,
+}
+
+impl Command {
+    /// Parses the arguments after the binary name, running the consumers without any.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> AnyResult<Self> {
+        let command = match args.next() {
+            None => return Ok(Self::Run),
+            Some(command) => command,
+        };
+        let mut command_args: Vec<String> = args.collect();
+
+        match (command.as_str(), command_args.len()) {
+            ("run", 0) => Ok(Self::Run),
+            ("send", 1) => Ok(Self::Send(command_args.remove(0))),
+            ("validate", args_count) if args_count > 0 => Ok(Self::Validate(command_args)),
+            ("check-config", 0) => Ok(Self::CheckConfig),
+            ("smtp-test", 0) => Ok(Self::SmtpTest),
+            ("help", _) | ("--help", _) | ("-h", _) => Ok(Self::Help),
+            _ => Err(anyerror!(
+                "Invalid command: {} {}\n\n{}",
+                command,
+                command_args.join(" "),
+                USAGE
+            )),
+        }
+    }
+}
+
+/// Prints the loaded config, which is only checked, nothing is connected to.
+pub fn check_config(config_source: &ConfigSource) -> AnyResult<()> {
+    let config = MailerConfig::load(config_source)?;
+
+    println!("{:#?}", config);
+    println!("Config is valid");
+
+    Ok(())
+}
+
+/// The message size is only known after composing, so it is checked by `send` alone.
+pub fn validate_drafts(config_source: &ConfigSource, draft_paths: &[String]) -> AnyResult<()> {
+    let draft_limits = DraftLimits::load(config_source);
+
+    config_source.check()?;
+
+    let draft_validator = DraftValidator::new(&draft_limits);
+    let mut invalid_count = 0;
+
+    for draft_path in draft_paths {
+        let validation_result = read_draft_file(draft_path)
+            .map_err(|e| e.to_string())
+            .and_then(|draft| draft_validator.validate(&draft));
+
+        match validation_result {
+            Err(reason) => {
+                invalid_count += 1;
+                println!("INVALID {}: {}", draft_path, reason);
+            }
+            Ok(_) => println!("VALID {}", draft_path),
+        }
+    }
+
+    if invalid_count > 0 {
+        return Err(anyerror!("{} of {} drafts are invalid!", invalid_count, draft_paths.len()));
+    }
+
+    Ok(())
+}
+
+/// Prints the `MessageSent` or `MessageFail` instead of publishing it. Suppressions and quotas
+/// apply as for consumed drafts, though the quotas are not shared with running instances.
+pub async fn send_draft(config_source: &ConfigSource, draft_path: &str) -> AnyResult<()> {
+    let config = MailerConfig::load(config_source)?;
+    let draft = read_draft_file(draft_path)?;
+    let engagement_tracker =
+        config.tracking_config.as_ref().map(EngagementTracker::new).transpose()?;
+    let (tracer, _) = Tracer::new(None);
+    let mut mailer = Mailer::new(
+        &config,
+        SuppressionList::open(&config.suppression_config)?,
+        engagement_tracker,
+        Metrics::new()?,
+        tracer,
+    )
+    .await?;
+
+    match mailer.compose_and_send(None, &config.instance_name, draft).await {
+        EmailSendingResult::Fail(message_fail) => {
+            println!("{}", message_fail.to_json_string_pretty());
+            Err(anyerror!("Draft {} failed!", draft_path))
+        }
+        EmailSendingResult::Sent(message_sent) => {
+            println!("{}", message_sent.to_json_string_pretty());
+            Ok(())
+        }
+    }
+}
+
+pub async fn smtp_test(config_source: &ConfigSource) -> AnyResult<()> {
+    let smtp_config = SmtpConfig::load(config_source, true);
+
+    config_source.check()?;
+
+    // The routed relays are tested as well, as drafts to their domains go through them
+    for relay_config in once(smtp_config.default_relay()).chain(smtp_config.relays) {
+        let server_info = test_smtp_relay(&relay_config).await?;
+
+        println!(
+            "Authenticated to {} relay {} as {}",
+            relay_config.name, relay_config.host, relay_config.user
+        );
+        println!("Server: {}", server_info);
+    }
+
+    Ok(())
+}
+
+fn read_draft_file(draft_path: &str) -> AnyResult<MessageDraft> {
+    let draft_bytes =
+        read(draft_path).map_err(|e| anyerror!("Cannot read draft {}: {}", draft_path, e))?;
+
+    MessageDraft::from_json_bytes(&draft_bytes)
+        .map_err(|e| anyerror!("Cannot parse draft {}: {}", draft_path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::env::temp_dir;
+    use std::fs::{remove_file, write};
+
+    fn to_args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Command::parse(to_args(&[])).unwrap(), Command::Run);
+        assert_eq!(
+            Command::parse(to_args(&["send", "draft.json"])).unwrap(),
+            Command::Send("draft.json".into())
+        );
+        assert_eq!(
+            Command::parse(to_args(&["validate", "a.json", "b.json"])).unwrap(),
+            Command::Validate(vec!["a.json".into(), "b.json".into()])
+        );
+        assert_eq!(Command::parse(to_args(&["check-config"])).unwrap(), Command::CheckConfig);
+        assert!(Command::parse(to_args(&["send"])).is_err());
+        assert!(Command::parse(to_args(&["validate"])).is_err());
+        assert!(Command::parse(to_args(&["smtp-test", "extra"])).is_err());
+        assert!(Command::parse(to_args(&["unknown"])).is_err());
+    }
+
+    #[test]
+    fn test_validate_draft_files() {
+        let valid_path = temp_dir().join(format!("draft-{}.json", uuid::Uuid::new_v4()));
+        let invalid_path = temp_dir().join(format!("draft-{}.json", uuid::Uuid::new_v4()));
+        let create_draft_json = |email_to: &str| {
+            format!(
+                "{{\"id\":\"320b0555-4c73-4abf-aaf0-461b84860046\",\"email_to\":\"{}\",\
+                \"email_to_name\":null,\"email_from\":\"noreply@example.com\",\
+                \"email_from_name\":null,\"subject\":\"Test\",\"body_type\":\"ASCII\",\
+                \"body\":\"Hello!!\",\"smime_recipient_cert\":null,\
+                \"timestamp\":\"2021-02-22T10:45:22.427738+00:00\"}}",
+                email_to
+            )
+        };
+        let config_source = ConfigSource::from_file_values(HashMap::new());
+        let valid_path_string = valid_path.to_str().unwrap().to_string();
+        let invalid_path_string = invalid_path.to_str().unwrap().to_string();
+
+        write(&valid_path, create_draft_json("admin@example.com")).unwrap();
+        write(&invalid_path, create_draft_json("admin")).unwrap();
+
+        assert!(validate_drafts(&config_source, &[valid_path_string.clone()]).is_ok());
+        assert!(validate_drafts(&config_source, &[valid_path_string, invalid_path_string]).is_err());
+        assert!(validate_drafts(&config_source, &["missing.json".into()]).is_err());
+
+        remove_file(valid_path).unwrap();
+        remove_file(invalid_path).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{anyerror, warn, AnyResult};
+use secstr::SecUtf8;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::fs::read_to_string;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const FILE_RECHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Credential set directly or mounted as a file, e.g. a Docker or Kubernetes secret. Files are
+/// re-read when used, at most every 10 seconds, so rotated secrets apply without a restart.
+pub struct Secret {
+    file_path: Option<String>,
+    state: Mutex<SecretState>,
+}
+
+struct SecretState {
+    value: SecUtf8,
+    checked_at: Instant,
+}
+
+impl Secret {
+    pub fn new(value: SecUtf8) -> Self {
+        Self {
+            file_path: None,
+            state: Mutex::new(SecretState { value, checked_at: Instant::now() }),
+        }
+    }
+
+    pub fn from_file(file_path: &str) -> AnyResult<Self> {
+        Ok(Self {
+            file_path: Some(file_path.into()),
+            state: Mutex::new(SecretState {
+                value: read_secret_file(file_path)?,
+                checked_at: Instant::now(),
+            }),
+        })
+    }
+
+    /// A secret file that cannot be read anymore keeps its last value.
+    pub fn get(&self) -> SecUtf8 {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(file_path) = self.file_path.as_ref() {
+            if state.checked_at.elapsed() >= FILE_RECHECK_INTERVAL {
+                match read_secret_file(file_path) {
+                    Err(e) => warn!("Keeping the last secret, {}", e),
+                    Ok(value) => state.value = value,
+                }
+
+                state.checked_at = Instant::now();
+            }
+        }
+
+        state.value.clone()
+    }
+}
+
+impl Default for Secret {
+    fn default() -> Self {
+        Self::new(SecUtf8::from(""))
+    }
+}
+
+impl Clone for Secret {
+    fn clone(&self) -> Self {
+        let state = self.state.lock().unwrap();
+
+        Self {
+            file_path: self.file_path.clone(),
+            state: Mutex::new(SecretState {
+                value: state.value.clone(),
+                checked_at: state.checked_at,
+            }),
+        }
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self.file_path.as_ref() {
+            None => f.write_str("***SECRET***"),
+            Some(file_path) => write!(f, "***SECRET*** from {}", file_path),
+        }
+    }
+}
+
+/// Editors and `echo` leave a trailing newline, which is never part of the secret.
+fn read_secret_file(file_path: &str) -> AnyResult<SecUtf8> {
+    match read_to_string(file_path) {
+        Err(e) => Err(anyerror!("Cannot read secret file {}: {}", file_path, e)),
+        Ok(content) => Ok(SecUtf8::from(content.trim_end_matches(|c| c == '\n' || c == '\r'))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{remove_file, write};
+
+    #[test]
+    fn test_reread_rotated_file() {
+        let secret_path = temp_dir().join(format!("secret-{}", uuid::Uuid::new_v4()));
+        let secret_path_str = secret_path.to_str().unwrap();
+
+        write(&secret_path, "old-password\n").unwrap();
+
+        let secret = Secret::from_file(secret_path_str).unwrap();
+
+        assert_eq!(secret.get().unsecure(), "old-password");
+        write(&secret_path, "new-password\r\n").unwrap();
+        assert_eq!(secret.get().unsecure(), "old-password");
+
+        secret.state.lock().unwrap().checked_at -= FILE_RECHECK_INTERVAL;
+        assert_eq!(secret.get().unsecure(), "new-password");
+
+        remove_file(&secret_path).unwrap();
+        secret.state.lock().unwrap().checked_at -= FILE_RECHECK_INTERVAL;
+        assert_eq!(secret.get().unsecure(), "new-password");
+        assert_eq!(format!("{:?}", secret), format!("***SECRET*** from {}", secret_path_str));
+    }
+}
@@ -0,0 +1,395 @@
+use super::Secret;
+use crate::{anyerror, AnyResult};
+use secstr::SecUtf8;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env::var;
+use std::fmt::Display;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::str::FromStr;
+
+const CONFIG_FILE_VAR: &str = "MAILER_CONFIG_FILE";
+
+/// Every setting is read by its env var name, env vars override the optional config file.
+///
+/// Invalid and missing settings are reported instead of failing at the first one, the loaders
+/// fall back to defaults and `finish` fails with every reported error at once.
+pub struct ConfigSource {
+    file_values: HashMap<String, String>,
+    /// Read instead of the process env vars when set, so tests do not share them.
+    env_values: Option<HashMap<String, String>>,
+    read_keys: RefCell<HashSet<String>>,
+    disabled_prefixes: RefCell<Vec<String>>,
+    errors: RefCell<Vec<String>>,
+}
+
+impl ConfigSource {
+    /// Layers env vars over `MAILER_CONFIG_FILE` when it is set.
+    pub fn load() -> AnyResult<Self> {
+        match var(CONFIG_FILE_VAR) {
+            Ok(config_file) if !config_file.is_empty() => Self::load_file(&config_file),
+            _ => Ok(Self::from_file_values(HashMap::new())),
+        }
+    }
+
+    /// TOML or YAML by extension. Sections are joined to their keys, so `max_per_day` under
+    /// `[smtp]` is `SMTP_MAX_PER_DAY`, and lists are joined with commas. Lists of tables are
+    /// numbered from 0, so `host` of the first `[[smtp.relays]]` is `SMTP_RELAYS_0_HOST`.
+    pub fn load_file(config_file: &str) -> AnyResult<Self> {
+        let content = match read_to_string(config_file) {
+            Err(e) => return Err(anyerror!("Cannot read config file {}: {}", config_file, e)),
+            Ok(content) => content,
+        };
+        let extension = Path::new(config_file).extension().and_then(|e| e.to_str());
+        let parsed_content: Value = match extension {
+            Some("toml") => toml::from_str(&content).map_err(|e| e.to_string()),
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content).map_err(|e| e.to_string()),
+            _ => return Err(anyerror!("Config file {} is neither TOML nor YAML!", config_file)),
+        }
+        .map_err(|e| anyerror!("Cannot parse config file {}: {}", config_file, e))?;
+        let mut file_values = HashMap::new();
+        let mut errors = Vec::new();
+
+        flatten_value("", &parsed_content, &mut file_values, &mut errors);
+
+        if !errors.is_empty() {
+            return Err(create_error(config_file, &errors));
+        }
+
+        Ok(Self::from_file_values(file_values))
+    }
+
+    pub fn from_file_values(file_values: HashMap<String, String>) -> Self {
+        Self {
+            file_values,
+            env_values: None,
+            read_keys: RefCell::default(),
+            disabled_prefixes: RefCell::default(),
+            errors: RefCell::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn from_pairs(pairs: &[(&str, &str)]) -> Self {
+        Self::from_file_values(
+            pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+        )
+    }
+
+    #[cfg(test)]
+    pub fn with_env(mut self, pairs: &[(&str, &str)]) -> Self {
+        self.env_values =
+            Some(pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect());
+
+        self
+    }
+
+    /// Empty values are treated as unset, so blank env vars do not hide the config file.
+    pub fn var(&self, key: &str) -> Option<String> {
+        self.read_keys.borrow_mut().insert(key.into());
+
+        let env_value = match self.env_values.as_ref() {
+            None => var(key).ok(),
+            Some(env_values) => env_values.get(key).cloned(),
+        };
+
+        match env_value {
+            Some(value) if !value.is_empty() => Some(value),
+            _ => self.file_values.get(key).filter(|value| !value.is_empty()).cloned(),
+        }
+    }
+
+    /// Reports `key` when it is not set.
+    pub fn require(&self, key: &str) -> Option<String> {
+        let value = self.var(key);
+
+        if value.is_none() {
+            self.report(format!("{} not set!", key));
+        }
+
+        value
+    }
+
+    /// `key` itself, or the content of the file in `key_FILE`.
+    pub fn secret(&self, key: &str) -> Option<Secret> {
+        let file_key = format!("{}_FILE", key);
+
+        match (self.var(key), self.var(&file_key)) {
+            (Some(_), Some(_)) => {
+                self.report(format!("{} and {} are both set!", key, file_key));
+                None
+            }
+            (Some(value), None) => Some(Secret::new(SecUtf8::from(value))),
+            (None, Some(file_path)) => match Secret::from_file(&file_path) {
+                Err(e) => {
+                    self.invalid(&file_key, e);
+                    None
+                }
+                Ok(secret) => Some(secret),
+            },
+            (None, None) => None,
+        }
+    }
+
+    /// Reports `key` when neither it nor `key_FILE` is set.
+    pub fn require_secret(&self, key: &str) -> Option<Secret> {
+        let file_key = format!("{}_FILE", key);
+
+        if self.var(key).is_none() && self.var(&file_key).is_none() {
+            self.report(format!("{} or {} not set!", key, file_key));
+        }
+
+        self.secret(key)
+    }
+
+    /// Reports `key` when it is set but cannot be parsed.
+    pub fn parse<T>(&self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.var(key)?.parse::<T>() {
+            Err(e) => {
+                self.invalid(key, e);
+                None
+            }
+            Ok(parsed_value) => Some(parsed_value),
+        }
+    }
+
+    /// Keeps the config file keys starting with `prefix` from being reported as unknown and
+    /// returns `None`, for loaders of a disabled feature.
+    pub fn disabled<T>(&self, prefix: &str) -> Option<T> {
+        self.disabled_prefixes.borrow_mut().push(prefix.into());
+
+        None
+    }
+
+    pub fn invalid(&self, key: &str, reason: impl Display) {
+        self.report(format!("{} is invalid: {}", key, reason));
+    }
+
+    pub fn report(&self, error: String) {
+        self.errors.borrow_mut().push(error);
+    }
+
+    /// Fails with every reported error, including the config file keys nothing has read
+    /// unless their feature is disabled, e.g. a typo.
+    pub fn finish(&self) -> AnyResult<()> {
+        let read_keys = self.read_keys.borrow();
+        let disabled_prefixes = self.disabled_prefixes.borrow();
+        let mut unknown_keys: Vec<_> = self
+            .file_values
+            .keys()
+            .filter(|key| !read_keys.contains(*key))
+            .filter(|key| !disabled_prefixes.iter().any(|prefix| key.starts_with(prefix)))
+            .collect();
+
+        unknown_keys.sort();
+
+        for unknown_key in unknown_keys {
+            self.report(format!("Config file key {} is unknown!", unknown_key));
+        }
+
+        self.check()
+    }
+
+    /// Fails with every reported error, for commands only loading a part of the config.
+    pub fn check(&self) -> AnyResult<()> {
+        let errors = self.errors.borrow();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(create_error("configuration", &errors))
+        }
+    }
+}
+
+fn flatten_value(
+    key: &str,
+    value: &Value,
+    file_values: &mut HashMap<String, String>,
+    errors: &mut Vec<String>,
+) {
+    match value {
+        Value::Null => {}
+        Value::Object(table) => {
+            for (table_key, table_value) in table {
+                let table_key = table_key.to_uppercase().replace('-', "_");
+                let flat_key =
+                    if key.is_empty() { table_key } else { format!("{}_{}", key, table_key) };
+
+                flatten_value(&flat_key, table_value, file_values, errors);
+            }
+        }
+        Value::Array(items) if items.iter().any(Value::is_object) => {
+            if !items.iter().all(Value::is_object) {
+                errors.push(format!("{} can only list tables, or strings and numbers!", key));
+            }
+
+            for (index, item) in items.iter().enumerate().filter(|(_, item)| item.is_object()) {
+                flatten_value(&format!("{}_{}", key, index), item, file_values, errors);
+            }
+        }
+        Value::Array(items) => {
+            let mut flat_items = Vec::with_capacity(items.len());
+
+            for item in items {
+                match to_flat_string(item) {
+                    None => errors
+                        .push(format!("{} can only list tables, or strings and numbers!", key)),
+                    Some(flat_item) => flat_items.push(flat_item),
+                }
+            }
+
+            file_values.insert(key.into(), flat_items.join(","));
+        }
+        _ if key.is_empty() => errors.push("The config file is not a table!".into()),
+        _ => {
+            file_values.insert(key.into(), to_flat_string(value).unwrap_or_default());
+        }
+    }
+}
+
+fn to_flat_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => Some(string.clone()),
+        Value::Bool(boolean) => Some(boolean.to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+fn create_error(source_name: &str, errors: &[String]) -> anyhow::Error {
+    let mut message = format!("Invalid {}:", source_name);
+
+    for error in errors {
+        message.push_str("\n  - ");
+        message.push_str(error);
+    }
+
+    anyerror!(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::write;
+
+    #[test]
+    fn test_load_toml_and_yaml_files() {
+        let toml_path = temp_dir().join(format!("mailer-{}.toml", uuid::Uuid::new_v4()));
+        let yaml_path = temp_dir().join(format!("mailer-{}.yaml", uuid::Uuid::new_v4()));
+
+        write(
+            &toml_path,
+            "[smtp]\nhost = \"smtp.example.com\"\nmax-per-day = 1000\n\
+             domain_limits = [\"gmail.com=2:60:1000\", \"outlook.com=::500\"]\n",
+        )
+        .unwrap();
+        write(&yaml_path, "smtp:\n  host: smtp.example.com\n  max_per_day: 1000\n").unwrap();
+
+        for config_path in [toml_path, yaml_path].iter() {
+            let config_source = ConfigSource::load_file(config_path.to_str().unwrap()).unwrap();
+
+            assert_eq!(config_source.var("SMTP_HOST").unwrap(), "smtp.example.com");
+            assert_eq!(config_source.parse::<usize>("SMTP_MAX_PER_DAY"), Some(1000));
+            std::fs::remove_file(config_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_report_every_bad_key() {
+        let config_source = ConfigSource::from_pairs(&[
+            ("CONFIG_TEST_MAX_PER_DAY", "abc"),
+            ("CONFIG_TEST_USE_TLS", "yes"),
+            ("CONFIG_TEST_TYPO", "1"),
+            ("CONFIG_TEST_OVERRIDDEN", "file"),
+        ])
+        .with_env(&[("CONFIG_TEST_OVERRIDDEN", "env"), ("CONFIG_TEST_USE_TLS", "")]);
+
+        assert_eq!(config_source.var("CONFIG_TEST_OVERRIDDEN").unwrap(), "env");
+        assert_eq!(config_source.parse::<usize>("CONFIG_TEST_MAX_PER_DAY"), None);
+        assert_eq!(config_source.parse::<bool>("CONFIG_TEST_USE_TLS"), None);
+        assert_eq!(config_source.require("CONFIG_TEST_HOST"), None);
+
+        let error = config_source.finish().unwrap_err().to_string();
+
+        assert!(error.contains("CONFIG_TEST_MAX_PER_DAY is invalid"));
+        assert!(error.contains("CONFIG_TEST_USE_TLS is invalid"));
+        assert!(error.contains("CONFIG_TEST_HOST not set!"));
+        assert!(error.contains("Config file key CONFIG_TEST_TYPO is unknown!"));
+        assert!(!error.contains("CONFIG_TEST_OVERRIDDEN"));
+    }
+
+    #[test]
+    fn test_keys_of_disabled_features_are_not_unknown() {
+        let create_source = || {
+            ConfigSource::from_pairs(&[
+                ("CONFIG_DISABLED_HOST", "imap.example.com"),
+                ("CONFIG_DISABLED_PORT", "993"),
+            ])
+        };
+        let disabled_source = create_source();
+
+        assert!(create_source().finish().is_err());
+        assert_eq!(disabled_source.disabled::<()>("CONFIG_DISABLED_"), None);
+        assert!(disabled_source.finish().is_ok());
+    }
+
+    #[test]
+    fn test_number_lists_of_tables() {
+        let toml_path = temp_dir().join(format!("mailer-{}.toml", uuid::Uuid::new_v4()));
+        let yaml_path = temp_dir().join(format!("mailer-{}.yaml", uuid::Uuid::new_v4()));
+
+        write(
+            &toml_path,
+            "[[smtp.relays]]\nname = \"bulk\"\nhost = \"smtp1.example.com\"\n\
+             [[smtp.relays]]\nname = \"transactional\"\nhost = \"smtp2.example.com\"\n\
+             [[smtp.routes]]\ndomains = [\"gmail.com\", \"outlook.com\"]\nrelay = \"bulk\"\n",
+        )
+        .unwrap();
+        write(
+            &yaml_path,
+            "smtp:\n  relays:\n    - name: bulk\n      host: smtp1.example.com\n\
+             \x20   - name: transactional\n      host: smtp2.example.com\n\
+             \x20 routes:\n    - domains: [gmail.com, outlook.com]\n      relay: bulk\n",
+        )
+        .unwrap();
+
+        for config_path in [toml_path, yaml_path].iter() {
+            let config_source = ConfigSource::load_file(config_path.to_str().unwrap()).unwrap();
+
+            assert_eq!(config_source.var("SMTP_RELAYS_0_HOST").unwrap(), "smtp1.example.com");
+            assert_eq!(config_source.var("SMTP_RELAYS_1_NAME").unwrap(), "transactional");
+            assert_eq!(
+                config_source.var("SMTP_ROUTES_0_DOMAINS").unwrap(),
+                "gmail.com,outlook.com"
+            );
+            assert_eq!(config_source.var("SMTP_ROUTES_0_RELAY").unwrap(), "bulk");
+            std::fs::remove_file(config_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reject_mixed_and_nested_lists() {
+        let toml_path = temp_dir().join(format!("mailer-{}.toml", uuid::Uuid::new_v4()));
+
+        write(
+            &toml_path,
+            "[smtp]\nrelays = [{ host = \"smtp1.example.com\" }, \"smtp2.example.com\"]\n\
+             domain_limits = [[\"gmail.com=2:60:1000\"]]\n",
+        )
+        .unwrap();
+
+        let error = ConfigSource::load_file(toml_path.to_str().unwrap()).err().unwrap().to_string();
+
+        std::fs::remove_file(&toml_path).unwrap();
+        assert!(error.contains("SMTP_RELAYS can only list tables, or strings and numbers!"));
+        assert!(error.contains("SMTP_DOMAIN_LIMITS can only list tables, or strings and numbers!"));
+    }
+}
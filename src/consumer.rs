@@ -0,0 +1,342 @@
+use crate::control::Control;
+use crate::health::Health;
+use crate::logging::redact_draft;
+use crate::mailer::{EmailSendingResult, Mailer};
+use crate::messages::{MessageDraft, MessageDraftPriority, MessageFail, MessageFailType};
+use crate::metrics::Metrics;
+use crate::telemetry::{SpanKind, TraceContext, Tracer};
+use crate::utils::get_email_domain;
+use crate::{anyerror, debug, error, warn, AnyResult};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tapa_cgloop_nats::ProcessResult;
+use tapa_trait_serde::IJsonSerializable;
+use tokio::runtime::Runtime;
+use tokio::sync::Mutex;
+
+const DEFERRAL_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Publishes raw draft bytes back to the topic they were consumed from.
+pub type DraftRequeuer = Arc<dyn Fn(&[u8]) -> AnyResult<()> + Send + Sync>;
+
+struct DeferredDomain {
+    ready_instant: Instant,
+    drafts: Vec<Vec<u8>>,
+}
+
+/// A topic drafts are consumed from. Drafts to a throttled domain are deferred per domain and
+/// published back to the topic once the domain may be sent to again, so the consumer keeps
+/// taking drafts to the other domains meanwhile.
+#[derive(Clone)]
+pub struct DraftLane {
+    priority: Option<MessageDraftPriority>,
+    requeuer: DraftRequeuer,
+    deferred_domains: Arc<std::sync::Mutex<HashMap<String, DeferredDomain>>>,
+}
+
+impl DraftLane {
+    /// Drafts consumed by a lane with `priority` are sent with that priority instead of their
+    /// own.
+    pub fn new(priority: Option<MessageDraftPriority>, requeuer: DraftRequeuer) -> Self {
+        Self { priority, requeuer, deferred_domains: Arc::default() }
+    }
+
+    /// Publishes the deferred drafts back once their domain may be sent to again, or right away
+    /// when the quotas are changed. When stopping, every deferred draft is handed back as soon
+    /// as the drafts in flight are done.
+    pub fn run_deferral(&self, control: &Control) {
+        let mut quota_changes = control.quota_changes();
+
+        loop {
+            let stopped = control.is_stopping() && control.in_flight() == 0;
+            let current_quota_changes = control.quota_changes();
+            let ready_before = if control.is_stopping() || current_quota_changes != quota_changes {
+                None
+            } else {
+                Some(Instant::now())
+            };
+
+            quota_changes = current_quota_changes;
+
+            for draft_bytes in self.take_deferred(ready_before.as_ref()) {
+                if let Err(e) = (self.requeuer)(&draft_bytes) {
+                    error!("Cannot publish a deferred draft back to NATS: {}", e);
+                }
+            }
+
+            if stopped {
+                return;
+            }
+
+            sleep(DEFERRAL_CHECK_INTERVAL);
+        }
+    }
+
+    fn defer(&self, domain: &str, draft_bytes: &[u8], duration_to_wait: Duration) {
+        let ready_instant = Instant::now() + duration_to_wait;
+        let mut deferred_domains = self.deferred_domains.lock().unwrap();
+        let deferred_domain = deferred_domains
+            .entry(domain.into())
+            .or_insert_with(|| DeferredDomain { ready_instant, drafts: Vec::new() });
+
+        // The latest throttle decides, e.g. after the domain limits were reloaded
+        deferred_domain.ready_instant = ready_instant;
+        deferred_domain.drafts.push(draft_bytes.to_vec());
+    }
+
+    /// Takes the drafts of the domains ready at `ready_before`, or of every domain when `None`.
+    fn take_deferred(&self, ready_before: Option<&Instant>) -> Vec<Vec<u8>> {
+        let mut deferred_domains = self.deferred_domains.lock().unwrap();
+        let ready_domains: Vec<String> = deferred_domains
+            .iter()
+            .filter(|(_, deferred_domain)| {
+                ready_before
+                    .map_or(true, |ready_before| deferred_domain.ready_instant <= *ready_before)
+            })
+            .map(|(domain, _)| domain.clone())
+            .collect();
+
+        ready_domains
+            .iter()
+            .filter_map(|domain| deferred_domains.remove(domain))
+            .flat_map(|deferred_domain| deferred_domain.drafts)
+            .collect()
+    }
+}
+
+pub struct DraftEmailConsumer {
+    mailer: Arc<Mutex<Mailer>>,
+    service_instance_name: String,
+    lane: DraftLane,
+    metrics: Metrics,
+    health: Health,
+    tracer: Tracer,
+    control: Control,
+    async_runtime: Runtime,
+}
+
+impl DraftEmailConsumer {
+    pub fn new(
+        mailer: Arc<Mutex<Mailer>>,
+        service_instance_name: &str,
+        lane: DraftLane,
+        metrics: Metrics,
+        health: Health,
+        tracer: Tracer,
+        control: Control,
+    ) -> AnyResult<Self> {
+        Ok(Self {
+            mailer,
+            lane,
+            metrics,
+            health,
+            tracer,
+            control,
+            service_instance_name: service_instance_name.into(),
+            async_runtime: Runtime::new()?,
+        })
+    }
+
+    /// Drafts held by a pause or a quota when stopping are published back instead of sent, so
+    /// another instance sends them. The error keeps any result from being published.
+    fn hand_back(&self, draft_bytes: &[u8]) -> AnyResult<ProcessResult> {
+        (self.lane.requeuer)(draft_bytes)?;
+
+        Err(anyerror!("Stopped while the draft was held, handed it back to NATS"))
+    }
+
+    /// Drafts handed back or deferred are consumed again later, so only the drafts with a
+    /// result count as consumed.
+    pub fn handle_draft(&mut self, draft_bytes: &[u8]) -> AnyResult<ProcessResult> {
+        let process_result = self.process_draft(draft_bytes)?;
+
+        match self.lane.priority {
+            None => self.metrics.record_consumed("default"),
+            Some(_) => self.metrics.record_consumed("priority"),
+        }
+
+        Ok(process_result)
+    }
+
+    fn process_draft(&mut self, draft_bytes: &[u8]) -> AnyResult<ProcessResult> {
+        let ten_seconds = Duration::from_secs(10);
+        let service_instance_name = &self.service_instance_name;
+        let _in_flight_draft = match self.control.take_draft() {
+            None => return self.hand_back(draft_bytes),
+            Some(in_flight_draft) => in_flight_draft,
+        };
+
+        if let Ok(mut message_draft) = MessageDraft::from_json_bytes(draft_bytes) {
+            debug!(
+                "Got new message draft: {}",
+                redact_draft(&message_draft).to_json_string_pretty()
+            );
+
+            if let Some(lane_priority) = self.lane.priority {
+                message_draft.priority = lane_priority;
+            }
+
+            let producer_context =
+                message_draft.trace_context.as_deref().and_then(TraceContext::parse);
+
+            loop {
+                if !self.control.wait_while_paused() {
+                    return self.hand_back(draft_bytes);
+                }
+
+                let retry_draft = message_draft.clone();
+                let mailer = &self.mailer;
+
+                match self.async_runtime.block_on(async move {
+                    mailer
+                        .lock()
+                        .await
+                        .compose_and_send(None, service_instance_name, retry_draft)
+                        .await
+                }) {
+                    EmailSendingResult::Fail(message_fail) => match &message_fail.fail_reason {
+                        MessageFailType::Unknown => {
+                            return Err(anyerror!("MessageFailType::Unknown should never occur!"));
+                        }
+                        MessageFailType::QuotaExhausted(duration_to_wait, error_string) => {
+                            warn!("{}", error_string);
+                            let _quota_wait_span = self.tracer.start_span(
+                                "quota_wait",
+                                SpanKind::Internal,
+                                producer_context.as_ref(),
+                            );
+                            let wait_start = Instant::now();
+
+                            self.health.block_on_quota(*duration_to_wait);
+
+                            // Pauses and quota changes cut the wait short, the draft is retried
+                            let waited = self.control.wait_for_quota(*duration_to_wait);
+
+                            self.metrics.record_quota_wait(wait_start.elapsed());
+
+                            if !waited {
+                                return self.hand_back(draft_bytes);
+                            }
+
+                            continue;
+                        }
+                        MessageFailType::DomainThrottled(duration_to_wait, error_string) => {
+                            let domain =
+                                get_email_domain(&message_draft.email_to).unwrap_or_default();
+
+                            warn!("{}, deferred the draft", error_string);
+                            self.lane.defer(&domain, draft_bytes, *duration_to_wait);
+
+                            return Err(anyerror!("Deferred the draft to throttled {}", domain));
+                        }
+                        MessageFailType::UndeliverableDomain(error_string)
+                        | MessageFailType::Suppressed(error_string) => {
+                            warn!("{}", error_string);
+                            let message_fail = Bytes::from(message_fail.to_json_bytes_pretty());
+                            return Ok(ProcessResult::Failure(message_fail));
+                        }
+                        MessageFailType::Other(reason) | MessageFailType::BadDraft(reason) => {
+                            error!("{}", reason);
+                            let message_fail = Bytes::from(message_fail.to_json_bytes_pretty());
+                            sleep(ten_seconds);
+                            return Ok(ProcessResult::Failure(message_fail));
+                        }
+                    },
+                    EmailSendingResult::Sent(message_success) => {
+                        let message_success = Bytes::from(message_success.to_json_bytes_pretty());
+
+                        return Ok(ProcessResult::Success(message_success));
+                    }
+                }
+            }
+        } else {
+            let error_message = format!(
+                "Cannot parse to correct JSON format, draft message length is {}",
+                draft_bytes.len()
+            );
+            error!("{}", error_message);
+            let message_fail = MessageFail::new(
+                None,
+                service_instance_name,
+                error_message.clone(),
+                MessageFailType::BadDraft(error_message),
+            );
+            let message_fail = Bytes::from(message_fail.to_json_bytes_pretty());
+
+            Ok(ProcessResult::Failure(message_fail))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailer::create_file_mailer;
+    use std::env::temp_dir;
+    use std::fs::remove_dir_all;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_throttled_domain_does_not_hold_other_domains() {
+        let test_dir = temp_dir().join(format!("consumer-{}", Uuid::new_v4()));
+        let mailer = Runtime::new()
+            .unwrap()
+            .block_on(create_file_mailer(&test_dir, &[("SMTP_DOMAIN_LIMITS", "gmail.com=::1")]));
+        let requeued_drafts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let requeuer_drafts = requeued_drafts.clone();
+        let lane = DraftLane::new(
+            None,
+            Arc::new(move |draft_bytes: &[u8]| {
+                requeuer_drafts.lock().unwrap().push(draft_bytes.to_vec());
+                Ok(())
+            }),
+        );
+        let shutdown_flag = Arc::new(AtomicBool::new(false));
+        let control = Control::new(shutdown_flag.clone());
+        let (tracer, _) = Tracer::new(None);
+        let metrics = Metrics::new().unwrap();
+        let mut consumer = DraftEmailConsumer::new(
+            Arc::new(Mutex::new(mailer)),
+            "MAILER-TEST",
+            lane.clone(),
+            metrics.clone(),
+            Health::new(),
+            tracer,
+            control.clone(),
+        )
+        .unwrap();
+        let create_draft_bytes = |email_to: &str| {
+            MessageDraft { email_to: email_to.into(), ..MessageDraft::default() }
+                .to_json_bytes_pretty()
+        };
+        let throttled_draft = create_draft_bytes("second@gmail.com");
+
+        assert!(matches!(
+            consumer.handle_draft(&create_draft_bytes("first@gmail.com")),
+            Ok(ProcessResult::Success(_))
+        ));
+        assert!(consumer.handle_draft(&throttled_draft).is_err());
+        assert!(matches!(
+            consumer.handle_draft(&create_draft_bytes("first@outlook.com")),
+            Ok(ProcessResult::Success(_))
+        ));
+        assert!(requeued_drafts.lock().unwrap().is_empty());
+
+        // The domain is throttled for an hour, so only stopping publishes the draft back
+        shutdown_flag.store(true, Ordering::Relaxed);
+        lane.run_deferral(&control);
+        drop(consumer);
+        remove_dir_all(&test_dir).unwrap();
+
+        assert_eq!(*requeued_drafts.lock().unwrap(), vec![throttled_draft]);
+
+        // The deferred draft only counts once it is consumed again
+        let exposition = String::from_utf8(metrics.encode().unwrap()).unwrap();
+
+        assert!(exposition.contains("mailer_drafts_consumed_total{lane=\"default\"} 2\n"));
+    }
+}
@@ -0,0 +1,215 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+struct ControlState {
+    paused: AtomicBool,
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    quota_changes: AtomicUsize,
+    shutdown_flag: Arc<AtomicBool>,
+}
+
+/// Pause and drain state of the consumers, changed from the control topic. Pausing holds every
+/// draft, draining only stops new drafts so the ones in flight can finish. Drafts still held
+/// when a stop signal comes are handed back to NATS instead of sent.
+#[derive(Clone)]
+pub struct Control {
+    state: Arc<ControlState>,
+}
+
+/// Counted as in flight until dropped.
+pub struct InFlightDraft {
+    state: Arc<ControlState>,
+}
+
+impl Control {
+    pub fn new(shutdown_flag: Arc<AtomicBool>) -> Self {
+        Self {
+            state: Arc::new(ControlState {
+                paused: AtomicBool::new(false),
+                draining: AtomicBool::new(false),
+                in_flight: AtomicUsize::new(0),
+                quota_changes: AtomicUsize::new(0),
+                shutdown_flag,
+            }),
+        }
+    }
+
+    pub fn pause(&self) {
+        self.state.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn drain(&self) {
+        self.state.draining.store(true, Ordering::Relaxed);
+    }
+
+    /// Lifts both a pause and a drain.
+    pub fn resume(&self) {
+        self.state.paused.store(false, Ordering::Relaxed);
+        self.state.draining.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.state.draining.load(Ordering::Relaxed)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.state.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.state.shutdown_flag.load(Ordering::Relaxed)
+    }
+
+    /// Wakes the drafts waiting for quota, so they are retried with the changed quotas.
+    pub fn notify_quota_changed(&self) {
+        self.state.quota_changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the quota changes, so deferred drafts can be retried once quotas change.
+    pub fn quota_changes(&self) -> usize {
+        self.state.quota_changes.load(Ordering::Relaxed)
+    }
+
+    /// Blocks a consumer while paused or draining, before its draft counts as in flight. Returns
+    /// `None` when stopped meanwhile.
+    pub fn take_draft(&self) -> Option<InFlightDraft> {
+        if !self.wait_while(|| self.is_paused() || self.is_draining()) {
+            return None;
+        }
+
+        self.state.in_flight.fetch_add(1, Ordering::Relaxed);
+
+        Some(InFlightDraft { state: self.state.clone() })
+    }
+
+    /// Blocks a draft in flight while paused, e.g. between quota retries. Returns false when
+    /// stopped meanwhile.
+    pub fn wait_while_paused(&self) -> bool {
+        self.wait_while(|| self.is_paused())
+    }
+
+    /// Sleeps `duration` until the quota refills, waking early when paused or when the quotas
+    /// are changed. Returns false when stopped meanwhile.
+    pub fn wait_for_quota(&self, duration: Duration) -> bool {
+        let quota_changes = self.state.quota_changes.load(Ordering::Relaxed);
+        let wait_end = Instant::now() + duration;
+
+        loop {
+            if self.is_stopping() {
+                return false;
+            }
+
+            let current_instant = Instant::now();
+
+            if current_instant >= wait_end
+                || self.is_paused()
+                || self.state.quota_changes.load(Ordering::Relaxed) != quota_changes
+            {
+                return true;
+            }
+
+            sleep(PAUSE_CHECK_INTERVAL.min(wait_end - current_instant));
+        }
+    }
+
+    fn wait_while(&self, condition: impl Fn() -> bool) -> bool {
+        while condition() {
+            if self.is_stopping() {
+                return false;
+            }
+
+            sleep(PAUSE_CHECK_INTERVAL);
+        }
+
+        true
+    }
+}
+
+impl Drop for InFlightDraft {
+    fn drop(&mut self) {
+        self.state.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::spawn;
+
+    #[test]
+    fn test_drain_lets_drafts_in_flight_finish() {
+        let shutdown_flag = Arc::new(AtomicBool::new(false));
+        let control = Control::new(shutdown_flag.clone());
+        let in_flight_draft = control.take_draft().unwrap();
+        let waiting_control = control.clone();
+
+        control.drain();
+        assert!(control.wait_while_paused());
+
+        let waiting_consumer = spawn(move || {
+            let _in_flight_draft = waiting_control.take_draft().unwrap();
+            waiting_control.in_flight()
+        });
+
+        sleep(PAUSE_CHECK_INTERVAL * 2);
+        assert_eq!(control.in_flight(), 1);
+        drop(in_flight_draft);
+        assert_eq!(control.in_flight(), 0);
+
+        control.resume();
+        assert_eq!(waiting_consumer.join().unwrap(), 1);
+        assert_eq!(control.in_flight(), 0);
+
+        control.pause();
+        shutdown_flag.store(true, Ordering::Relaxed);
+        assert!(control.take_draft().is_none());
+        assert!(!control.wait_while_paused());
+        assert_eq!(control.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_pause_and_quota_change_wake_quota_wait() {
+        let shutdown_flag = Arc::new(AtomicBool::new(false));
+        let control = Control::new(shutdown_flag.clone());
+        let one_minute = Duration::from_secs(60);
+        let spawn_quota_wait = |control: Control| {
+            spawn(move || {
+                let wait_start = Instant::now();
+                let waited = control.wait_for_quota(one_minute);
+                (waited, wait_start.elapsed())
+            })
+        };
+
+        let waiting_consumer = spawn_quota_wait(control.clone());
+
+        sleep(PAUSE_CHECK_INTERVAL);
+        control.pause();
+
+        let (waited, wait_duration) = waiting_consumer.join().unwrap();
+
+        assert!(waited);
+        assert!(wait_duration < one_minute);
+        control.resume();
+
+        let waiting_consumer = spawn_quota_wait(control.clone());
+
+        sleep(PAUSE_CHECK_INTERVAL);
+        control.notify_quota_changed();
+        assert!(waiting_consumer.join().unwrap().1 < one_minute);
+
+        let waiting_consumer = spawn_quota_wait(control);
+
+        sleep(PAUSE_CHECK_INTERVAL);
+        shutdown_flag.store(true, Ordering::Relaxed);
+        assert!(!waiting_consumer.join().unwrap().0);
+    }
+}
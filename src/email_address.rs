@@ -0,0 +1,241 @@
+use idna::domain_to_ascii;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::net::Ipv4Addr;
+
+const MAX_ADDRESS_LENGTH: usize = 254;
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+const MAX_DOMAIN_LENGTH: usize = 253;
+const MAX_LABEL_LENGTH: usize = 63;
+const ATEXT_SYMBOLS: &str = "!#$%&'*+-/=?^_`{|}~";
+
+/// Mailbox address (RFC 5321 4.1.2, RFC 5322 3.4.1), with internationalized domains kept in
+/// their ASCII (punycode) form. UTF-8 local parts (RFC 6531 3.3) are accepted and only sent
+/// through a relay advertising SMTPUTF8. Quoted local parts and IPv6 literals are rejected,
+/// since they cannot be sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmailAddress {
+    local_part: String,
+    domain: String,
+}
+
+impl EmailAddress {
+    /// Returns the reason the address cannot be used.
+    pub fn parse(address: &str) -> Result<Self, String> {
+        let at_index = match address.rfind('@') {
+            None => return Err("Missing @!".into()),
+            Some(at_index) => at_index,
+        };
+        let local_part = &address[..at_index];
+        let domain = normalize_domain(&address[at_index + 1..])?;
+
+        validate_local_part(local_part)?;
+
+        if local_part.len() + 1 + domain.len() > MAX_ADDRESS_LENGTH {
+            return Err(format!("Address is longer than {} octets!", MAX_ADDRESS_LENGTH));
+        }
+
+        Ok(Self { local_part: local_part.into(), domain })
+    }
+
+    pub fn local_part(&self) -> &str {
+        &self.local_part
+    }
+
+
+    /// Lowercased and in ASCII, IDN labels are converted to punycode.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+}
+
+impl Display for EmailAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}@{}", self.local_part, self.domain)
+    }
+}
+
+/// UTF-8 characters are atext too with SMTPUTF8 (RFC 6532 3.2), but controls and spaces would
+/// not survive most relays.
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric()
+        || ATEXT_SYMBOLS.contains(c)
+        || (!c.is_ascii() && !c.is_control() && !c.is_whitespace())
+}
+
+fn validate_local_part(local_part: &str) -> Result<(), String> {
+    if local_part.is_empty() {
+        return Err("Empty local part!".into());
+    }
+
+    if local_part.len() > MAX_LOCAL_PART_LENGTH {
+        return Err(format!("Local part is longer than {} octets!", MAX_LOCAL_PART_LENGTH));
+    }
+
+    if local_part.starts_with('"') {
+        return Err("Quoted local parts are not supported!".into());
+    }
+
+    if local_part.starts_with('.') || local_part.ends_with('.') || local_part.contains("..") {
+        return Err("Misplaced dot in local part!".into());
+    }
+
+    match local_part.chars().find(|c| *c != '.' && !is_atext(*c)) {
+        Some(c) => Err(format!("Invalid character {:?} in local part!", c)),
+        None => Ok(()),
+    }
+}
+
+fn normalize_domain(domain: &str) -> Result<String, String> {
+    if domain.is_empty() {
+        return Err("Empty domain!".into());
+    }
+
+    if domain.starts_with('[') {
+        return validate_domain_literal(domain);
+    }
+
+    let ascii_domain = match domain_to_ascii(domain) {
+        Err(_) => return Err(format!("Invalid internationalized domain {}!", domain)),
+        Ok(ascii_domain) => ascii_domain,
+    };
+
+    if ascii_domain.len() > MAX_DOMAIN_LENGTH {
+        return Err(format!("Domain is longer than {} octets!", MAX_DOMAIN_LENGTH));
+    }
+
+    let labels: Vec<&str> = ascii_domain.split('.').collect();
+
+    for label in labels.iter() {
+        if label.is_empty() {
+            return Err("Empty label in domain!".into());
+        }
+
+        if label.len() > MAX_LABEL_LENGTH {
+            return Err(format!("Domain label is longer than {} octets!", MAX_LABEL_LENGTH));
+        }
+
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err("Domain label starts or ends with a hyphen!".into());
+        }
+
+        if let Some(c) = label.chars().find(|c| !c.is_ascii_alphanumeric() && *c != '-') {
+            return Err(format!("Invalid character {:?} in domain!", c));
+        }
+    }
+
+    if labels.len() < 2 {
+        return Err("Domain is not fully qualified!".into());
+    }
+
+    if labels.last().map_or(false, |tld| tld.chars().all(|c| c.is_ascii_digit())) {
+        return Err("Numeric top-level domain, use an address literal for IPs!".into());
+    }
+
+    Ok(ascii_domain)
+}
+
+/// Only IPv4 address literals such as `[192.0.2.1]` (RFC 5321 4.1.3) are supported.
+fn validate_domain_literal(domain: &str) -> Result<String, String> {
+    let literal = match domain.strip_prefix('[').and_then(|domain| domain.strip_suffix(']')) {
+        None => return Err("Unterminated address literal!".into()),
+        Some(literal) => literal,
+    };
+
+    if literal.get(..5).map_or(false, |tag| tag.eq_ignore_ascii_case("IPv6:")) {
+        return Err("IPv6 address literals are not supported!".into());
+    }
+
+    if literal.parse::<Ipv4Addr>().is_err() {
+        return Err(format!("Invalid address literal {}!", domain));
+    }
+
+    Ok(domain.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_valid_addresses() {
+        let valid_addresses = [
+            ("admin@example.com", "admin", "example.com"),
+            ("Admin.User@Example.COM", "Admin.User", "example.com"),
+            ("first-last@example.com", "first-last", "example.com"),
+            ("user+tag@example.com", "user+tag", "example.com"),
+            ("o'brien@example.com", "o'brien", "example.com"),
+            ("!#$%&'*+-/=?^_`{|}~@example.com", "!#$%&'*+-/=?^_`{|}~", "example.com"),
+            ("admin@studio.photography", "admin", "studio.photography"),
+            ("curator@art.museum", "curator", "art.museum"),
+            ("admin@mail-1.sub.example.co.id", "admin", "mail-1.sub.example.co.id"),
+            ("admin@xn--bcher-kva.example", "admin", "xn--bcher-kva.example"),
+            ("admin@bücher.example", "admin", "xn--bcher-kva.example"),
+            ("admin@例え.テスト", "admin", "xn--r8jz45g.xn--zckzah"),
+            ("Admin@Bücher.Example", "Admin", "xn--bcher-kva.example"),
+            ("admin@[192.0.2.1]", "admin", "[192.0.2.1]"),
+            ("josé@example.com", "josé", "example.com"),
+            ("用户@例子.广告", "用户", "xn--fsqu00a.xn--4rr70v"),
+            (
+                "Δοκιμή.χρήστης@παράδειγμα.δοκιμή",
+                "Δοκιμή.χρήστης",
+                "xn--hxajbheg2az3al.xn--jxalpdlp",
+            ),
+        ];
+
+        for (address, local_part, domain) in valid_addresses.iter() {
+            let email_address = EmailAddress::parse(address)
+                .unwrap_or_else(|reason| panic!("{} rejected: {}", address, reason));
+
+            assert_eq!(email_address.local_part(), *local_part);
+            assert_eq!(email_address.domain(), *domain);
+        }
+    }
+
+    #[test]
+    fn test_reject_invalid_addresses() {
+        let long_label = "a".repeat(64);
+        let long_local_part = format!("{}@example.com", "a".repeat(65));
+        let long_address = format!(
+            "{}@{}.{}.{}.com",
+            "a".repeat(64),
+            "b".repeat(63),
+            "c".repeat(63),
+            "d".repeat(60)
+        );
+        let long_label_address = format!("admin@{}.com", long_label);
+        let invalid_addresses = [
+            ("", "Missing @!"),
+            ("admin.example.com", "Missing @!"),
+            ("@example.com", "Empty local part!"),
+            ("admin@", "Empty domain!"),
+            (".admin@example.com", "Misplaced dot in local part!"),
+            ("admin.@example.com", "Misplaced dot in local part!"),
+            ("ad..min@example.com", "Misplaced dot in local part!"),
+            ("ad min@example.com", "Invalid character ' ' in local part!"),
+            ("admin,root@example.com", "Invalid character ',' in local part!"),
+            ("admin@root@example.com", "Invalid character '@' in local part!"),
+            ("admin\u{0}@example.com", "Invalid character '\\u{0}' in local part!"),
+            ("\"john doe\"@example.com", "Quoted local parts are not supported!"),
+            ("jos\u{85}é@example.com", "Invalid character '\\u{85}' in local part!"),
+            ("josé\u{a0}@example.com", "Invalid character '\\u{a0}' in local part!"),
+            ("admin@[IPv6:2001:db8::1]", "IPv6 address literals are not supported!"),
+            ("admin@localhost", "Domain is not fully qualified!"),
+            ("admin@example..com", "Empty label in domain!"),
+            ("admin@example.com.", "Empty label in domain!"),
+            ("admin@-example.com", "Domain label starts or ends with a hyphen!"),
+            ("admin@example-.com", "Domain label starts or ends with a hyphen!"),
+            ("admin@exam_ple.com", "Invalid character '_' in domain!"),
+            ("admin@example.com>", "Invalid character '>' in domain!"),
+            ("admin@192.168.0.1", "Numeric top-level domain, use an address literal for IPs!"),
+            ("admin@[192.168.0.256]", "Invalid address literal [192.168.0.256]!"),
+            ("admin@[192.168.0.1", "Unterminated address literal!"),
+            (&long_local_part, "Local part is longer than 64 octets!"),
+            (&long_label_address, "Domain label is longer than 63 octets!"),
+            (&long_address, "Address is longer than 254 octets!"),
+        ];
+
+        for (address, reason) in invalid_addresses.iter() {
+            assert_eq!(EmailAddress::parse(address), Err(reason.to_string()), "{}", address);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+mod server;
+
+pub use server::HealthServer;
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tapa_trait_serde::IJsonSerializable;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, IJsonSerializable)]
+pub struct HealthReport {
+    pub nats_connected: bool,
+    pub smtp_reachable: bool,
+    pub quota_blocked: bool,
+}
+
+impl HealthReport {
+    pub fn is_ready(&self) -> bool {
+        self.nats_connected && self.smtp_reachable && !self.quota_blocked
+    }
+}
+
+struct HealthState {
+    nats_connected: AtomicBool,
+    smtp_reachable: AtomicBool,
+    quota_blocked_until: Mutex<Option<Instant>>,
+}
+
+/// Readiness signals shared by the consumers, the SMTP check and the health endpoint.
+/// NATS starts as connected, since the consumers exit when they cannot connect at start.
+#[derive(Clone)]
+pub struct Health {
+    state: Arc<HealthState>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(HealthState {
+                nats_connected: AtomicBool::new(true),
+                smtp_reachable: AtomicBool::new(false),
+                quota_blocked_until: Mutex::new(None),
+            }),
+        }
+    }
+
+    pub fn set_nats_connected(&self, nats_connected: bool) {
+        self.state.nats_connected.store(nats_connected, Ordering::Relaxed);
+    }
+
+    pub fn set_smtp_reachable(&self, smtp_reachable: bool) {
+        self.state.smtp_reachable.store(smtp_reachable, Ordering::Relaxed);
+    }
+
+    /// Marks the instance as not ready until `duration_to_wait` has passed.
+    pub fn block_on_quota(&self, duration_to_wait: Duration) {
+        let blocked_until = Instant::now() + duration_to_wait;
+        let mut quota_blocked_until = self.state.quota_blocked_until.lock().unwrap();
+
+        if quota_blocked_until.map_or(true, |current| current < blocked_until) {
+            *quota_blocked_until = Some(blocked_until);
+        }
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let quota_blocked_until = *self.state.quota_blocked_until.lock().unwrap();
+
+        HealthReport {
+            nats_connected: self.state.nats_connected.load(Ordering::Relaxed),
+            smtp_reachable: self.state.smtp_reachable.load(Ordering::Relaxed),
+            quota_blocked: quota_blocked_until.map_or(false, |until| Instant::now() < until),
+        }
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_readiness() {
+        let health = Health::new();
+
+        assert!(!health.report().is_ready());
+        health.set_smtp_reachable(true);
+        assert!(health.report().is_ready());
+
+        health.block_on_quota(Duration::from_secs(60));
+        health.block_on_quota(Duration::from_secs(0));
+        assert!(health.report().quota_blocked);
+
+        let expired_health = Health::new();
+        expired_health.set_smtp_reachable(true);
+        expired_health.block_on_quota(Duration::from_secs(0));
+        expired_health.set_nats_connected(false);
+
+        assert_eq!(
+            expired_health.report(),
+            HealthReport { nats_connected: false, smtp_reachable: true, quota_blocked: false }
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use super::Health;
+use crate::http::{create_empty_response, serve};
+use crate::metrics::MetricsServer;
+use crate::AnyResult;
+use futures::future::ready;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tapa_trait_serde::IJsonSerializable;
+
+const LIVENESS_PATH: &str = "/healthz";
+const READINESS_PATH: &str = "/readyz";
+
+/// Answers liveness on `GET /healthz` and readiness on `GET /readyz`, with a `HealthReport`.
+/// Every other request goes to `metrics_server` when it shares the listener.
+pub struct HealthServer {
+    health: Health,
+    metrics_server: Option<MetricsServer>,
+}
+
+impl HealthServer {
+    pub fn new(health: Health, metrics_server: Option<MetricsServer>) -> Self {
+        Self { health, metrics_server }
+    }
+
+    pub async fn run(
+        self,
+        listen_addr: SocketAddr,
+        shutdown_flag: Arc<AtomicBool>,
+    ) -> AnyResult<()> {
+        let health_server = Arc::new(self);
+
+        serve(listen_addr, shutdown_flag, move |request| ready(health_server.handle(request))).await
+    }
+
+    fn handle(&self, request: Request<Body>) -> Response<Body> {
+        if request.method() != Method::GET {
+            return create_empty_response(StatusCode::NOT_FOUND);
+        }
+
+        match request.uri().path() {
+            // Answering at all means the runtime is not wedged
+            LIVENESS_PATH => create_response(StatusCode::OK, "text/plain", Body::from("ok")),
+            READINESS_PATH => {
+                let health_report = self.health.report();
+                let status = if health_report.is_ready() {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                let body = Body::from(health_report.to_json_bytes_pretty());
+
+                create_response(status, "application/json", body)
+            }
+            _ => match self.metrics_server.as_ref() {
+                Some(metrics_server) => metrics_server.handle(request),
+                None => create_empty_response(StatusCode::NOT_FOUND),
+            },
+        }
+    }
+}
+
+fn create_response(status: StatusCode, content_type: &str, body: Body) -> Response<Body> {
+    Response::builder().status(status).header(CONTENT_TYPE, content_type).body(body).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+
+    fn get_status(health_server: &HealthServer, path: &str) -> StatusCode {
+        health_server.handle(Request::get(path).body(Body::empty()).unwrap()).status()
+    }
+
+    #[test]
+    fn test_serve_probes() {
+        let health = Health::new();
+        let health_server = HealthServer::new(health.clone(), None);
+
+        assert_eq!(get_status(&health_server, LIVENESS_PATH), StatusCode::OK);
+        assert_eq!(get_status(&health_server, READINESS_PATH), StatusCode::SERVICE_UNAVAILABLE);
+        health.set_smtp_reachable(true);
+        assert_eq!(get_status(&health_server, READINESS_PATH), StatusCode::OK);
+        assert_eq!(get_status(&health_server, "/metrics"), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_serve_metrics_on_shared_listener() {
+        let metrics_server = MetricsServer::new(Metrics::new().unwrap());
+        let health_server = HealthServer::new(Health::new(), Some(metrics_server));
+
+        assert_eq!(get_status(&health_server, LIVENESS_PATH), StatusCode::OK);
+        assert_eq!(get_status(&health_server, "/metrics"), StatusCode::OK);
+        assert_eq!(get_status(&health_server, "/other"), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,45 @@
+use crate::utils::wait_for_shutdown;
+use crate::AnyResult;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Answers every request on `listen_addr` with `handle` until a stop signal sets
+/// `shutdown_flag`, letting the requests in progress finish.
+pub async fn serve<H, F>(
+    listen_addr: SocketAddr,
+    shutdown_flag: Arc<AtomicBool>,
+    handle: H,
+) -> AnyResult<()>
+where
+    H: Fn(Request<Body>) -> F + Send + Sync + 'static,
+    F: Future<Output = Response<Body>> + Send + 'static,
+{
+    let handle = Arc::new(handle);
+    let make_service = make_service_fn(move |_| {
+        let handle = handle.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = handle(request);
+
+                async move { Ok::<_, Infallible>(response.await) }
+            }))
+        }
+    });
+
+    Server::try_bind(&listen_addr)?
+        .serve(make_service)
+        .with_graceful_shutdown(wait_for_shutdown(shutdown_flag))
+        .await?;
+
+    Ok(())
+}
+
+pub fn create_empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder().status(status).body(Body::empty()).unwrap()
+}
@@ -0,0 +1,404 @@
+use crate::config::{IngestionConfig, Secret};
+use crate::http::{create_empty_response, serve};
+use crate::mailer::{DraftValidator, EmailSendingResult, Mailer};
+use crate::messages::{MessageDraft, MessageFailType};
+use crate::{error, warn, AnyResult};
+use hyper::body::HttpBody;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use openssl::memcmp;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tapa_trait_serde::IJsonSerializable;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+use uuid::Uuid;
+
+const DRAFTS_PATH: &str = "/drafts";
+const API_KEY_HEADER: &str = "x-api-key";
+
+pub type DraftPublisher = Box<dyn Fn(&MessageDraft) -> AnyResult<()> + Send + Sync>;
+pub type ResultPublisher = Box<dyn Fn(&EmailSendingResult) -> AnyResult<()> + Send + Sync>;
+
+/// `Queue` publishes accepted drafts to `MQ_TOPIC_SOURCE`, `Send` sends them right away through
+/// the mailer shared with the consumers, so both take from the same quota buckets.
+pub enum IngestionDelivery {
+    Queue(DraftPublisher),
+    Send(Arc<Mutex<Mailer>>, ResultPublisher),
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, IJsonSerializable)]
+pub enum IngestionStatus {
+    #[serde(rename = "QUEUED")]
+    Queued,
+    #[serde(rename = "SENT")]
+    Sent,
+    #[serde(rename = "REJECTED")]
+    Rejected,
+    #[serde(rename = "DEFERRED")]
+    Deferred,
+    #[serde(rename = "FAILED")]
+    Failed,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, IJsonSerializable)]
+pub struct IngestionResponse {
+    pub draft_id: Option<Uuid>,
+    pub status: IngestionStatus,
+    pub message_id: Option<String>,
+    pub reason: Option<String>,
+}
+
+impl IngestionResponse {
+    fn new(draft_id: Option<Uuid>, status: IngestionStatus, reason: Option<String>) -> Self {
+        Self { draft_id, status, message_id: None, reason }
+    }
+}
+
+/// Accepts `MessageDraft` JSON on `POST /drafts` for services that cannot publish to NATS.
+pub struct IngestionServer {
+    api_keys: Secret,
+    draft_validator: DraftValidator,
+    service_instance_name: String,
+    delivery: IngestionDelivery,
+}
+
+impl IngestionServer {
+    pub fn new(
+        ingestion_config: &IngestionConfig,
+        draft_validator: DraftValidator,
+        service_instance_name: &str,
+        delivery: IngestionDelivery,
+    ) -> Self {
+        Self {
+            api_keys: ingestion_config.api_keys.clone(),
+            draft_validator,
+            service_instance_name: service_instance_name.into(),
+            delivery,
+        }
+    }
+
+    pub async fn run(
+        self,
+        listen_addr: SocketAddr,
+        shutdown_flag: Arc<AtomicBool>,
+    ) -> AnyResult<()> {
+        let ingestion_server = Arc::new(self);
+
+        serve(listen_addr, shutdown_flag, move |request| {
+            let ingestion_server = ingestion_server.clone();
+
+            async move { ingestion_server.handle(request).await }
+        })
+        .await
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        if request.uri().path() != DRAFTS_PATH {
+            return create_empty_response(StatusCode::NOT_FOUND);
+        }
+
+        if request.method() != Method::POST {
+            return create_empty_response(StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        if !self.is_authorized(&request) {
+            return create_empty_response(StatusCode::UNAUTHORIZED);
+        }
+
+        let request_body =
+            match read_body(request.into_body(), self.draft_validator.max_message_bytes()).await {
+                Err(status) => return create_empty_response(status),
+                Ok(request_body) => request_body,
+            };
+        let draft = match MessageDraft::from_json_bytes(&request_body) {
+            Err(e) => {
+                let reason = format!("Cannot parse draft: {}", e);
+                let ingestion_response =
+                    IngestionResponse::new(None, IngestionStatus::Rejected, Some(reason));
+
+                return create_response(StatusCode::BAD_REQUEST, &ingestion_response);
+            }
+            Ok(draft) => draft,
+        };
+        let draft_id = draft.id;
+
+        if let Err(reason) = self.draft_validator.validate(&draft) {
+            let ingestion_response =
+                IngestionResponse::new(Some(draft_id), IngestionStatus::Rejected, Some(reason));
+
+            return create_response(StatusCode::UNPROCESSABLE_ENTITY, &ingestion_response);
+        }
+
+        match &self.delivery {
+            IngestionDelivery::Queue(publish) => match publish(&draft) {
+                Err(e) => {
+                    error!("Cannot enqueue draft {}: {}", draft_id, e);
+                    let reason = Some("Cannot enqueue the draft!".into());
+                    let ingestion_response =
+                        IngestionResponse::new(Some(draft_id), IngestionStatus::Failed, reason);
+
+                    create_response(StatusCode::SERVICE_UNAVAILABLE, &ingestion_response)
+                }
+                Ok(_) => {
+                    let ingestion_response =
+                        IngestionResponse::new(Some(draft_id), IngestionStatus::Queued, None);
+
+                    create_response(StatusCode::ACCEPTED, &ingestion_response)
+                }
+            },
+            IngestionDelivery::Send(mailer, publish) => {
+                let email_sending_result = mailer
+                    .lock()
+                    .await
+                    .compose_and_send(None, &self.service_instance_name, draft)
+                    .await;
+
+                // Deferred drafts are retried by the caller, so only final results are published
+                if create_retry_after(&email_sending_result).is_none() {
+                    if let Err(e) = publish(&email_sending_result) {
+                        warn!("Cannot publish the result of draft {}: {}", draft_id, e);
+                    }
+                }
+
+                create_send_response(draft_id, email_sending_result)
+            }
+        }
+    }
+
+    /// Accepts a key from `X-Api-Key` or `Authorization: Bearer`.
+    fn is_authorized(&self, request: &Request<Body>) -> bool {
+        let headers = request.headers();
+        let api_key =
+            headers.get(API_KEY_HEADER).and_then(|value| value.to_str().ok()).or_else(|| {
+                headers
+                    .get(AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.strip_prefix("Bearer "))
+            });
+
+        match api_key {
+            None => false,
+            Some(api_key) => {
+                let known_keys = self.api_keys.get();
+
+                known_keys.unsecure().split(',').map(str::trim).filter(|k| !k.is_empty()).any(
+                    |known_key| {
+                        let known_key = known_key.as_bytes();
+
+                        known_key.len() == api_key.len()
+                            && memcmp::eq(known_key, api_key.as_bytes())
+                    },
+                )
+            }
+        }
+    }
+}
+
+async fn read_body(mut body: Body, max_bytes: usize) -> Result<Vec<u8>, StatusCode> {
+    let mut request_body = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+
+        if request_body.len() + chunk.len() > max_bytes {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        request_body.extend_from_slice(&chunk);
+    }
+
+    Ok(request_body)
+}
+
+/// Quota and throttling failures are only a wait, the draft itself was accepted.
+fn create_retry_after(email_sending_result: &EmailSendingResult) -> Option<Duration> {
+    match email_sending_result {
+        EmailSendingResult::Fail(message_fail) => match &message_fail.fail_reason {
+            MessageFailType::QuotaExhausted(duration_to_wait, _)
+            | MessageFailType::DomainThrottled(duration_to_wait, _) => Some(*duration_to_wait),
+            _ => None,
+        },
+        EmailSendingResult::Sent(_) => None,
+    }
+}
+
+fn create_send_response(
+    draft_id: Uuid,
+    email_sending_result: EmailSendingResult,
+) -> Response<Body> {
+    let retry_after = create_retry_after(&email_sending_result);
+    let message_fail = match email_sending_result {
+        EmailSendingResult::Sent(message_sent) => {
+            let mut ingestion_response =
+                IngestionResponse::new(Some(draft_id), IngestionStatus::Sent, None);
+            ingestion_response.message_id = Some(message_sent.message_id);
+
+            return create_response(StatusCode::OK, &ingestion_response);
+        }
+        EmailSendingResult::Fail(message_fail) => message_fail,
+    };
+    let (status_code, ingestion_status, reason) = match message_fail.fail_reason {
+        MessageFailType::QuotaExhausted(_, reason)
+        | MessageFailType::DomainThrottled(_, reason) => {
+            (StatusCode::TOO_MANY_REQUESTS, IngestionStatus::Deferred, reason)
+        }
+        MessageFailType::BadDraft(reason)
+        | MessageFailType::UndeliverableDomain(reason)
+        | MessageFailType::Suppressed(reason) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, IngestionStatus::Rejected, reason)
+        }
+        MessageFailType::Other(reason) => {
+            (StatusCode::BAD_GATEWAY, IngestionStatus::Failed, reason)
+        }
+        MessageFailType::Unknown => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            IngestionStatus::Failed,
+            "MessageFailType::Unknown should never occur!".into(),
+        ),
+    };
+    let ingestion_response = IngestionResponse::new(Some(draft_id), ingestion_status, Some(reason));
+    let mut response = create_response(status_code, &ingestion_response);
+
+    if let Some(retry_after) = retry_after {
+        // Whole seconds, rounded up so the caller never retries too early
+        let retry_after_seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        response.headers_mut().insert(RETRY_AFTER, retry_after_seconds.max(1).into());
+    }
+
+    response
+}
+
+fn create_response(status: StatusCode, ingestion_response: &IngestionResponse) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(ingestion_response.to_json_bytes_pretty()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DraftLimits, IngestionMode};
+    use crate::messages::{MessageFail, MessageSent};
+    use secstr::SecUtf8;
+    use std::sync::Mutex as StdMutex;
+
+    const DRAFT_ID: &str = "320b0555-4c73-4abf-aaf0-461b84860046";
+
+    fn create_draft_json(email_to: &str) -> String {
+        format!(
+            "{{\"id\":\"{}\",\"email_to\":\"{}\",\"email_to_name\":null,\
+            \"email_from\":\"noreply@example.com\",\"email_from_name\":null,\
+            \"subject\":\"Test\",\"body_type\":\"ASCII\",\"body\":\"Hello!!\",\
+            \"smime_recipient_cert\":null,\"timestamp\":\"2021-02-22T10:45:22.427738+00:00\"}}",
+            DRAFT_ID, email_to
+        )
+    }
+
+    fn create_server(published: Arc<StdMutex<Vec<Uuid>>>) -> IngestionServer {
+        let ingestion_config = IngestionConfig {
+            listen_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            api_keys: Secret::new(SecUtf8::from("old-key, new-key")),
+            mode: IngestionMode::Queue,
+        };
+        let draft_limits =
+            DraftLimits { max_subject_length: 998, max_body_bytes: 1024, max_message_bytes: 2048 };
+
+        IngestionServer::new(
+            &ingestion_config,
+            DraftValidator::new(&draft_limits),
+            "MAILER-TEST",
+            IngestionDelivery::Queue(Box::new(move |draft: &MessageDraft| {
+                published.lock().unwrap().push(draft.id);
+                Ok(())
+            })),
+        )
+    }
+
+    fn create_request(api_key: &str, body: String) -> Request<Body> {
+        Request::post(DRAFTS_PATH).header(API_KEY_HEADER, api_key).body(Body::from(body)).unwrap()
+    }
+
+    async fn read_response(response: Response<Body>) -> IngestionResponse {
+        let response_body = read_body(response.into_body(), usize::MAX).await.unwrap();
+
+        IngestionResponse::from_json_bytes(&response_body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_drafts() {
+        let published = Arc::new(StdMutex::new(Vec::new()));
+        let ingestion_server = create_server(published.clone());
+        let draft_id = Uuid::parse_str(DRAFT_ID).unwrap();
+
+        let queued = ingestion_server
+            .handle(create_request("new-key", create_draft_json("admin@example.com")))
+            .await;
+        let unauthorized = ingestion_server
+            .handle(create_request("new-kez", create_draft_json("admin@example.com")))
+            .await;
+        let invalid = ingestion_server
+            .handle(create_request("old-key", create_draft_json("admin@@example.com")))
+            .await;
+        let malformed = ingestion_server.handle(create_request("old-key", "{}".into())).await;
+        let too_large = ingestion_server.handle(create_request("old-key", "x".repeat(4096))).await;
+
+        assert_eq!(queued.status(), StatusCode::ACCEPTED);
+        assert_eq!(unauthorized.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(invalid.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(malformed.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(too_large.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let queued = read_response(queued).await;
+        let invalid = read_response(invalid).await;
+
+        assert_eq!(queued.draft_id, Some(draft_id));
+        assert_eq!(queued.status, IngestionStatus::Queued);
+        assert_eq!(invalid.status, IngestionStatus::Rejected);
+        assert!(invalid.reason.unwrap().starts_with("Invalid destination"));
+        assert_eq!(*published.lock().unwrap(), vec![draft_id]);
+    }
+
+    #[test]
+    fn test_map_sending_results() {
+        let draft_id = Uuid::parse_str(DRAFT_ID).unwrap();
+        let create_fail = |fail_reason| {
+            EmailSendingResult::Fail(MessageFail::new(
+                None,
+                "MAILER-TEST",
+                "{}".into(),
+                fail_reason,
+            ))
+        };
+
+        let sent = create_send_response(
+            draft_id,
+            EmailSendingResult::Sent(MessageSent::new(
+                None,
+                "MAILER-TEST",
+                draft_id,
+                "<a@b>".into(),
+            )),
+        );
+        let deferred = create_send_response(
+            draft_id,
+            create_fail(MessageFailType::QuotaExhausted(
+                Duration::from_millis(1500),
+                "Exhausted maximum email per second!".into(),
+            )),
+        );
+        let suppressed =
+            create_send_response(draft_id, create_fail(MessageFailType::Suppressed("".into())));
+        let failed = create_send_response(draft_id, create_fail(MessageFailType::Other("".into())));
+
+        assert_eq!(sent.status(), StatusCode::OK);
+        assert_eq!(deferred.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(deferred.headers()[RETRY_AFTER], "2");
+        assert_eq!(suppressed.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failed.status(), StatusCode::BAD_GATEWAY);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::config::{LogConfig, LogFormat};
+use crate::log;
+use crate::messages::MessageDraft;
+use chrono::{SecondsFormat, Utc};
+use env_logger::builder as log_builder;
+use log::{Level, Record};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::env::{set_var, var};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use uuid::Uuid;
+
+const RUST_LOG: &str = "RUST_LOG";
+const REDACTED: &str = "***";
+
+static REDACT_BODIES: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Extra JSON fields of the record being logged by this thread.
+    static LOG_FIELDS: RefCell<Vec<(&'static str, Value)>> = RefCell::new(Vec::new());
+}
+
+pub fn init_logger(log_config: &LogConfig) {
+    if var(RUST_LOG).is_err() {
+        #[cfg(debug_assertions)]
+        set_var(RUST_LOG, "debug");
+        #[cfg(not(debug_assertions))]
+        set_var(RUST_LOG, "info");
+    }
+
+    REDACT_BODIES.store(log_config.redact_bodies, Ordering::Relaxed);
+
+    let mut builder = log_builder();
+
+    match log_config.format {
+        LogFormat::Text if !log_config.redact_addresses => {
+            builder.default_format().format_timestamp_nanos().format_indent(None);
+        }
+        LogFormat::Text => {
+            builder.format(|buf, record| {
+                let message = redact_addresses(&record.args().to_string());
+
+                writeln!(
+                    buf,
+                    "[{} {:<5} {}] {}",
+                    buf.timestamp_nanos(),
+                    record.level(),
+                    record.target(),
+                    message
+                )
+            });
+        }
+        LogFormat::Json => {
+            let log_config = log_config.clone();
+
+            builder.format(move |buf, record| {
+                writeln!(buf, "{}", create_json_line(&log_config, record))
+            });
+        }
+    }
+
+    builder.init();
+}
+
+/// Logs one attempt at a draft with `draft_id`, `outcome` and `latency_ms` as JSON fields.
+pub fn log_draft_outcome(level: Level, draft_id: &Uuid, outcome: &str, latency: Duration) {
+    let latency_ms = latency.as_millis() as u64;
+
+    LOG_FIELDS.with(|log_fields| {
+        *log_fields.borrow_mut() = vec![
+            ("draft_id", Value::from(draft_id.to_string())),
+            ("outcome", Value::from(outcome)),
+            ("latency_ms", Value::from(latency_ms)),
+        ]
+    });
+    log!(level, "Draft {} {} in {} ms", draft_id, outcome, latency_ms);
+    LOG_FIELDS.with(|log_fields| log_fields.borrow_mut().clear());
+}
+
+/// Copy of `draft` safe to log, the subject and body are masked when `LOG_REDACT_BODIES` is
+/// set. Addresses are masked by the logger itself.
+pub fn redact_draft(draft: &MessageDraft) -> MessageDraft {
+    let mut redacted_draft = draft.clone();
+
+    if REDACT_BODIES.load(Ordering::Relaxed) {
+        redacted_draft.subject = REDACTED.into();
+        redacted_draft.body = format!("{} ({} bytes)", REDACTED, draft.body.len());
+        redacted_draft.email_to_name = draft.email_to_name.as_ref().map(|_| REDACTED.into());
+        redacted_draft.email_from_name = draft.email_from_name.as_ref().map(|_| REDACTED.into());
+    }
+
+    redacted_draft
+}
+
+fn create_json_line(log_config: &LogConfig, record: &Record) -> String {
+    let mut message = record.args().to_string();
+
+    if log_config.redact_addresses {
+        message = redact_addresses(&message);
+    }
+
+    let mut json_line = Map::new();
+
+    json_line
+        .insert("timestamp".into(), Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true).into());
+    json_line.insert("level".into(), record.level().as_str().into());
+    json_line.insert("target".into(), record.target().into());
+
+    if let Some(instance_name) = log_config.instance_name.as_ref() {
+        json_line.insert("instance_name".into(), instance_name.as_str().into());
+    }
+
+    json_line.insert("message".into(), message.into());
+    LOG_FIELDS.with(|log_fields| {
+        for (name, value) in log_fields.borrow().iter() {
+            json_line.insert((*name).into(), value.clone());
+        }
+    });
+
+    Value::Object(json_line).to_string()
+}
+
+/// Masks the local part of every address in `text`, the domain is kept for troubleshooting.
+fn redact_addresses(text: &str) -> String {
+    let mut redacted_text = String::with_capacity(text.len());
+    let mut copied_until = 0;
+
+    for (at_index, _) in text.match_indices('@') {
+        let local_part_start = text[..at_index]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_local_part_char(*c))
+            .last()
+            .map(|(index, _)| index);
+
+        if let Some(local_part_start) = local_part_start {
+            if local_part_start >= copied_until {
+                redacted_text.push_str(&text[copied_until..local_part_start]);
+                redacted_text.push_str(REDACTED);
+                copied_until = at_index;
+            }
+        }
+    }
+
+    redacted_text.push_str(&text[copied_until..]);
+    redacted_text
+}
+
+fn is_local_part_char(c: char) -> bool {
+    c.is_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_addresses() {
+        assert_eq!(
+            redact_addresses("Destination admin.x+1@example.com is suppressed (HardBounce)!"),
+            "Destination ***@example.com is suppressed (HardBounce)!"
+        );
+        assert_eq!(
+            redact_addresses("<pelé@例え.jp>, \"Bob\" <bob@example.com>; @mention"),
+            "<***@例え.jp>, \"Bob\" <***@example.com>; @mention"
+        );
+    }
+
+    #[test]
+    fn test_create_json_line() {
+        let log_config = LogConfig {
+            format: LogFormat::Json,
+            redact_addresses: true,
+            redact_bodies: true,
+            instance_name: Some("MAILER-TEST_host".into()),
+        };
+        let draft_id = Uuid::new_v4();
+
+        LOG_FIELDS.with(|log_fields| {
+            *log_fields.borrow_mut() = vec![
+                ("draft_id", Value::from(draft_id.to_string())),
+                ("latency_ms", Value::from(42)),
+            ]
+        });
+
+        let json_line = create_json_line(
+            &log_config,
+            &Record::builder()
+                .args(format_args!("Sent to admin@example.com"))
+                .level(Level::Info)
+                .target("tapa_micro_mailer")
+                .build(),
+        );
+        let json_line: Value = serde_json::from_str(&json_line).unwrap();
+
+        LOG_FIELDS.with(|log_fields| log_fields.borrow_mut().clear());
+
+        assert_eq!(json_line["level"], "INFO");
+        assert_eq!(json_line["instance_name"], "MAILER-TEST_host");
+        assert_eq!(json_line["message"], "Sent to ***@example.com");
+        assert_eq!(json_line["draft_id"], draft_id.to_string());
+        assert_eq!(json_line["latency_ms"], 42);
+    }
+}
@@ -0,0 +1,220 @@
+use super::rate_limiter::RateLimiter;
+use crate::utils::{DAY_IN_SECONDS, HOUR_IN_SECONDS, MINUTE_IN_SECONDS};
+use chrono::{
+    DateTime, Duration as ChronoDuration, LocalResult, NaiveDateTime, TimeZone, Timelike, Utc,
+};
+use chrono_tz::Tz;
+use tokio::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalendarPeriod {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl CalendarPeriod {
+    pub fn from_interval(interval: Duration) -> Option<Self> {
+        match interval.as_secs() {
+            MINUTE_IN_SECONDS => Some(Self::Minute),
+            HOUR_IN_SECONDS => Some(Self::Hour),
+            DAY_IN_SECONDS => Some(Self::Day),
+            _ => None,
+        }
+    }
+}
+
+/// Fixed window bucket which refills on wall-clock boundaries (e.g. midnight) in `timezone`.
+pub struct CalendarBucket {
+    bucket_size: usize,
+    current_bucket_size: usize,
+    period: CalendarPeriod,
+    timezone: Tz,
+    anchor_instant: Instant,
+    anchor_time: DateTime<Utc>,
+    window_end: Option<Instant>,
+}
+
+impl CalendarBucket {
+    pub fn new(bucket_size: usize, period: CalendarPeriod, timezone: Tz) -> Self {
+        Self::new_with_anchor(bucket_size, period, timezone, Instant::now(), Utc::now())
+    }
+
+    /// Same as `new`, but maps `anchor_instant` to `anchor_time` instead of the current time.
+    pub fn new_with_anchor(
+        bucket_size: usize,
+        period: CalendarPeriod,
+        timezone: Tz,
+        anchor_instant: Instant,
+        anchor_time: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            bucket_size,
+            period,
+            timezone,
+            anchor_instant,
+            anchor_time,
+            current_bucket_size: bucket_size,
+            window_end: None,
+        }
+    }
+
+    fn next_boundary(&self, current_instant: &Instant) -> Instant {
+        let elapsed = current_instant.saturating_duration_since(self.anchor_instant);
+        let current_time = self.anchor_time
+            + ChronoDuration::from_std(elapsed).unwrap_or_else(|_| ChronoDuration::zero());
+        let local_time = current_time.with_timezone(&self.timezone).naive_local();
+        let local_date = local_time.date();
+        let next_local_time = match self.period {
+            CalendarPeriod::Minute => {
+                local_date.and_hms(local_time.hour(), local_time.minute(), 0)
+                    + ChronoDuration::minutes(1)
+            }
+            CalendarPeriod::Hour => {
+                local_date.and_hms(local_time.hour(), 0, 0) + ChronoDuration::hours(1)
+            }
+            CalendarPeriod::Day => local_date.and_hms(0, 0, 0) + ChronoDuration::days(1),
+        };
+        let next_time = self.resolve_local_time(next_local_time, &current_time);
+
+        *current_instant + (next_time - current_time).to_std().unwrap_or_default()
+    }
+
+    fn resolve_local_time(
+        &self,
+        local_time: NaiveDateTime,
+        current_time: &DateTime<Utc>,
+    ) -> DateTime<Utc> {
+        let mut candidate_time = local_time;
+
+        loop {
+            match self.timezone.from_local_datetime(&candidate_time) {
+                LocalResult::Single(resolved_time) => return resolved_time.with_timezone(&Utc),
+                // Local times repeated by DST fall back, take the first one not already passed
+                LocalResult::Ambiguous(earliest_time, latest_time) => {
+                    if earliest_time.with_timezone(&Utc) > *current_time {
+                        return earliest_time.with_timezone(&Utc);
+                    }
+
+                    return latest_time.with_timezone(&Utc);
+                }
+                // Local times skipped by DST spring forward, take the first one after the gap
+                LocalResult::None => candidate_time += ChronoDuration::minutes(15),
+            }
+        }
+    }
+}
+
+impl RateLimiter for CalendarBucket {
+    fn try_take(&mut self, current_instant: &Instant) -> Option<Duration> {
+        let window_end = match self.window_end {
+            Some(window_end) if window_end > *current_instant => window_end,
+            _ => {
+                let window_end = self.next_boundary(current_instant);
+
+                self.current_bucket_size = self.bucket_size;
+                self.window_end = Some(window_end);
+
+                window_end
+            }
+        };
+
+        if self.current_bucket_size > 0 {
+            self.current_bucket_size -= 1;
+
+            None
+        } else {
+            Some(window_end - *current_instant)
+        }
+    }
+
+    fn remaining(&self, current_instant: &Instant) -> usize {
+        match self.window_end {
+            Some(window_end) if window_end > *current_instant => self.current_bucket_size,
+            _ => self.bucket_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::{America::New_York, Asia::Jakarta, Asia::Kolkata};
+
+    fn create_bucket(
+        period: CalendarPeriod,
+        timezone: Tz,
+        anchor_time: &str,
+    ) -> (CalendarBucket, Instant) {
+        let anchor_instant = Instant::now();
+        let anchor_time = DateTime::parse_from_rfc3339(anchor_time).unwrap().with_timezone(&Utc);
+
+        (
+            CalendarBucket::new_with_anchor(1, period, timezone, anchor_instant, anchor_time),
+            anchor_instant,
+        )
+    }
+
+    #[test]
+    fn test_day_window_ends_at_local_midnight() {
+        // 23:59:30 in Jakarta (UTC+7)
+        let (mut bucket, anchor_instant) =
+            create_bucket(CalendarPeriod::Day, Jakarta, "2021-03-01T16:59:30+00:00");
+
+        assert_eq!(bucket.try_take(&anchor_instant), None);
+        assert_eq!(bucket.try_take(&anchor_instant), Some(Duration::from_secs(30)));
+        assert_eq!(bucket.try_take(&(anchor_instant + Duration::from_secs(30))), None);
+        assert_eq!(
+            bucket.try_take(&(anchor_instant + Duration::from_secs(30))),
+            Some(Duration::from_secs(DAY_IN_SECONDS))
+        );
+    }
+
+    #[test]
+    fn test_hour_window_follows_half_hour_offset() {
+        // 10:50:00 in Kolkata (UTC+5:30)
+        let (mut bucket, anchor_instant) =
+            create_bucket(CalendarPeriod::Hour, Kolkata, "2021-03-01T05:20:00+00:00");
+
+        assert_eq!(bucket.try_take(&anchor_instant), None);
+        assert_eq!(
+            bucket.try_take(&anchor_instant),
+            Some(Duration::from_secs(10 * MINUTE_IN_SECONDS))
+        );
+    }
+
+    #[test]
+    fn test_minute_window_ends_at_next_minute() {
+        let (mut bucket, anchor_instant) =
+            create_bucket(CalendarPeriod::Minute, Jakarta, "2021-03-01T16:59:45+00:00");
+
+        assert_eq!(bucket.try_take(&anchor_instant), None);
+        assert_eq!(bucket.try_take(&anchor_instant), Some(Duration::from_secs(15)));
+    }
+
+    #[test]
+    fn test_minute_window_in_repeated_dst_hour() {
+        // 01:30:30 EST in New York, the second time that hour is seen on 2021-11-07
+        let (mut bucket, anchor_instant) =
+            create_bucket(CalendarPeriod::Minute, New_York, "2021-11-07T06:30:30+00:00");
+
+        assert_eq!(bucket.try_take(&anchor_instant), None);
+        assert_eq!(bucket.try_take(&anchor_instant), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_day_window_spans_dst_change() {
+        // 12:00 in New York the day before clocks go forward, the next midnight is 12 hours away
+        let (mut bucket, anchor_instant) =
+            create_bucket(CalendarPeriod::Day, New_York, "2021-03-13T17:00:00+00:00");
+        let next_midnight = anchor_instant + Duration::from_secs(12 * HOUR_IN_SECONDS);
+
+        assert_eq!(bucket.try_take(&anchor_instant), None);
+        assert_eq!(bucket.try_take(&next_midnight), None);
+        // 2021-03-14 only lasts 23 hours in New York
+        assert_eq!(
+            bucket.try_take(&next_midnight),
+            Some(Duration::from_secs(23 * HOUR_IN_SECONDS))
+        );
+    }
+}
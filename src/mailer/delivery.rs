@@ -0,0 +1,173 @@
+use crate::warn;
+use lettre::address::Envelope;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::Error as SmtpError;
+use lettre::{Address, AsyncSmtpTransport, Tokio02Connector, Tokio02Transport};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{stdout, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs::write;
+use uuid::Uuid;
+
+const ORIGINAL_RECIPIENT_HEADER: &str = "X-Original-Recipient";
+
+pub enum DeliveryError {
+    Smtp(SmtpError),
+    Sandbox(String),
+}
+
+impl Display for DeliveryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Smtp(e) => e.fmt(f),
+            Self::Sandbox(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// Where composed emails go. The sandbox deliveries stand in for the SMTP relay, so staging
+/// runs the whole pipeline without emailing anyone. Clones share the SMTP transport.
+#[derive(Clone)]
+pub enum Delivery {
+    Smtp(Arc<AsyncSmtpTransport<Tokio02Connector>>),
+    /// Writes `<draft id>.eml` files to the directory, retries overwrite their previous file.
+    File(PathBuf),
+    Stdout,
+}
+
+impl Delivery {
+    pub async fn send(
+        &self,
+        envelope: &Envelope,
+        raw_email: &[u8],
+        draft_id: &Uuid,
+    ) -> Result<(), DeliveryError> {
+        match self {
+            Self::Smtp(transport) => transport
+                .send_raw(envelope, raw_email)
+                .await
+                .map(|_| ())
+                .map_err(DeliveryError::Smtp),
+            Self::File(file_dir) => {
+                let file_path = file_dir.join(format!("{}.eml", draft_id));
+
+                write(&file_path, raw_email).await.map_err(|e| {
+                    DeliveryError::Sandbox(format!("Cannot write {}: {}", file_path.display(), e))
+                })
+            }
+            Self::Stdout => {
+                let recipients: Vec<_> =
+                    envelope.to().iter().map(|address| address.to_string()).collect();
+                let stdout = stdout();
+                let mut stdout = stdout.lock();
+
+                writeln!(
+                    stdout,
+                    "----- Draft {} from <{}> to <{}> -----",
+                    draft_id,
+                    envelope.from().map(|address| address.to_string()).unwrap_or_default(),
+                    recipients.join(">, <")
+                )
+                .and_then(|_| stdout.write_all(raw_email))
+                .and_then(|_| writeln!(stdout))
+                .and_then(|_| stdout.flush())
+                .map_err(|e| DeliveryError::Sandbox(format!("Cannot write to stdout: {}", e)))
+            }
+        }
+    }
+
+    pub fn is_smtp(&self) -> bool {
+        matches!(self, Self::Smtp(_))
+    }
+
+    /// Opens a connection to the SMTP server and checks it answers `NOOP`. The sandbox
+    /// deliveries are always reachable.
+    pub async fn is_reachable(&self) -> bool {
+        let transport = match self {
+            Self::Smtp(transport) => transport,
+            Self::File(_) | Self::Stdout => return true,
+        };
+
+        match transport.test_connection().await {
+            Err(e) => {
+                warn!("SMTP connection test failed: {}", e);
+                false
+            }
+            Ok(is_connected) => is_connected,
+        }
+    }
+}
+
+/// Sends every email to a safe address instead of its destination, which is kept in a header.
+pub struct Redirect {
+    address: Address,
+}
+
+impl Redirect {
+    pub fn new(address: Address) -> Self {
+        Self { address }
+    }
+
+    /// Replaces the mailbox of the destination, keeping its display name.
+    pub fn recipient(&self, name: Option<String>) -> Mailbox {
+        Mailbox::new(name, self.address.clone())
+    }
+
+    pub fn add_original_recipient(&self, raw_email: &[u8], original_recipient: &str) -> Vec<u8> {
+        let header = format!("{}: {}\r\n", ORIGINAL_RECIPIENT_HEADER, original_recipient);
+
+        [header.as_bytes(), raw_email].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lettre::Message as Email;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, read, remove_dir_all};
+
+    fn create_email(to_address: Mailbox) -> Email {
+        Email::builder()
+            .from("noreply@example.com".parse().unwrap())
+            .to(to_address)
+            .subject("Test")
+            .body("Hello!!".to_string())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_file_delivery_writes_eml_file() {
+        let file_dir = temp_dir().join(format!("emails-{}", Uuid::new_v4()));
+        let draft_id = Uuid::new_v4();
+        let email = create_email("admin@example.com".parse().unwrap());
+        let raw_email = email.formatted();
+
+        create_dir_all(&file_dir).unwrap();
+
+        let delivery = Delivery::File(file_dir.clone());
+        let send_result = delivery.send(email.envelope(), &raw_email, &draft_id).await;
+        let written_email = read(file_dir.join(format!("{}.eml", draft_id)));
+
+        remove_dir_all(&file_dir).unwrap();
+
+        assert!(send_result.is_ok());
+        assert!(!delivery.is_smtp());
+        assert_eq!(written_email.unwrap(), raw_email);
+    }
+
+    #[test]
+    fn test_redirect_rewrites_recipient() {
+        let redirect = Redirect::new("safe@example.com".parse().unwrap());
+        let email = create_email(redirect.recipient(Some("Admin".into())));
+        let safe_address: Address = "safe@example.com".parse().unwrap();
+        let raw_email = redirect.add_original_recipient(&email.formatted(), "admin@example.com");
+        let raw_email = String::from_utf8(raw_email).unwrap();
+
+        assert_eq!(email.envelope().to(), &[safe_address][..]);
+        assert!(raw_email.starts_with("X-Original-Recipient: admin@example.com\r\n"));
+        assert!(raw_email.contains("safe@example.com"));
+        assert_eq!(raw_email.matches("admin@example.com").count(), 1);
+    }
+}
@@ -0,0 +1,254 @@
+use super::mime::{parse_headers, split_message};
+use crate::config::{DkimConfig, DkimPrivateKey};
+use crate::{anyerror, AnyResult};
+use chrono::Utc;
+use openssl::base64::encode_block;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::sha::sha256;
+use openssl::sign::Signer;
+use std::collections::HashMap;
+
+const HEADER_NAME: &str = "DKIM-Signature";
+
+/// Signs outgoing messages of one sender domain, with relaxed/relaxed canonicalization (RFC 6376)
+pub struct DkimSigner {
+    domain: String,
+    selector: String,
+    signed_headers: Vec<String>,
+    private_key: PKey<Private>,
+}
+
+impl DkimSigner {
+    pub fn new(
+        domain: &str,
+        selector: &str,
+        signed_headers: &[String],
+        private_key: &DkimPrivateKey,
+    ) -> Self {
+        Self {
+            domain: domain.to_lowercase(),
+            selector: selector.into(),
+            signed_headers: signed_headers.to_vec(),
+            private_key: private_key.as_pkey().clone(),
+        }
+    }
+
+    /// Creates one signer per configured sender domain.
+    pub fn from_config(dkim_config: &DkimConfig) -> HashMap<String, Self> {
+        dkim_config
+            .private_keys
+            .iter()
+            .map(|(domain, private_key)| {
+                let signer =
+                    Self::new(domain, &dkim_config.selector, &dkim_config.headers, private_key);
+
+                (signer.domain.clone(), signer)
+            })
+            .collect()
+    }
+
+    /// Returns `raw_email` with a `DKIM-Signature` header prepended.
+    pub fn sign(&self, raw_email: &[u8]) -> AnyResult<Vec<u8>> {
+        self.sign_at(raw_email, Utc::now().timestamp())
+    }
+
+    fn sign_at(&self, raw_email: &[u8], timestamp: i64) -> AnyResult<Vec<u8>> {
+        let (header_block, body) = split_message(raw_email);
+        let headers = parse_headers(header_block);
+        let algorithm = match self.private_key.id() {
+            Id::RSA => "rsa-sha256",
+            Id::ED25519 => "ed25519-sha256",
+            _ => return Err(anyerror!("Unsupported DKIM key type for {}!", self.domain)),
+        };
+        let body_hash = encode_block(&sha256(&canonicalize_body_relaxed(body)));
+        let mut signed_header_names = Vec::new();
+        let mut signing_input = Vec::new();
+        let mut used_header_indexes = Vec::new();
+
+        // Multiple instances of a header are signed from the bottom up (RFC 6376 5.4.2)
+        for header_name in self.signed_headers.iter() {
+            let found_header = headers.iter().enumerate().rev().find(|(index, (name, _))| {
+                name.eq_ignore_ascii_case(header_name) && !used_header_indexes.contains(index)
+            });
+
+            if let Some((index, (name, value))) = found_header {
+                used_header_indexes.push(index);
+                signing_input.extend(canonicalize_header_relaxed(name, value).into_bytes());
+                signing_input.extend(b"\r\n");
+            }
+
+            signed_header_names.push(header_name.to_lowercase());
+        }
+
+        let header_value = format!(
+            "v=1; a={}; c=relaxed/relaxed; d={}; s={};\r\n\tt={}; h={};\r\n\tbh={};\r\n\tb=",
+            algorithm,
+            self.domain,
+            self.selector,
+            timestamp,
+            signed_header_names.join(":"),
+            body_hash
+        );
+
+        signing_input.extend(canonicalize_header_relaxed(HEADER_NAME, &header_value).into_bytes());
+
+        let signature = match self.private_key.id() {
+            Id::ED25519 => {
+                // Ed25519 signs the SHA-256 digest of the signing input (RFC 8463)
+                let mut signer = Signer::new_without_digest(&self.private_key)?;
+                signer.sign_oneshot_to_vec(&sha256(&signing_input))?
+            }
+            _ => {
+                let mut signer = Signer::new(MessageDigest::sha256(), &self.private_key)?;
+                signer.update(&signing_input)?;
+                signer.sign_to_vec()?
+            }
+        };
+        let mut signed_email = Vec::with_capacity(raw_email.len() + 1024);
+
+        signed_email.extend(format!("{}: {}", HEADER_NAME, header_value).into_bytes());
+        signed_email.extend(encode_block(&signature).into_bytes());
+        signed_email.extend(b"\r\n");
+        signed_email.extend(raw_email);
+
+        Ok(signed_email)
+    }
+}
+
+fn compress_whitespace(value: &str) -> String {
+    let mut compressed = String::with_capacity(value.len());
+    let mut in_whitespace = false;
+
+    for c in value.chars() {
+        if c == ' ' || c == '\t' {
+            in_whitespace = true;
+        } else {
+            if in_whitespace {
+                compressed.push(' ');
+                in_whitespace = false;
+            }
+
+            compressed.push(c);
+        }
+    }
+
+    if in_whitespace {
+        compressed.push(' ');
+    }
+
+    compressed
+}
+
+fn canonicalize_header_relaxed(name: &str, value: &str) -> String {
+    let unfolded_value = value.replace("\r\n", "");
+
+    format!("{}:{}", name.trim().to_lowercase(), compress_whitespace(&unfolded_value).trim())
+}
+
+fn canonicalize_body_relaxed(body: &[u8]) -> Vec<u8> {
+    let body = String::from_utf8_lossy(body);
+    let mut lines: Vec<String> = body
+        .split("\r\n")
+        .map(|line| compress_whitespace(line).trim_end_matches(' ').to_string())
+        .collect();
+
+    while lines.last().map_or(false, |line| line.is_empty()) {
+        lines.pop();
+    }
+
+    if lines.is_empty() {
+        return Vec::new();
+    }
+
+    let mut canonical_body = lines.join("\r\n");
+    canonical_body.push_str("\r\n");
+
+    canonical_body.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::rsa::Rsa;
+    use openssl::sign::Verifier;
+
+    const RAW_EMAIL: &[u8] = b"From: Tapalogi System <noreply@example.com>\r\n\
+        To: admin@example.com\r\n\
+        Subject: Tapa Micro Mailer -\r\n Test\r\n\
+        \r\n\
+        Hello!!  This is from example.com \r\n\r\n";
+
+    fn create_signer(private_key: PKey<Private>) -> DkimSigner {
+        DkimSigner {
+            private_key,
+            domain: "example.com".into(),
+            selector: "mail".into(),
+            signed_headers: vec!["From".into(), "To".into(), "Subject".into(), "Date".into()],
+        }
+    }
+
+    fn verify_signature(signed_email: &[u8], private_key: &PKey<Private>) -> bool {
+        let (header_block, _) = split_message(signed_email);
+        let headers = parse_headers(header_block);
+        let (_, signature_value) = headers.first().unwrap();
+        let (unsigned_value, signature) =
+            signature_value.split_at(signature_value.find("\tb=").unwrap() + 3);
+        let signature = openssl::base64::decode_block(signature).unwrap();
+        let mut signing_input = Vec::new();
+
+        for (name, value) in headers.iter().skip(1).filter(|(name, _)| name != "Date") {
+            signing_input.extend(canonicalize_header_relaxed(name, value).into_bytes());
+            signing_input.extend(b"\r\n");
+        }
+
+        signing_input.extend(canonicalize_header_relaxed(HEADER_NAME, unsigned_value).into_bytes());
+
+        let public_key =
+            PKey::public_key_from_der(&private_key.public_key_to_der().unwrap()).unwrap();
+
+        if private_key.id() == Id::ED25519 {
+            let mut verifier = Verifier::new_without_digest(&public_key).unwrap();
+            verifier.verify_oneshot(&signature, &sha256(&signing_input)).unwrap()
+        } else {
+            let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key).unwrap();
+            verifier.update(&signing_input).unwrap();
+            verifier.verify(&signature).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_relaxed_canonicalization() {
+        // Example from RFC 6376 3.4.5
+        assert_eq!(canonicalize_header_relaxed("A", " X"), "a:X");
+        assert_eq!(canonicalize_header_relaxed("B ", " Y\t\r\n\tZ  "), "b:Y Z");
+        assert_eq!(canonicalize_body_relaxed(b" C \r\nD \t E\r\n\r\n\r\n"), b" C\r\nD E\r\n");
+        assert_eq!(canonicalize_body_relaxed(b"\r\n\r\n"), b"");
+    }
+
+    #[test]
+    fn test_sign_with_rsa_key() {
+        let private_key = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let signed_email =
+            create_signer(private_key.clone()).sign_at(RAW_EMAIL, 1613990722).unwrap();
+        let signed_email_string = String::from_utf8(signed_email.clone()).unwrap();
+
+        assert!(signed_email_string.starts_with(
+            "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com; s=mail;\r\n\
+            \tt=1613990722; h=from:to:subject:date;\r\n\
+            \tbh=zbTlgEBHRK2dXm/TWcx8CmQXoNm5rM4UTvwOFUHIItI=;\r\n\tb="
+        ));
+        assert!(signed_email.ends_with(RAW_EMAIL));
+        assert!(verify_signature(&signed_email, &private_key));
+    }
+
+    #[test]
+    fn test_sign_with_ed25519_key() {
+        let private_key = PKey::generate_ed25519().unwrap();
+        let signed_email =
+            create_signer(private_key.clone()).sign_at(RAW_EMAIL, 1613990722).unwrap();
+
+        assert!(String::from_utf8(signed_email.clone()).unwrap().contains("a=ed25519-sha256;"));
+        assert!(verify_signature(&signed_email, &private_key));
+    }
+}
@@ -0,0 +1,253 @@
+use super::rate_limiter::{create_rate_limiter, RateLimiter};
+use crate::config::DomainQuotaLimits;
+use crate::utils::{get_email_domain, HOUR_IN_SECONDS, MINUTE_IN_SECONDS};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+use tokio::time::{Duration, Instant};
+
+struct DomainBucket {
+    period_name: &'static str,
+    max: usize,
+    limiter: Box<dyn RateLimiter + Send>,
+}
+
+pub struct DomainThrottle {
+    domain_buckets: HashMap<String, Vec<DomainBucket>>,
+}
+
+impl DomainThrottle {
+    pub fn new(
+        domain_limits: &HashMap<String, DomainQuotaLimits>,
+        quota_timezone: Option<&Tz>,
+    ) -> Self {
+        let mut domain_buckets = HashMap::new();
+
+        for (domain, limits) in domain_limits {
+            let mut buckets = Vec::new();
+
+            if let Some(mpt) = limits.max_per_second.as_ref() {
+                buckets.push(DomainBucket {
+                    period_name: "second",
+                    max: mpt.max,
+                    limiter: create_rate_limiter(mpt, Duration::from_secs(1), quota_timezone),
+                });
+            }
+
+            if let Some(mpt) = limits.max_per_minute.as_ref() {
+                buckets.push(DomainBucket {
+                    period_name: "minute",
+                    max: mpt.max,
+                    limiter: create_rate_limiter(
+                        mpt,
+                        Duration::from_secs(MINUTE_IN_SECONDS),
+                        quota_timezone,
+                    ),
+                });
+            }
+
+            if let Some(mpt) = limits.max_per_hour.as_ref() {
+                buckets.push(DomainBucket {
+                    period_name: "hour",
+                    max: mpt.max,
+                    limiter: create_rate_limiter(
+                        mpt,
+                        Duration::from_secs(HOUR_IN_SECONDS),
+                        quota_timezone,
+                    ),
+                });
+            }
+
+            domain_buckets.insert(domain.to_lowercase(), buckets);
+        }
+
+        Self { domain_buckets }
+    }
+
+    /// Charges `self` with the permits `previous_throttle` has used per domain and period, so
+    /// limits changed on reload do not start over.
+    pub fn carry_over(&mut self, previous_throttle: &DomainThrottle, current_instant: &Instant) {
+        for (domain, buckets) in self.domain_buckets.iter_mut() {
+            let previous_buckets = match previous_throttle.domain_buckets.get(domain) {
+                None => continue,
+                Some(previous_buckets) => previous_buckets,
+            };
+
+            for bucket in buckets.iter_mut() {
+                if let Some(previous_bucket) = previous_buckets
+                    .iter()
+                    .find(|previous| previous.period_name == bucket.period_name)
+                {
+                    let used = previous_bucket
+                        .max
+                        .saturating_sub(previous_bucket.limiter.remaining(current_instant));
+                    let taken_instants = previous_bucket.limiter.taken_instants(current_instant);
+
+                    bucket.limiter.take_used(used, &taken_instants, current_instant);
+                }
+            }
+        }
+    }
+
+    /// Returns the duration to wait and the reason when `email_address`'s domain is throttled,
+    /// without taking any permit.
+    pub fn check(
+        &mut self,
+        email_address: &str,
+        current_instant: &Instant,
+    ) -> Option<(Duration, String)> {
+        let domain = get_email_domain(email_address)?;
+        let buckets = self.domain_buckets.get_mut(&domain)?;
+
+        for bucket in buckets.iter_mut() {
+            if let Some(duration_to_wait) = bucket.limiter.check(current_instant) {
+                return Some((
+                    duration_to_wait,
+                    format!("Exhausted maximum email per {} to {}!", bucket.period_name, domain),
+                ));
+            }
+        }
+
+        None
+    }
+
+    /// Same as `check`, but takes a permit of every period when none is throttled.
+    pub fn try_take(
+        &mut self,
+        email_address: &str,
+        current_instant: &Instant,
+    ) -> Option<(Duration, String)> {
+        if let Some(throttled) = self.check(email_address, current_instant) {
+            return Some(throttled);
+        }
+
+        let domain = get_email_domain(email_address)?;
+        let buckets = self.domain_buckets.get_mut(&domain)?;
+
+        for bucket in buckets.iter_mut() {
+            bucket.limiter.try_take(current_instant);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{QuotaLimit, RateLimiterKind};
+
+    fn create_throttle() -> DomainThrottle {
+        create_throttle_with_max(1)
+    }
+
+    fn create_throttle_with_max(max: usize) -> DomainThrottle {
+        create_throttle_with_limit(max, RateLimiterKind::SlidingWindow)
+    }
+
+    fn create_throttle_with_limit(max: usize, limiter_kind: RateLimiterKind) -> DomainThrottle {
+        let mut domain_limits = HashMap::new();
+        let per_second = QuotaLimit { max, limiter_kind };
+
+        domain_limits.insert(
+            "Gmail.com".to_string(),
+            DomainQuotaLimits {
+                max_per_second: Some(per_second),
+                max_per_minute: None,
+                max_per_hour: None,
+            },
+        );
+
+        DomainThrottle::new(&domain_limits, None)
+    }
+
+    #[test]
+    fn test_throttled_domain_does_not_block_other_domains() {
+        let mut throttle = create_throttle();
+        let current_instant = Instant::now();
+
+        assert!(throttle.try_take("first@gmail.com", &current_instant).is_none());
+        assert!(throttle.try_take("second@GMAIL.COM", &current_instant).is_some());
+        assert!(throttle.try_take("first@outlook.com", &current_instant).is_none());
+        assert!(throttle.try_take("second@outlook.com", &current_instant).is_none());
+    }
+
+    #[test]
+    fn test_throttled_domain_reports_wait_and_period() {
+        let mut throttle = create_throttle();
+        let current_instant = Instant::now();
+
+        throttle.try_take("first@gmail.com", &current_instant);
+
+        assert_eq!(
+            throttle.try_take("second@gmail.com", &current_instant),
+            Some((
+                Duration::from_secs(1),
+                "Exhausted maximum email per second to gmail.com!".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_carry_over_used_permits() {
+        let mut previous_throttle = create_throttle_with_max(3);
+        let current_instant = Instant::now();
+
+        previous_throttle.try_take("first@gmail.com", &current_instant);
+        previous_throttle.try_take("second@gmail.com", &current_instant);
+
+        let mut throttle = create_throttle_with_max(3);
+
+        throttle.carry_over(&previous_throttle, &current_instant);
+
+        assert!(throttle.try_take("third@gmail.com", &current_instant).is_none());
+        assert!(throttle.try_take("fourth@gmail.com", &current_instant).is_some());
+    }
+
+    #[test]
+    fn test_carry_over_fixed_window() {
+        let mut previous_throttle = create_throttle_with_limit(3, RateLimiterKind::FixedWindow);
+        let window_start = Instant::now();
+
+        previous_throttle.try_take("first@gmail.com", &window_start);
+        previous_throttle.try_take("second@gmail.com", &window_start);
+
+        // Taken before the new throttle is built, as on reload
+        let reload_instant = Instant::now();
+        let mut throttle = create_throttle_with_limit(3, RateLimiterKind::FixedWindow);
+
+        throttle.carry_over(&previous_throttle, &reload_instant);
+
+        assert!(throttle.try_take("third@gmail.com", &reload_instant).is_none());
+        assert!(throttle.try_take("fourth@gmail.com", &reload_instant).is_some());
+
+        let next_window = window_start + Duration::from_secs(1);
+
+        assert!(throttle.try_take("fourth@gmail.com", &next_window).is_none());
+    }
+
+    #[test]
+    fn test_throttled_period_does_not_charge_other_periods() {
+        let mut domain_limits = HashMap::new();
+        let create_limit = |max| QuotaLimit { max, limiter_kind: RateLimiterKind::SlidingWindow };
+
+        domain_limits.insert(
+            "gmail.com".to_string(),
+            DomainQuotaLimits {
+                max_per_second: Some(create_limit(3)),
+                max_per_minute: Some(create_limit(1)),
+                max_per_hour: None,
+            },
+        );
+
+        let mut throttle = DomainThrottle::new(&domain_limits, None);
+        let current_instant = Instant::now();
+
+        assert!(throttle.try_take("first@gmail.com", &current_instant).is_none());
+        assert!(throttle.try_take("second@gmail.com", &current_instant).is_some());
+        assert!(throttle.check("third@gmail.com", &current_instant).is_some());
+
+        let second_bucket = &throttle.domain_buckets["gmail.com"][0];
+
+        assert_eq!(second_bucket.limiter.remaining(&current_instant), 2);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config::DraftLimits;
+use crate::messages::MessageDraft;
+use std::sync::{Arc, RwLock};
+
+/// Clones share their limits, so a reload updates the consumers and the ingestion server at once.
+#[derive(Clone)]
+pub struct DraftValidator {
+    limits: Arc<RwLock<DraftLimits>>,
+}
+
+impl DraftValidator {
+    pub fn new(limits: &DraftLimits) -> Self {
+        Self { limits: Arc::new(RwLock::new(*limits)) }
+    }
+
+    pub fn set_limits(&self, limits: &DraftLimits) {
+        *self.limits.write().unwrap() = *limits;
+    }
+
+    pub fn max_message_bytes(&self) -> usize {
+        self.limits().max_message_bytes
+    }
+
+    /// Returns the reason the draft must be rejected, before any quota is taken for it.
+    pub fn validate(&self, draft: &MessageDraft) -> Result<(), String> {
+        let limits = self.limits();
+
+        if draft.has_empty_body() {
+            return Err("Empty body!".into());
+        }
+
+        if draft.has_empty_subject() {
+            return Err("Empty subject!".into());
+        }
+
+        check_header_value("subject", &draft.subject)?;
+        check_header_value("destination", &draft.email_to)?;
+        check_header_value("sender", &draft.email_from)?;
+
+        if let Some(email_to_name) = draft.email_to_name.as_ref() {
+            check_header_value("destination name", email_to_name)?;
+        }
+
+        if let Some(email_from_name) = draft.email_from_name.as_ref() {
+            check_header_value("sender name", email_from_name)?;
+        }
+
+        if draft.subject.chars().count() > limits.max_subject_length {
+            return Err(format!(
+                "Subject is longer than {} characters!",
+                limits.max_subject_length
+            ));
+        }
+
+        if draft.body.len() > limits.max_body_bytes {
+            return Err(format!("Body is larger than {} bytes!", limits.max_body_bytes));
+        }
+
+        if draft.smime_encrypt && draft.pgp_encrypt {
+            return Err("Cannot encrypt with both S/MIME and OpenPGP!".into());
+        }
+
+        if let Err(reason) = draft.parse_destination() {
+            return Err(format!("Invalid destination: {}", reason));
+        }
+
+        if let Err(reason) = draft.parse_sender() {
+            return Err(format!("Invalid sender: {}", reason));
+        }
+
+        Ok(())
+    }
+
+    /// Checked after composing, as encoding and headers add to the body size.
+    pub fn validate_message_size(&self, message_size: usize) -> Result<(), String> {
+        let max_message_bytes = self.max_message_bytes();
+
+        if message_size > max_message_bytes {
+            return Err(format!("Message is larger than {} bytes!", max_message_bytes));
+        }
+
+        Ok(())
+    }
+
+    fn limits(&self) -> DraftLimits {
+        *self.limits.read().unwrap()
+    }
+}
+
+fn check_header_value(field_name: &str, value: &str) -> Result<(), String> {
+    if value.contains(|c| c == '\r' || c == '\n') {
+        return Err(format!("Line break in {}!", field_name));
+    }
+
+    if value.contains(char::is_control) {
+        return Err(format!("Control character in {}!", field_name));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_validator() -> DraftValidator {
+        DraftValidator::new(&DraftLimits {
+            max_subject_length: 32,
+            max_body_bytes: 64,
+            max_message_bytes: 256,
+        })
+    }
+
+    #[test]
+    fn test_accept_valid_draft() {
+        assert_eq!(create_validator().validate(&MessageDraft::default()), Ok(()));
+    }
+
+    #[test]
+    fn test_reject_header_injection() {
+        let validator = create_validator();
+        let draft = MessageDraft {
+            subject: "Hello\r\nBcc: victim@example.com".into(),
+            ..MessageDraft::default()
+        };
+        assert_eq!(validator.validate(&draft), Err("Line break in subject!".into()));
+
+        let draft = MessageDraft {
+            email_to_name: Some("Admin\nBcc: victim@example.com".into()),
+            ..MessageDraft::default()
+        };
+        assert_eq!(validator.validate(&draft), Err("Line break in destination name!".into()));
+
+        let draft =
+            MessageDraft { email_from_name: Some("System\0".into()), ..MessageDraft::default() };
+        assert_eq!(validator.validate(&draft), Err("Control character in sender name!".into()));
+    }
+
+    #[test]
+    fn test_reject_invalid_address_with_reason() {
+        let validator = create_validator();
+        let draft = MessageDraft { email_to: "admin@gmail".into(), ..MessageDraft::default() };
+        assert_eq!(
+            validator.validate(&draft),
+            Err("Invalid destination: Domain is not fully qualified!".into())
+        );
+
+        let draft =
+            MessageDraft { email_from: "no reply@example.com".into(), ..MessageDraft::default() };
+        assert_eq!(
+            validator.validate(&draft),
+            Err("Invalid sender: Invalid character ' ' in local part!".into())
+        );
+    }
+
+    #[test]
+    fn test_reject_oversized_draft() {
+        let validator = create_validator();
+        let draft = MessageDraft { subject: "ä".repeat(33), ..MessageDraft::default() };
+        assert_eq!(validator.validate(&draft), Err("Subject is longer than 32 characters!".into()));
+
+        let draft = MessageDraft { subject: "ä".repeat(32), ..MessageDraft::default() };
+        assert_eq!(validator.validate(&draft), Ok(()));
+
+        let draft = MessageDraft { body: "a".repeat(65), ..MessageDraft::default() };
+        assert_eq!(validator.validate(&draft), Err("Body is larger than 64 bytes!".into()));
+
+        assert_eq!(
+            validator.validate_message_size(257),
+            Err("Message is larger than 256 bytes!".into())
+        );
+        assert_eq!(validator.validate_message_size(256), Ok(()));
+    }
+
+    #[test]
+    fn test_clones_share_limits() {
+        let validator = create_validator();
+        let shared_validator = validator.clone();
+        let draft = MessageDraft { body: "a".repeat(65), ..MessageDraft::default() };
+
+        validator.set_limits(&DraftLimits {
+            max_subject_length: 32,
+            max_body_bytes: 128,
+            max_message_bytes: 512,
+        });
+
+        assert_eq!(shared_validator.validate(&draft), Ok(()));
+        assert_eq!(shared_validator.max_message_bytes(), 512);
+    }
+}
@@ -0,0 +1,68 @@
+/// Splits a raw message into its header block (with the last CRLF) and its body.
+pub fn split_message(raw_email: &[u8]) -> (&[u8], &[u8]) {
+    match raw_email.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(position) => (&raw_email[..position + 2], &raw_email[position + 4..]),
+        None => (raw_email, &[]),
+    }
+}
+
+/// Returns every header as its name and its raw (still folded) value.
+pub fn parse_headers(header_block: &[u8]) -> Vec<(String, String)> {
+    let header_block = String::from_utf8_lossy(header_block);
+    let mut headers: Vec<(String, String)> = Vec::new();
+
+    for line in header_block.split("\r\n").filter(|line| !line.is_empty()) {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push_str("\r\n");
+                value.push_str(line);
+            }
+        } else if let Some(colon_index) = line.find(':') {
+            headers.push((line[..colon_index].into(), line[colon_index + 1..].into()));
+        }
+    }
+
+    headers
+}
+
+/// Splits a raw message into its message headers and its MIME entity (the `Content-*` headers
+/// and the body), so the entity can be wrapped. `MIME-Version` is left for the wrapper to set.
+pub fn split_entity(raw_email: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let (header_block, body) = split_message(raw_email);
+    let mut message_headers = Vec::new();
+    let mut entity = Vec::new();
+
+    for (name, value) in parse_headers(header_block) {
+        let header_line = format!("{}:{}\r\n", name, value).into_bytes();
+
+        if name.to_lowercase().starts_with("content-") {
+            entity.extend(header_line);
+        } else if !name.eq_ignore_ascii_case("MIME-Version") {
+            message_headers.extend(header_line);
+        }
+    }
+
+    entity.extend(b"\r\n");
+    entity.extend(body);
+
+    (message_headers, entity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_entity() {
+        let (message_headers, entity) = split_entity(
+            b"From: noreply@example.com\r\nMIME-Version: 1.0\r\nContent-Type: text/plain;\r\n \
+            charset=utf-8\r\nSubject: Test\r\n\r\nHello!!\r\n",
+        );
+
+        assert_eq!(message_headers, b"From: noreply@example.com\r\nSubject: Test\r\n".to_vec());
+        assert_eq!(
+            entity,
+            b"Content-Type: text/plain;\r\n charset=utf-8\r\n\r\nHello!!\r\n".to_vec()
+        );
+    }
+}
@@ -1,189 +1,470 @@
+mod calendar_bucket;
+mod delivery;
+mod dkim;
+mod domain_throttle;
+mod draft_validator;
+pub(crate) mod mime;
+mod mx_checker;
+mod pgp;
+mod quota_bucket;
+mod rate_limiter;
 mod resettable_bucket;
+mod sliding_window;
+mod smime;
+mod token_bucket;
 
-use crate::config::SmtpConfig;
+use crate::config::{
+    DeliveryConfig, DeliveryMode, MailerConfig, QuotaLimit, RateLimiterKind, SmtpConfig,
+    SmtpRelayConfig,
+};
+use crate::email_address::EmailAddress;
+use crate::logging::log_draft_outcome;
 use crate::messages::{
     MessageDraft, MessageDraftBodyType, MessageFail, MessageFailType, MessageSent,
+    QuotaBucketState, QuotaPeriod, SuppressionReason,
+};
+use crate::metrics::Metrics;
+use crate::suppression_list::{is_recipient_rejection, SuppressionList};
+use crate::telemetry::{SpanKind, TraceContext, Tracer};
+use crate::tracking::EngagementTracker;
+use crate::utils::{
+    get_email_domain, get_hostname, DAY_IN_SECONDS, HOUR_IN_SECONDS, MINUTE_IN_SECONDS,
 };
-use crate::utils::{DAY_IN_SECONDS, HOUR_IN_SECONDS, MINUTE_IN_SECONDS};
-use crate::{anyerror, AnyResult};
+use crate::{anyerror, info, warn, AnyResult};
+use delivery::{Delivery, DeliveryError, Redirect};
+use dkim::DkimSigner;
+use domain_throttle::DomainThrottle;
+pub(crate) use draft_validator::DraftValidator;
+use lettre::address::Envelope;
 use lettre::message::header::ContentType;
 use lettre::message::{Mailbox, SinglePart};
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::transport::smtp::authentication::Mechanism;
+use lettre::transport::smtp::client::{AsyncSmtpConnection, TlsParameters};
+use lettre::transport::smtp::commands::{Data, Mail, Rcpt};
+use lettre::transport::smtp::extension::{ClientId, Extension, MailParameter};
+use lettre::transport::smtp::Error as SmtpError;
 use lettre::{Address, AsyncSmtpTransport, Message as Email, Tokio02Connector, Tokio02Transport};
-use resettable_bucket::ResettableBucket;
+use log::Level;
+use mx_checker::MxChecker;
+use pgp::PgpEncryptor;
+use quota_bucket::QuotaBucket;
+use secstr::SecUtf8;
+use smime::SmimeComposer;
+use std::collections::HashMap;
+use std::fs::create_dir_all;
+use std::sync::Arc;
 use tapa_trait_serde::IJsonSerializable;
 use tokio::time::{Duration, Instant};
 
+const SMTP_PORT: u16 = 587;
+const SECOND: Duration = Duration::from_secs(1);
+const MINUTE: Duration = Duration::from_secs(MINUTE_IN_SECONDS);
+const HOUR: Duration = Duration::from_secs(HOUR_IN_SECONDS);
+const DAY: Duration = Duration::from_secs(DAY_IN_SECONDS);
+
 pub enum EmailSendingResult {
     Fail(MessageFail),
     Sent(MessageSent),
 }
 
-pub struct Mailer {
+/// A relay of `SMTP_ROUTES_*`, with the password its transport was built with.
+struct RoutedRelay {
+    relay_config: SmtpRelayConfig,
     transport: AsyncSmtpTransport<Tokio02Connector>,
-    bucket_second: Option<ResettableBucket>,
-    bucket_minute: Option<ResettableBucket>,
-    bucket_hour: Option<ResettableBucket>,
-    bucket_day: Option<ResettableBucket>,
+    transport_pass: SecUtf8,
+}
+
+impl RoutedRelay {
+    fn new(relay_config: &SmtpRelayConfig) -> AnyResult<Self> {
+        let transport_pass = relay_config.pass.get();
+
+        Ok(Self {
+            transport: create_transport(relay_config, &transport_pass)?,
+            transport_pass,
+            relay_config: relay_config.clone(),
+        })
+    }
+}
+
+pub struct Mailer {
+    delivery: Delivery,
+    transport_pass: SecUtf8,
+    /// Routed relays by name, empty for the sandbox deliveries.
+    routed_relays: HashMap<String, RoutedRelay>,
+    redirect: Option<Redirect>,
+    smtp_config: SmtpConfig,
+    bucket_second: Option<QuotaBucket>,
+    bucket_minute: Option<QuotaBucket>,
+    bucket_hour: Option<QuotaBucket>,
+    bucket_day: Option<QuotaBucket>,
+    domain_throttle: DomainThrottle,
+    draft_validator: DraftValidator,
+    dkim_signers: HashMap<String, DkimSigner>,
+    smime_composer: SmimeComposer,
+    pgp_encryptor: PgpEncryptor,
+    mx_checker: Option<MxChecker>,
+    suppression_list: SuppressionList,
+    verp_return_path: Option<EmailAddress>,
+    engagement_tracker: Option<EngagementTracker>,
+    metrics: Metrics,
+    tracer: Tracer,
 }
 
 impl Mailer {
-    pub fn new(smtp_config: &SmtpConfig) -> AnyResult<Self> {
-        let creds =
-            Credentials::new(smtp_config.user.clone(), smtp_config.pass.unsecure().to_string());
-        let mailer_build_result = if smtp_config.use_starttls {
-            AsyncSmtpTransport::<Tokio02Connector>::starttls_relay(&smtp_config.host)
-        } else {
-            AsyncSmtpTransport::<Tokio02Connector>::relay(&smtp_config.host)
+    pub async fn new(
+        config: &MailerConfig,
+        suppression_list: SuppressionList,
+        engagement_tracker: Option<EngagementTracker>,
+        metrics: Metrics,
+        tracer: Tracer,
+    ) -> AnyResult<Self> {
+        let smtp_config = &config.smtp_config;
+        let transport_pass = smtp_config.pass.get();
+        let delivery = create_delivery(&config.delivery_config, smtp_config, &transport_pass)?;
+        let routed_relays = create_routed_relays(&config.delivery_config, smtp_config)?;
+        let redirect = create_redirect(&config.delivery_config)?;
+        let bucket_second = create_bucket(smtp_config, smtp_config.max_per_second, SECOND);
+        let bucket_minute = create_bucket(smtp_config, smtp_config.max_per_minute, MINUTE);
+        let bucket_hour = create_bucket(smtp_config, smtp_config.max_per_hour, HOUR);
+        let bucket_day = create_bucket(smtp_config, smtp_config.max_per_day, DAY);
+        let mut mx_checker = None;
+
+        if config.dns_check_config.enabled {
+            mx_checker = Some(MxChecker::new(&config.dns_check_config).await?);
+        }
+
+        Ok(Self {
+            tracer,
+            metrics,
+            engagement_tracker,
+            verp_return_path: smtp_config.verp_return_path.clone(),
+            suppression_list,
+            mx_checker,
+            pgp_encryptor: PgpEncryptor::new(&config.pgp_config),
+            smime_composer: SmimeComposer::new(&config.smime_config),
+            dkim_signers: DkimSigner::from_config(&config.dkim_config),
+            draft_validator: DraftValidator::new(&config.draft_limits),
+            domain_throttle: DomainThrottle::new(
+                &smtp_config.domain_limits,
+                smtp_config.quota_timezone.as_ref(),
+            ),
+            bucket_day,
+            bucket_hour,
+            bucket_minute,
+            bucket_second,
+            delivery,
+            transport_pass,
+            routed_relays,
+            redirect,
+            smtp_config: smtp_config.clone(),
+        })
+    }
+
+    pub async fn compose_and_send(
+        &mut self,
+        origin_offset: Option<i64>,
+        service_instance_name: &str,
+        draft: MessageDraft,
+    ) -> EmailSendingResult {
+        let draft_id = draft.id;
+        let started_instant = Instant::now();
+        let producer_context = draft.trace_context.as_deref().and_then(TraceContext::parse);
+        let mut send_span =
+            self.tracer.start_span("send_draft", SpanKind::Internal, producer_context.as_ref());
+
+        send_span.set_attribute("draft.id", draft_id);
+
+        let trace_context = *send_span.context();
+        let mut email_sending_result = self
+            .try_compose_and_send(origin_offset, service_instance_name, draft, &trace_context)
+            .await;
+        let latency = started_instant.elapsed();
+
+        match &mut email_sending_result {
+            EmailSendingResult::Fail(message_fail) => {
+                let fail_reason = &message_fail.fail_reason;
+
+                self.metrics.record_failed(fail_reason);
+                log_draft_outcome(Level::Info, &draft_id, fail_reason.name(), latency);
+                send_span.set_error(fail_reason.name());
+                message_fail.trace_context = Some(trace_context.to_traceparent());
+            }
+            EmailSendingResult::Sent(message_sent) => {
+                self.metrics.record_sent();
+                log_draft_outcome(Level::Info, &draft_id, "SENT", latency);
+                message_sent.trace_context = Some(trace_context.to_traceparent());
+            }
+        }
+
+        self.update_quota_metrics(&Instant::now());
+
+        email_sending_result
+    }
+
+    /// Shares the current delivery, so its connection can be tested without holding the mailer
+    /// while the SMTP server answers.
+    pub fn delivery(&self) -> Delivery {
+        self.delivery.clone()
+    }
+
+    /// Applies the SMTP settings and draft limits of a reloaded `config`, the rest needs a
+    /// restart. Changed quotas keep the permits already used, and nothing is applied when a
+    /// new transport cannot be built.
+    pub fn reload(&mut self, config: &MailerConfig) -> AnyResult<()> {
+        let smtp_config = &config.smtp_config;
+        let transport_pass = smtp_config.pass.get();
+        let delivery = create_delivery(&config.delivery_config, smtp_config, &transport_pass)?;
+        let routed_relays = create_routed_relays(&config.delivery_config, smtp_config)?;
+        let redirect = create_redirect(&config.delivery_config)?;
+        let current_instant = Instant::now();
+        let quota_settings_changed = smtp_config.quota_timezone != self.smtp_config.quota_timezone
+            || smtp_config.priority_reserved_percent != self.smtp_config.priority_reserved_percent;
+        let periods = vec![
+            (
+                &mut self.bucket_second,
+                smtp_config.max_per_second,
+                self.smtp_config.max_per_second,
+                SECOND,
+            ),
+            (
+                &mut self.bucket_minute,
+                smtp_config.max_per_minute,
+                self.smtp_config.max_per_minute,
+                MINUTE,
+            ),
+            (&mut self.bucket_hour, smtp_config.max_per_hour, self.smtp_config.max_per_hour, HOUR),
+            (&mut self.bucket_day, smtp_config.max_per_day, self.smtp_config.max_per_day, DAY),
+        ];
+
+        for (bucket, quota_limit, previous_quota_limit, interval) in periods {
+            if quota_settings_changed || quota_limit != previous_quota_limit {
+                replace_bucket(bucket, smtp_config, quota_limit, interval, &current_instant);
+            }
+        }
+
+        if quota_settings_changed || smtp_config.domain_limits != self.smtp_config.domain_limits {
+            let mut domain_throttle = DomainThrottle::new(
+                &smtp_config.domain_limits,
+                smtp_config.quota_timezone.as_ref(),
+            );
+
+            domain_throttle.carry_over(&self.domain_throttle, &current_instant);
+            self.domain_throttle = domain_throttle;
+        }
+
+        self.delivery = delivery;
+        self.transport_pass = transport_pass;
+        self.routed_relays = routed_relays;
+        self.redirect = redirect;
+        self.verp_return_path = smtp_config.verp_return_path.clone();
+        self.draft_validator.set_limits(&config.draft_limits);
+        self.smtp_config = smtp_config.clone();
+        self.update_quota_metrics(&current_instant);
+
+        Ok(())
+    }
+
+    /// Sets the maximum of a global quota until the next reload, unlimited when `max` is
+    /// `None`. The limiter kind is kept, a quota that was unlimited gets a fixed window.
+    pub fn set_quota(&mut self, quota_period: QuotaPeriod, max: Option<usize>) {
+        let current_instant = Instant::now();
+        let (bucket, quota_limit, interval) = match quota_period {
+            QuotaPeriod::Second => {
+                (&mut self.bucket_second, &mut self.smtp_config.max_per_second, SECOND)
+            }
+            QuotaPeriod::Minute => {
+                (&mut self.bucket_minute, &mut self.smtp_config.max_per_minute, MINUTE)
+            }
+            QuotaPeriod::Hour => (&mut self.bucket_hour, &mut self.smtp_config.max_per_hour, HOUR),
+            QuotaPeriod::Day => (&mut self.bucket_day, &mut self.smtp_config.max_per_day, DAY),
         };
+        let limiter_kind = quota_limit
+            .map_or(RateLimiterKind::FixedWindow, |quota_limit| quota_limit.limiter_kind);
 
-        match mailer_build_result {
-            Err(build_error) => Err(anyerror!(build_error.to_string())),
-            Ok(mailer) => {
-                let mut bucket_second = None;
-                let mut bucket_minute = None;
-                let mut bucket_hour = None;
-                let mut bucket_day = None;
+        *quota_limit = max.map(|max| QuotaLimit { max, limiter_kind });
 
-                if let Some(mpt) = smtp_config.max_per_second.as_ref() {
-                    bucket_second = Some(ResettableBucket::new(*mpt, Duration::from_secs(1)));
-                }
+        let quota_limit = *quota_limit;
 
-                if let Some(mpt) = smtp_config.max_per_minute.as_ref() {
-                    bucket_minute =
-                        Some(ResettableBucket::new(*mpt, Duration::from_secs(MINUTE_IN_SECONDS)));
-                }
+        replace_bucket(bucket, &self.smtp_config, quota_limit, interval, &current_instant);
+        info!("Quota per {:?} set to {:?}", quota_period, quota_limit);
+        self.update_quota_metrics(&current_instant);
+    }
+
+    /// Shares the draft limits, so reloads apply to the ingestion server too.
+    pub fn draft_validator(&self) -> DraftValidator {
+        self.draft_validator.clone()
+    }
+
+    /// Maximum and remaining permits of every limited global quota.
+    pub fn quota_state(&self) -> Vec<QuotaBucketState> {
+        let current_instant = Instant::now();
+        let buckets = [
+            (QuotaPeriod::Second, &self.bucket_second),
+            (QuotaPeriod::Minute, &self.bucket_minute),
+            (QuotaPeriod::Hour, &self.bucket_hour),
+            (QuotaPeriod::Day, &self.bucket_day),
+        ];
+
+        buckets
+            .iter()
+            .filter_map(|(period, bucket)| {
+                bucket.as_ref().map(|bucket| QuotaBucketState {
+                    period: *period,
+                    max: bucket.max(),
+                    remaining: bucket.remaining(&current_instant),
+                })
+            })
+            .collect()
+    }
+
+    /// Rebuilds the transports once a rotated `SMTP_PASS_FILE` or `SMTP_RELAYS_<n>_PASS_FILE`
+    /// has been re-read.
+    fn refresh_transports(&mut self) {
+        if !self.delivery.is_smtp() {
+            return;
+        }
+
+        let pass = self.smtp_config.pass.get();
 
-                if let Some(mpt) = smtp_config.max_per_hour.as_ref() {
-                    bucket_hour =
-                        Some(ResettableBucket::new(*mpt, Duration::from_secs(HOUR_IN_SECONDS)));
+        if pass != self.transport_pass {
+            match create_transport(&self.smtp_config.default_relay(), &pass) {
+                Err(e) => {
+                    warn!("Cannot rebuild the SMTP transport with the rotated password: {}", e)
+                }
+                Ok(transport) => {
+                    info!("SMTP password rotated, transport rebuilt");
+                    self.delivery = Delivery::Smtp(Arc::new(transport));
+                    self.transport_pass = pass;
                 }
+            }
+        }
 
-                if let Some(mpt) = smtp_config.max_per_day.as_ref() {
-                    bucket_day =
-                        Some(ResettableBucket::new(*mpt, Duration::from_secs(DAY_IN_SECONDS)));
+        for routed_relay in self.routed_relays.values_mut() {
+            let relay_name = &routed_relay.relay_config.name;
+            let pass = routed_relay.relay_config.pass.get();
+
+            if pass == routed_relay.transport_pass {
+                continue;
+            }
+
+            match create_transport(&routed_relay.relay_config, &pass) {
+                Err(e) => warn!(
+                    "Cannot rebuild the transport of relay {} with the rotated password: {}",
+                    relay_name, e
+                ),
+                Ok(transport) => {
+                    info!("Password of relay {} rotated, transport rebuilt", relay_name);
+                    routed_relay.transport = transport;
+                    routed_relay.transport_pass = pass;
                 }
+            }
+        }
+    }
 
-                Ok(Self {
-                    bucket_day,
-                    bucket_hour,
-                    bucket_minute,
-                    bucket_second,
-                    transport: mailer
-                        .credentials(creds)
-                        .authentication(vec![Mechanism::Login])
-                        .port(587)
-                        .build(),
-                })
+    fn update_quota_metrics(&self, current_instant: &Instant) {
+        let buckets = [
+            ("second", &self.bucket_second),
+            ("minute", &self.bucket_minute),
+            ("hour", &self.bucket_hour),
+            ("day", &self.bucket_day),
+        ];
+
+        for (bucket_name, bucket) in buckets.iter() {
+            if let Some(bucket) = bucket {
+                self.metrics.set_quota_remaining(bucket_name, bucket.remaining(current_instant));
             }
         }
     }
 
-    pub async fn compose_and_send(
+    async fn try_compose_and_send(
         &mut self,
         origin_offset: Option<i64>,
         service_instance_name: &str,
         draft: MessageDraft,
+        trace_context: &TraceContext,
     ) -> EmailSendingResult {
-        let current_instant = Instant::now();
         let mut message_fail = MessageFail::new(
             origin_offset,
             service_instance_name,
             draft.to_json_string_pretty(),
             MessageFailType::Unknown,
         );
+        let validation_span =
+            self.tracer.start_span("validate", SpanKind::Internal, Some(trace_context));
 
-        if draft.has_empty_body() {
-            message_fail.fail_reason = MessageFailType::BadDraft("Empty body!".into());
-            return EmailSendingResult::Fail(message_fail);
-        }
-
-        if draft.has_empty_subject() {
-            message_fail.fail_reason = MessageFailType::BadDraft("Empty subject!".into());
+        if let Err(reason) = self.draft_validator.validate(&draft) {
+            message_fail.fail_reason = MessageFailType::BadDraft(reason);
             return EmailSendingResult::Fail(message_fail);
         }
 
-        if draft.has_invalid_destination() {
-            message_fail.fail_reason = MessageFailType::BadDraft("Invalid destination!".into());
-            return EmailSendingResult::Fail(message_fail);
-        }
-
-        if draft.has_invalid_sender() {
-            message_fail.fail_reason = MessageFailType::BadDraft("Invalid sender!".into());
-            return EmailSendingResult::Fail(message_fail);
-        }
-
-        // Check max per second bucket
-        if let Some(bucket) = self.bucket_second.as_mut() {
-            if let Some(duration_to_wait) = bucket.try_take(&current_instant) {
-                message_fail.fail_reason = MessageFailType::QuotaExhausted(
-                    duration_to_wait,
-                    "Exhausted maximum email per second!".into(),
-                );
-                return EmailSendingResult::Fail(message_fail);
-            }
-        }
-
-        // Check max per minute bucket
-        if let Some(bucket) = self.bucket_minute.as_mut() {
-            if let Some(duration_to_wait) = bucket.try_take(&current_instant) {
-                message_fail.fail_reason = MessageFailType::QuotaExhausted(
-                    duration_to_wait,
-                    "Exhausted maximum email per minute!".into(),
-                );
-                return EmailSendingResult::Fail(message_fail);
+        // Skip suppressed destinations, before spending any quota on them
+        if let Ok(destination) = draft.parse_destination() {
+            match self.suppression_list.get(&destination) {
+                Err(e) => {
+                    message_fail.fail_reason = MessageFailType::Other(e.to_string());
+                    return EmailSendingResult::Fail(message_fail);
+                }
+                Ok(Some(suppression_entry)) => {
+                    message_fail.fail_reason = MessageFailType::Suppressed(format!(
+                        "Destination {} is suppressed ({:?})!",
+                        destination, suppression_entry.reason
+                    ));
+                    return EmailSendingResult::Fail(message_fail);
+                }
+                Ok(None) => {}
             }
         }
 
-        // Check max per hour bucket
-        if let Some(bucket) = self.bucket_hour.as_mut() {
-            if let Some(duration_to_wait) = bucket.try_take(&current_instant) {
-                message_fail.fail_reason = MessageFailType::QuotaExhausted(
-                    duration_to_wait,
-                    "Exhausted maximum email per minute!".into(),
-                );
-                return EmailSendingResult::Fail(message_fail);
+        // Check the destination domain can receive email, before spending any quota on it
+        if let Some(mx_checker) = self.mx_checker.as_ref() {
+            if let Some(domain) = get_email_domain(&draft.email_to) {
+                if let Some(reason) = mx_checker.check(&domain).await {
+                    message_fail.fail_reason = MessageFailType::UndeliverableDomain(reason);
+                    return EmailSendingResult::Fail(message_fail);
+                }
             }
         }
 
-        // Check max per day bucket
-        if let Some(bucket) = self.bucket_day.as_mut() {
-            if let Some(duration_to_wait) = bucket.try_take(&current_instant) {
-                message_fail.fail_reason = MessageFailType::QuotaExhausted(
-                    duration_to_wait,
-                    "Exhausted maximum email per minute!".into(),
-                );
-                return EmailSendingResult::Fail(message_fail);
-            }
-        }
+        drop(validation_span);
 
+        let compose_span =
+            self.tracer.start_span("compose", SpanKind::Internal, Some(trace_context));
         let from_address;
 
-        match (&draft.email_from).parse::<Address>() {
-            Err(e) => {
-                message_fail.fail_reason = MessageFailType::BadDraft(e.to_string());
+        match draft.parse_sender().and_then(|address| to_lettre_address(&address)) {
+            Err(reason) => {
+                message_fail.fail_reason = MessageFailType::BadDraft(reason);
                 return EmailSendingResult::Fail(message_fail);
             }
             Ok(address) => from_address = Mailbox::new(draft.email_from_name.clone(), address),
         }
 
-        let to_address;
+        let mut to_address;
 
-        match (&draft.email_to).parse::<Address>() {
-            Err(e) => {
-                message_fail.fail_reason = MessageFailType::BadDraft(e.to_string());
+        match draft.parse_destination().and_then(|address| to_lettre_address(&address)) {
+            Err(reason) => {
+                message_fail.fail_reason = MessageFailType::BadDraft(reason);
                 return EmailSendingResult::Fail(message_fail);
             }
             Ok(address) => to_address = Mailbox::new(draft.email_to_name.clone(), address),
         }
 
-        let email_builder =
-            Email::builder().from(from_address).to(to_address).subject(draft.subject);
+        // Sandboxed emails go to the safe address, the destination is kept in a header below
+        if let Some(redirect) = self.redirect.as_ref() {
+            to_address = redirect.recipient(draft.email_to_name.clone());
+        }
+
+        let message_id = create_message_id(&draft);
+        let email_builder = Email::builder()
+            .from(from_address)
+            .to(to_address)
+            .subject(draft.subject.clone())
+            .message_id(Some(message_id.clone()));
         let email;
 
         match draft.body_type {
-            MessageDraftBodyType::Ascii => match email_builder.body(draft.body) {
+            MessageDraftBodyType::Ascii => match email_builder.body(draft.body.clone()) {
                 Err(e) => {
                     message_fail.fail_reason = MessageFailType::BadDraft(e.to_string());
                     return EmailSendingResult::Fail(message_fail);
@@ -191,7 +472,21 @@ impl Mailer {
                 Ok(valid_email) => email = valid_email,
             },
             MessageDraftBodyType::Html => {
-                let body = SinglePart::builder().header(ContentType::html()).body(draft.body);
+                let mut html = draft.body.clone();
+
+                if draft.track_engagement {
+                    match self.engagement_tracker.as_ref() {
+                        Some(engagement_tracker) => {
+                            html = engagement_tracker.instrument(&draft.id, &html)
+                        }
+                        None => warn!(
+                            "Tracking is not configured, draft {} is sent untracked",
+                            draft.id
+                        ),
+                    }
+                }
+
+                let body = SinglePart::builder().header(ContentType::html()).body(html);
 
                 match email_builder.singlepart(body) {
                     Err(e) => {
@@ -203,7 +498,190 @@ impl Mailer {
             }
         }
 
-        match self.transport.send(email).await {
+        let mut envelope = email.envelope().clone();
+
+        // Bounces go to a return path unique to the draft, so they can be matched back to it
+        if let Some(verp_return_path) = self.verp_return_path.as_ref() {
+            match create_verp_address(verp_return_path, &draft).and_then(|address| {
+                Envelope::new(Some(address), envelope.to().to_vec()).map_err(|e| e.to_string())
+            }) {
+                Err(reason) => {
+                    message_fail.fail_reason = MessageFailType::Other(reason);
+                    return EmailSendingResult::Fail(message_fail);
+                }
+                Ok(verp_envelope) => envelope = verp_envelope,
+            }
+        }
+
+        let mut raw_email = email.formatted();
+
+        if let Some(redirect) = self.redirect.as_ref() {
+            raw_email = redirect.add_original_recipient(&raw_email, &draft.email_to);
+        }
+
+        if draft.smime_sign || draft.smime_encrypt {
+            match self.smime_composer.compose(&draft, &raw_email) {
+                Err(reason) => {
+                    message_fail.fail_reason = MessageFailType::BadDraft(reason);
+                    return EmailSendingResult::Fail(message_fail);
+                }
+                Ok(smime_email) => raw_email = smime_email,
+            }
+        }
+
+        if draft.pgp_encrypt {
+            match self.pgp_encryptor.compose(&draft, &raw_email) {
+                Err(reason) => {
+                    message_fail.fail_reason = MessageFailType::BadDraft(reason);
+                    return EmailSendingResult::Fail(message_fail);
+                }
+                Ok(pgp_email) => raw_email = pgp_email,
+            }
+        }
+
+        if let Err(reason) = self.draft_validator.validate_message_size(raw_email.len()) {
+            message_fail.fail_reason = MessageFailType::BadDraft(reason);
+            return EmailSendingResult::Fail(message_fail);
+        }
+
+        if let Some(dkim_signer) =
+            get_email_domain(&draft.email_from).and_then(|domain| self.dkim_signers.get(&domain))
+        {
+            match dkim_signer.sign(&raw_email) {
+                Err(e) => {
+                    message_fail.fail_reason = MessageFailType::Other(e.to_string());
+                    return EmailSendingResult::Fail(message_fail);
+                }
+                Ok(signed_email) => raw_email = signed_email,
+            }
+        }
+
+        drop(compose_span);
+
+        // Taken after composing, so oversized or malformed messages spend no quota
+        let current_instant = Instant::now();
+        let mut quota_span =
+            self.tracer.start_span("quota", SpanKind::Internal, Some(trace_context));
+
+        // Check destination domain buckets, so a throttled draft does not spend the global quota
+        if let Some((duration_to_wait, error_string)) =
+            self.domain_throttle.check(&draft.email_to, &current_instant)
+        {
+            quota_span.set_attribute("quota.wait_ms", duration_to_wait.as_millis());
+            message_fail.fail_reason =
+                MessageFailType::DomainThrottled(duration_to_wait, error_string);
+            return EmailSendingResult::Fail(message_fail);
+        }
+
+        let mut buckets = [
+            ("second", &mut self.bucket_second),
+            ("minute", &mut self.bucket_minute),
+            ("hour", &mut self.bucket_hour),
+            ("day", &mut self.bucket_day),
+        ];
+
+        // Check every global bucket before taking from any, so a refusing one does not leak
+        // the permits of the others
+        for (bucket_name, bucket) in buckets.iter_mut() {
+            if let Some(bucket) = bucket {
+                if let Some(duration_to_wait) = bucket.check(draft.priority, &current_instant) {
+                    quota_span.set_attribute("quota.wait_ms", duration_to_wait.as_millis());
+                    message_fail.fail_reason = MessageFailType::QuotaExhausted(
+                        duration_to_wait,
+                        format!("Exhausted maximum email per {}!", bucket_name),
+                    );
+                    return EmailSendingResult::Fail(message_fail);
+                }
+            }
+        }
+
+        for (_, bucket) in buckets.iter_mut() {
+            if let Some(bucket) = bucket {
+                bucket.try_take(draft.priority, &current_instant);
+            }
+        }
+
+        // Take the destination domain permits last, once the global quota is granted
+        self.domain_throttle.try_take(&draft.email_to, &current_instant);
+
+        drop(quota_span);
+
+        let mut smtp_send_span =
+            self.tracer.start_span("smtp_send", SpanKind::Client, Some(trace_context));
+        self.refresh_transports();
+
+        // Routed by the domain the email is actually sent to, the safe address when redirected
+        let routed_relay = envelope
+            .to()
+            .first()
+            .and_then(|to_address| self.smtp_config.route(to_address.domain()))
+            .and_then(|relay_config| self.routed_relays.get(&relay_config.name));
+        let send_instant = Instant::now();
+        let send_result = match routed_relay {
+            Some(routed_relay) if needs_smtputf8(&envelope) => send_smtputf8(
+                &routed_relay.relay_config,
+                &routed_relay.transport_pass,
+                &envelope,
+                &raw_email,
+            )
+            .await
+            .map_err(DeliveryError::Smtp),
+            Some(routed_relay) => routed_relay
+                .transport
+                .send_raw(&envelope, &raw_email)
+                .await
+                .map(|_| ())
+                .map_err(DeliveryError::Smtp),
+            None if self.delivery.is_smtp() && needs_smtputf8(&envelope) => send_smtputf8(
+                &self.smtp_config.default_relay(),
+                &self.transport_pass,
+                &envelope,
+                &raw_email,
+            )
+            .await
+            .map_err(DeliveryError::Smtp),
+            None => self.delivery.send(&envelope, &raw_email, &draft.id).await,
+        };
+
+        // Sandbox writes would skew the relay latency
+        if self.delivery.is_smtp() {
+            self.metrics.observe_smtp_send(send_instant.elapsed());
+        }
+
+        if let Err(e) = send_result.as_ref() {
+            smtp_send_span.set_error(e);
+        }
+
+        drop(smtp_send_span);
+
+        match send_result {
+            // A rejected safe address says nothing about the destination
+            Err(DeliveryError::Smtp(SmtpError::Permanent(response)))
+                if self.redirect.is_none()
+                    && is_recipient_rejection(
+                        &response.code.to_string(),
+                        response.message.first().map_or("", String::as_str),
+                    ) =>
+            {
+                let rejection = format!("{} {}", response.code, response.message.join(" "));
+                let reason = format!(
+                    "Destination {} rejected permanently and suppressed: {}",
+                    draft.email_to, rejection
+                );
+
+                if let Ok(destination) = draft.parse_destination() {
+                    if let Err(e) = self.suppression_list.add(
+                        &destination,
+                        SuppressionReason::HardBounce,
+                        Some(rejection),
+                    ) {
+                        warn!("Cannot suppress {}: {}", destination, e);
+                    }
+                }
+
+                message_fail.fail_reason = MessageFailType::Suppressed(reason);
+                EmailSendingResult::Fail(message_fail)
+            }
             Err(e) => {
                 message_fail.fail_reason = MessageFailType::Other(e.to_string());
                 EmailSendingResult::Fail(message_fail)
@@ -212,7 +690,346 @@ impl Mailer {
                 origin_offset,
                 service_instance_name,
                 draft.id,
+                message_id,
             )),
         }
     }
 }
+
+fn create_bucket(
+    smtp_config: &SmtpConfig,
+    quota_limit: Option<QuotaLimit>,
+    interval: Duration,
+) -> Option<QuotaBucket> {
+    quota_limit.map(|quota_limit| {
+        QuotaBucket::new(
+            &quota_limit,
+            interval,
+            smtp_config.quota_timezone.as_ref(),
+            smtp_config.priority_reserved_percent,
+        )
+    })
+}
+
+/// Replaces `bucket` with one for `quota_limit`, charged with the permits `bucket` has used.
+fn replace_bucket(
+    bucket: &mut Option<QuotaBucket>,
+    smtp_config: &SmtpConfig,
+    quota_limit: Option<QuotaLimit>,
+    interval: Duration,
+    current_instant: &Instant,
+) {
+    let mut new_bucket = create_bucket(smtp_config, quota_limit, interval);
+
+    if let (Some(new_bucket), Some(previous_bucket)) = (new_bucket.as_mut(), bucket.as_ref()) {
+        new_bucket.carry_over(previous_bucket, current_instant);
+    }
+
+    *bucket = new_bucket;
+}
+
+fn create_delivery(
+    delivery_config: &DeliveryConfig,
+    smtp_config: &SmtpConfig,
+    pass: &SecUtf8,
+) -> AnyResult<Delivery> {
+    match delivery_config.mode {
+        DeliveryMode::Smtp | DeliveryMode::Redirect => {
+            Ok(Delivery::Smtp(Arc::new(create_transport(&smtp_config.default_relay(), pass)?)))
+        }
+        DeliveryMode::File => match create_dir_all(&delivery_config.file_dir) {
+            Err(e) => Err(anyerror!("Cannot create {}: {}", delivery_config.file_dir, e)),
+            Ok(_) => Ok(Delivery::File(delivery_config.file_dir.clone().into())),
+        },
+        DeliveryMode::Stdout => Ok(Delivery::Stdout),
+    }
+}
+
+/// The sandbox deliveries never connect to the routed relays either.
+fn create_routed_relays(
+    delivery_config: &DeliveryConfig,
+    smtp_config: &SmtpConfig,
+) -> AnyResult<HashMap<String, RoutedRelay>> {
+    if !delivery_config.mode.uses_smtp() {
+        return Ok(HashMap::new());
+    }
+
+    smtp_config
+        .relays
+        .iter()
+        .map(|relay_config| Ok((relay_config.name.clone(), RoutedRelay::new(relay_config)?)))
+        .collect()
+}
+
+fn create_redirect(delivery_config: &DeliveryConfig) -> AnyResult<Option<Redirect>> {
+    delivery_config
+        .redirect_to
+        .as_ref()
+        .map(|redirect_to| {
+            to_lettre_address(redirect_to).map(Redirect::new).map_err(|e| anyerror!(e))
+        })
+        .transpose()
+}
+
+fn create_transport(
+    relay_config: &SmtpRelayConfig,
+    pass: &SecUtf8,
+) -> AnyResult<AsyncSmtpTransport<Tokio02Connector>> {
+    let creds = Credentials::new(relay_config.user.clone(), pass.unsecure().to_string());
+    let transport_build_result = if relay_config.use_starttls {
+        AsyncSmtpTransport::<Tokio02Connector>::starttls_relay(&relay_config.host)
+    } else {
+        AsyncSmtpTransport::<Tokio02Connector>::relay(&relay_config.host)
+    };
+
+    match transport_build_result {
+        Err(build_error) => Err(anyerror!(build_error.to_string())),
+        Ok(transport) => Ok(transport
+            .credentials(creds)
+            .authentication(vec![Mechanism::Login])
+            .port(SMTP_PORT)
+            .build()),
+    }
+}
+
+/// Connects and authenticates like the transport does.
+async fn connect_relay(
+    relay_config: &SmtpRelayConfig,
+    pass: &SecUtf8,
+) -> Result<AsyncSmtpConnection, SmtpError> {
+    let hello_name = ClientId::Domain(get_hostname());
+    let tls_parameters = TlsParameters::new(relay_config.host.clone())?;
+    let mut connection = if relay_config.use_starttls {
+        let mut connection =
+            AsyncSmtpConnection::connect_tokio02(&relay_config.host, SMTP_PORT, &hello_name, None)
+                .await?;
+
+        connection.starttls(tls_parameters, &hello_name).await?;
+        connection
+    } else {
+        AsyncSmtpConnection::connect_tokio02(
+            &relay_config.host,
+            SMTP_PORT,
+            &hello_name,
+            Some(tls_parameters),
+        )
+        .await?
+    };
+    let creds = Credentials::new(relay_config.user.clone(), pass.unsecure().to_string());
+
+    connection.auth(&[Mechanism::Login], &creds).await?;
+
+    Ok(connection)
+}
+
+/// Returns the name and the EHLO capabilities of the relay.
+pub async fn test_smtp_relay(relay_config: &SmtpRelayConfig) -> AnyResult<String> {
+    let mut connection = connect_relay(relay_config, &relay_config.pass.get()).await?;
+    let server_info = connection.server_info().to_string();
+
+    connection.quit().await?;
+
+    Ok(server_info)
+}
+
+/// UTF-8 local parts can only be sent with SMTPUTF8 (RFC 6531).
+fn needs_smtputf8(envelope: &Envelope) -> bool {
+    envelope.from().into_iter().chain(envelope.to()).any(|address| !address.to_string().is_ascii())
+}
+
+/// Sends on a connection of its own, since the transport does not ask for SMTPUTF8 in
+/// `MAIL FROM`. Relays not advertising SMTPUTF8 are never sent the UTF-8 addresses.
+async fn send_smtputf8(
+    relay_config: &SmtpRelayConfig,
+    pass: &SecUtf8,
+    envelope: &Envelope,
+    raw_email: &[u8],
+) -> Result<(), SmtpError> {
+    let mut connection = connect_relay(relay_config, pass).await?;
+
+    if !connection.server_info().supports_feature(Extension::SmtpUtfEight) {
+        // The draft fails either way, a failed QUIT would only hide why
+        let _ = connection.quit().await;
+
+        return Err(SmtpError::Client("The SMTP relay does not support SMTPUTF8!"));
+    }
+
+    connection
+        .command(Mail::new(envelope.from().cloned(), vec![MailParameter::SmtpUtfEight]))
+        .await?;
+
+    for to_address in envelope.to() {
+        connection.command(Rcpt::new(to_address.clone(), Vec::new())).await?;
+    }
+
+    connection.command(Data).await?;
+    connection.message(raw_email).await?;
+
+    // The email is accepted once the message is, a failed QUIT does not change that
+    let _ = connection.quit().await;
+
+    Ok(())
+}
+
+/// Lettre gets the punycode domain, so IDN addresses do not depend on its own conversion.
+fn to_lettre_address(email_address: &EmailAddress) -> Result<Address, String> {
+    Address::new(email_address.local_part(), email_address.domain()).map_err(|e| e.to_string())
+}
+
+/// Stable for every retry of the draft, so replies and bounces can be matched back to it.
+fn create_message_id(draft: &MessageDraft) -> String {
+    let domain = get_email_domain(&draft.email_from).unwrap_or_else(get_hostname);
+
+    format!("<{}@{}>", draft.id, domain)
+}
+
+/// `bounce@example.com` becomes `bounce+<draft id>@example.com`.
+fn create_verp_address(
+    verp_return_path: &EmailAddress,
+    draft: &MessageDraft,
+) -> Result<Address, String> {
+    let local_part = format!("{}+{}", verp_return_path.local_part(), draft.id);
+
+    Address::new(local_part, verp_return_path.domain()).map_err(|e| e.to_string())
+}
+
+/// Delivers to files in `test_dir`, with `values` added to the config.
+#[cfg(test)]
+pub async fn create_file_mailer(test_dir: &std::path::Path, values: &[(&str, &str)]) -> Mailer {
+    let file_dir = test_dir.join("emails");
+    let suppression_db_path = test_dir.join("suppression");
+    let mut pairs = vec![
+        ("MQ_URL", "nats:4222"),
+        ("MQ_CONSUMER_GROUP", "MAILER"),
+        ("MQ_TOPIC_SOURCE", "mailer.draft"),
+        ("MQ_TOPIC_FAILURE", "mailer.fail"),
+        ("MQ_TOPIC_SUCCESS", "mailer.sent"),
+        ("MAILER_INSTANCE_NAME", "MAILER-TEST"),
+        ("DELIVERY_MODE", "FILE"),
+        ("DELIVERY_FILE_DIR", file_dir.to_str().unwrap()),
+        ("SUPPRESSION_DB_PATH", suppression_db_path.to_str().unwrap()),
+    ];
+
+    pairs.extend_from_slice(values);
+
+    let source = crate::config::ConfigSource::from_pairs(&pairs);
+    let config = MailerConfig::load(&source).unwrap();
+    let (tracer, _) = Tracer::new(None);
+
+    Mailer::new(
+        &config,
+        SuppressionList::open(&config.suppression_config).unwrap(),
+        None,
+        Metrics::new().unwrap(),
+        tracer,
+    )
+    .await
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::remove_dir_all;
+    use uuid::Uuid;
+
+    fn create_draft(email_from: &str) -> MessageDraft {
+        MessageDraft { email_from: email_from.into(), ..MessageDraft::default() }
+    }
+
+    #[test]
+    fn test_verp_address_carries_draft_id() {
+        let verp_return_path = EmailAddress::parse("bounce@example.com").unwrap();
+        let draft = create_draft("noreply@example.com");
+        let verp_address = create_verp_address(&verp_return_path, &draft).unwrap();
+
+        assert_eq!(verp_address.to_string(), format!("bounce+{}@example.com", draft.id));
+    }
+
+    #[test]
+    fn test_message_id_falls_back_to_hostname() {
+        let draft = create_draft("noreply@example.com");
+        let unparsable_draft = create_draft("noreply");
+
+        assert_eq!(create_message_id(&draft), format!("<{}@example.com>", draft.id));
+        assert_eq!(
+            create_message_id(&unparsable_draft),
+            format!("<{}@{}>", unparsable_draft.id, get_hostname())
+        );
+    }
+
+    #[test]
+    fn test_utf8_local_parts_need_smtputf8() {
+        let create_address = |address| to_lettre_address(&EmailAddress::parse(address).unwrap());
+        let ascii_address = create_address("admin@bücher.example").unwrap();
+        let utf8_address = create_address("josé@example.com").unwrap();
+        let ascii_envelope =
+            Envelope::new(Some(ascii_address.clone()), vec![ascii_address.clone()]).unwrap();
+        let utf8_envelope = Envelope::new(Some(ascii_address), vec![utf8_address]).unwrap();
+
+        assert!(!needs_smtputf8(&ascii_envelope));
+        assert!(needs_smtputf8(&utf8_envelope));
+    }
+
+    #[tokio::test]
+    async fn test_delivery_is_tested_without_holding_mailer() {
+        let test_dir = temp_dir().join(format!("mailer-{}", Uuid::new_v4()));
+        let mailer = tokio::sync::Mutex::new(create_file_mailer(&test_dir, &[]).await);
+        let delivery = mailer.lock().await.delivery();
+        // Held as by a consumer sending meanwhile
+        let held_mailer = mailer.lock().await;
+
+        assert!(delivery.is_reachable().await);
+        drop(held_mailer);
+        remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sent_message_carries_message_id() {
+        let test_dir = temp_dir().join(format!("mailer-{}", Uuid::new_v4()));
+        let file_dir = test_dir.join("emails");
+        let mut mailer =
+            create_file_mailer(&test_dir, &[("SMTP_VERP_RETURN_PATH", "bounce@example.com")]).await;
+        let draft = create_draft("noreply@example.com");
+        let draft_id = draft.id;
+        let email_sending_result = mailer.compose_and_send(None, "MAILER-TEST", draft).await;
+        let eml_written = file_dir.join(format!("{}.eml", draft_id)).exists();
+
+        drop(mailer);
+        remove_dir_all(&test_dir).unwrap();
+
+        match email_sending_result {
+            EmailSendingResult::Fail(message_fail) => {
+                panic!("Draft failed: {}", message_fail.fail_reason.name())
+            }
+            EmailSendingResult::Sent(message_sent) => {
+                assert_eq!(message_sent.draft_id, draft_id);
+                assert_eq!(message_sent.message_id, format!("<{}@example.com>", draft_id));
+                assert!(eml_written);
+            }
+        }
+    }
+    #[tokio::test]
+    async fn test_set_quota_keeps_used_permits_of_fixed_window() {
+        let test_dir = temp_dir().join(format!("mailer-{}", Uuid::new_v4()));
+        let mut mailer = create_file_mailer(&test_dir, &[("SMTP_MAX_PER_HOUR", "5")]).await;
+
+        for _ in 0..3 {
+            let draft = create_draft("noreply@example.com");
+
+            mailer.compose_and_send(None, "MAILER-TEST", draft).await;
+        }
+
+        mailer.set_quota(QuotaPeriod::Hour, Some(4));
+
+        let quota_state = mailer.quota_state();
+
+        drop(mailer);
+        remove_dir_all(&test_dir).unwrap();
+
+        assert_eq!(quota_state.len(), 1);
+        assert_eq!(quota_state[0].max, 4);
+        assert_eq!(quota_state[0].remaining, 1);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::config::DnsCheckConfig;
+use crate::{warn, AnyResult};
+use trust_dns_resolver::config::{
+    NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig, ResolverOpts,
+};
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::system_conf::read_system_conf;
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// Checks that a recipient domain can receive email before any quota is spent on it.
+pub struct MxChecker {
+    resolver: TokioAsyncResolver,
+}
+
+impl MxChecker {
+    pub async fn new(dns_check_config: &DnsCheckConfig) -> AnyResult<Self> {
+        let resolver_opts = ResolverOpts {
+            timeout: dns_check_config.timeout,
+            cache_size: dns_check_config.cache_size,
+            negative_min_ttl: Some(dns_check_config.negative_ttl),
+            ..ResolverOpts::default()
+        };
+        let resolver_config = if dns_check_config.nameservers.is_empty() {
+            let (system_config, _) = read_system_conf()?;
+
+            system_config
+        } else {
+            let mut nameserver_group = NameServerConfigGroup::new();
+
+            for socket_addr in dns_check_config.nameservers.iter() {
+                nameserver_group.push(NameServerConfig {
+                    socket_addr: *socket_addr,
+                    protocol: Protocol::Udp,
+                    tls_dns_name: None,
+                });
+            }
+
+            ResolverConfig::from_parts(None, vec![], nameserver_group)
+        };
+        let resolver = TokioAsyncResolver::tokio(resolver_config, resolver_opts).await?;
+
+        Ok(Self { resolver })
+    }
+
+    /// Returns the reason `domain` cannot receive email. Lookup failures such as timeouts are
+    /// logged and let the draft through, so a DNS outage does not stop all sending.
+    pub async fn check(&self, domain: &str) -> Option<String> {
+        // Address literals have nothing to resolve
+        if domain.starts_with('[') {
+            return None;
+        }
+
+        // Fully qualified, so no search domain is appended
+        let fqdn = format!("{}.", domain);
+
+        match self.resolver.mx_lookup(fqdn.as_str()).await {
+            Ok(mx_lookup) => {
+                // A single MX with the root as exchange declares no email is accepted (RFC 7505)
+                if mx_lookup.iter().all(|mx| mx.exchange().is_root()) {
+                    return Some(format!("Domain {} does not accept email (null MX)!", domain));
+                }
+
+                None
+            }
+            Err(e) if is_no_records(&e) => {
+                // Without MX records, email goes to the address records (RFC 5321 5.1)
+                match self.resolver.lookup_ip(fqdn.as_str()).await {
+                    Ok(_) => None,
+                    Err(e) if is_no_records(&e) => {
+                        Some(format!("Domain {} has no MX or address records!", domain))
+                    }
+                    Err(e) => {
+                        warn!("Cannot look up address records of {}: {}", domain, e);
+                        None
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Cannot look up MX records of {}: {}", domain, e);
+                None
+            }
+        }
+    }
+}
+
+fn is_no_records(resolve_error: &ResolveError) -> bool {
+    matches!(resolve_error.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::thread::spawn;
+    use tokio::time::Duration;
+    use trust_dns_resolver::proto::op::{Message, MessageType, ResponseCode};
+    use trust_dns_resolver::proto::rr::rdata::MX;
+    use trust_dns_resolver::proto::rr::{Name, RData, Record, RecordType};
+
+    /// Answers queries from `records`, with NXDOMAIN for unknown names.
+    fn spawn_dns_stub(records: HashMap<(&'static str, RecordType), RData>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket_addr = socket.local_addr().unwrap();
+
+        spawn(move || loop {
+            let mut buffer = [0; 512];
+            let (size, peer_addr) = socket.recv_from(&mut buffer).unwrap();
+            let request = Message::from_vec(&buffer[..size]).unwrap();
+            let query = request.queries()[0].clone();
+            let name = query.name().to_ascii();
+            let mut response = Message::new();
+            response
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .set_recursion_desired(true)
+                .set_recursion_available(true)
+                .add_query(query.clone());
+
+            if let Some(rdata) = records.get(&(name.as_str(), query.query_type())) {
+                response.add_answer(Record::from_rdata(query.name().clone(), 60, rdata.clone()));
+            } else if !records.keys().any(|(record_name, _)| *record_name == name) {
+                response.set_response_code(ResponseCode::NXDomain);
+            }
+
+            socket.send_to(&response.to_vec().unwrap(), peer_addr).unwrap();
+        });
+
+        socket_addr
+    }
+
+    async fn create_checker(nameserver: SocketAddr) -> MxChecker {
+        MxChecker::new(&DnsCheckConfig {
+            enabled: true,
+            nameservers: vec![nameserver],
+            timeout: Duration::from_millis(200),
+            cache_size: 16,
+            negative_ttl: Duration::from_secs(60),
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check_recipient_domains() {
+        let mut records = HashMap::new();
+        let mail_exchange = Name::from_ascii("mail.example.com.").unwrap();
+        records.insert(("example.com.", RecordType::MX), RData::MX(MX::new(10, mail_exchange)));
+        records.insert(("null.example.com.", RecordType::MX), RData::MX(MX::new(0, Name::root())));
+        records.insert(("host.example.com.", RecordType::A), RData::A([192, 0, 2, 1].into()));
+        let checker = create_checker(spawn_dns_stub(records)).await;
+
+        assert_eq!(checker.check("example.com").await, None);
+        assert_eq!(checker.check("host.example.com").await, None);
+        assert_eq!(checker.check("[192.0.2.1]").await, None);
+        assert_eq!(
+            checker.check("null.example.com").await,
+            Some("Domain null.example.com does not accept email (null MX)!".into())
+        );
+        assert_eq!(
+            checker.check("gmial.com").await,
+            Some("Domain gmial.com has no MX or address records!".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_resolver_lets_draft_through() {
+        // Bound but never answering
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let checker = create_checker(socket.local_addr().unwrap()).await;
+
+        assert_eq!(checker.check("example.com").await, None);
+    }
+}
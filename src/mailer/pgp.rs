@@ -0,0 +1,1183 @@
+use super::mime::split_entity;
+use crate::config::PgpConfig;
+use crate::messages::MessageDraft;
+use crate::warn;
+use openssl::aes::{wrap_key, AesKey};
+use openssl::base64::{decode_block, encode_block};
+use openssl::bn::BigNum;
+use openssl::derive::Deriver;
+use openssl::hash::{hash, Hasher, MessageDigest};
+use openssl::pkey::{PKey, Public};
+use openssl::rand::rand_bytes;
+use openssl::rsa::{Padding, Rsa};
+use openssl::sha::sha1;
+use openssl::sign::Verifier;
+use openssl::symm::{encrypt, Cipher};
+use std::fs::read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const TAG_PUBLIC_KEY_ENCRYPTED_SESSION_KEY: u8 = 1;
+const TAG_SIGNATURE: u8 = 2;
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_LITERAL_DATA: u8 = 11;
+const TAG_USER_ID: u8 = 13;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+const TAG_SYM_ENCRYPTED_INTEGRITY_PROTECTED_DATA: u8 = 18;
+const TAG_MODIFICATION_DETECTION_CODE: u8 = 19;
+const ALGORITHM_RSA: u8 = 1;
+const ALGORITHM_RSA_ENCRYPT_ONLY: u8 = 2;
+const ALGORITHM_RSA_SIGN_ONLY: u8 = 3;
+const ALGORITHM_ECDH: u8 = 18;
+const ALGORITHM_EDDSA: u8 = 22;
+const ALGORITHM_AES_128: u8 = 7;
+const ALGORITHM_AES_192: u8 = 8;
+const ALGORITHM_AES_256: u8 = 9;
+const OID_ED25519: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0xDA, 0x47, 0x0F, 0x01];
+const OID_CV25519: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0x97, 0x55, 0x01, 0x05, 0x01];
+/// DER prefixes of the Ed25519 and X25519 subject public key infos (RFC 8410), followed by the
+/// raw 32 octet key.
+const DER_PREFIX_ED25519: &[u8] =
+    &[0x30, 0x2A, 0x30, 0x05, 0x06, 0x03, 0x2B, 0x65, 0x70, 0x03, 0x21, 0x00];
+const DER_PREFIX_X25519: &[u8] =
+    &[0x30, 0x2A, 0x30, 0x05, 0x06, 0x03, 0x2B, 0x65, 0x6E, 0x03, 0x21, 0x00];
+const SIGNATURE_POSITIVE_CERTIFICATION: u8 = 0x13;
+const SIGNATURE_GENERIC_CERTIFICATION: u8 = 0x10;
+const SIGNATURE_SUBKEY_BINDING: u8 = 0x18;
+const SIGNATURE_DIRECT_KEY: u8 = 0x1F;
+const SIGNATURE_KEY_REVOCATION: u8 = 0x20;
+const SIGNATURE_SUBKEY_REVOCATION: u8 = 0x28;
+const SUBPACKET_SIGNATURE_CREATION_TIME: u8 = 2;
+const SUBPACKET_SIGNATURE_EXPIRATION_TIME: u8 = 3;
+const SUBPACKET_KEY_EXPIRATION_TIME: u8 = 9;
+const SUBPACKET_KEY_FLAGS: u8 = 27;
+const KEY_FLAGS_ENCRYPT: u8 = 0x04 | 0x08;
+const KEY_FILE_EXTENSIONS: [&str; 3] = ["asc", "gpg", "pgp"];
+
+/// Public key of a recipient, parsed from an OpenPGP transferable public key (RFC 4880). Keys are
+/// RSA, or Curve25519 as made by gpg: an Ed25519 primary key with a Cv25519 subkey (RFC 6637).
+pub struct PgpRecipientKey {
+    fingerprint: [u8; 20],
+    key_id: [u8; 8],
+    key_material: KeyMaterial,
+}
+
+enum KeyMaterial {
+    Rsa(Rsa<Public>),
+    Ed25519(PKey<Public>),
+    /// X25519 key, with the KDF hash and the key wrap algorithm of its session keys.
+    Cv25519 {
+        public_key: PKey<Public>,
+        kdf_hash: u8,
+        key_wrap_algorithm: u8,
+    },
+}
+
+impl PgpRecipientKey {
+    /// Encrypts to the newest subkey flagged for encryption with a valid binding signature, not
+    /// revoked nor expired, or to the primary key when no subkey qualifies.
+    pub fn parse(key_data: &[u8]) -> Result<Self, String> {
+        let current_time =
+            SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+
+        Self::parse_at(key_data, current_time)
+    }
+
+    fn parse_at(key_data: &[u8], current_time: u64) -> Result<Self, String> {
+        let key_data = if key_data.starts_with(b"-----BEGIN") {
+            dearmor(key_data)?
+        } else {
+            key_data.to_vec()
+        };
+        let packets = parse_packets(&key_data)?;
+        let primary_body = match packets.first() {
+            Some((TAG_PUBLIC_KEY, body)) => *body,
+            _ => return Err("Missing primary key!".into()),
+        };
+        let primary_key =
+            parse_public_key(primary_body).ok_or_else(|| "Unsupported primary key!".to_string())?;
+        let primary_packet = hash_key_packet(primary_body);
+        let mut primary_signature: Option<Signature> = None;
+        let mut primary_revoked = false;
+        let mut subkeys: Vec<Subkey> = Vec::new();
+        let mut user_id = None;
+
+        for (tag, body) in packets[1..].iter() {
+            match *tag {
+                TAG_USER_ID => user_id = Some(*body),
+                TAG_PUBLIC_SUBKEY => {
+                    user_id = None;
+                    subkeys.push(Subkey { body, binding_signature: None, revoked: false });
+                }
+                TAG_SIGNATURE => {
+                    let signature = match Signature::parse(body) {
+                        Some(signature) => signature,
+                        None => continue,
+                    };
+
+                    match (signature.signature_type, subkeys.last_mut(), user_id) {
+                        (SIGNATURE_KEY_REVOCATION, None, _) => {
+                            primary_revoked |=
+                                signature.verify(&primary_key, &[&primary_packet], current_time);
+                        }
+                        (SIGNATURE_DIRECT_KEY, None, None) => {
+                            if signature.verify(&primary_key, &[&primary_packet], current_time) {
+                                primary_signature = Some(signature.newest(primary_signature));
+                            }
+                        }
+                        (
+                            SIGNATURE_GENERIC_CERTIFICATION..=SIGNATURE_POSITIVE_CERTIFICATION,
+                            None,
+                            Some(user_id),
+                        ) => {
+                            let mut user_id_packet = vec![0xB4];
+                            user_id_packet.extend(&(user_id.len() as u32).to_be_bytes());
+                            user_id_packet.extend(user_id);
+
+                            if signature.verify(
+                                &primary_key,
+                                &[&primary_packet, &user_id_packet],
+                                current_time,
+                            ) {
+                                primary_signature = Some(signature.newest(primary_signature));
+                            }
+                        }
+                        (SIGNATURE_SUBKEY_BINDING, Some(subkey), _) => {
+                            let subkey_packet = hash_key_packet(subkey.body);
+
+                            if signature.verify(
+                                &primary_key,
+                                &[&primary_packet, &subkey_packet],
+                                current_time,
+                            ) {
+                                subkey.binding_signature =
+                                    Some(signature.newest(subkey.binding_signature.take()));
+                            }
+                        }
+                        (SIGNATURE_SUBKEY_REVOCATION, Some(subkey), _) => {
+                            let subkey_packet = hash_key_packet(subkey.body);
+
+                            subkey.revoked |= signature.verify(
+                                &primary_key,
+                                &[&primary_packet, &subkey_packet],
+                                current_time,
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+                // Signatures of user attributes and unknown packets are not needed
+                _ => user_id = None,
+            }
+        }
+
+        let primary_signature = match primary_signature {
+            None => return Err("No valid self-signature on the primary key!".into()),
+            Some(primary_signature) => primary_signature,
+        };
+
+        if primary_revoked {
+            return Err("Primary key is revoked!".into());
+        }
+
+        if primary_signature.is_key_expired(primary_body, current_time) {
+            return Err("Primary key is expired!".into());
+        }
+
+        let newest_subkey = subkeys
+            .iter()
+            .filter(|subkey| !subkey.revoked)
+            .filter_map(|subkey| {
+                let binding_signature = subkey.binding_signature.as_ref()?;
+
+                if !binding_signature.allows_encryption(subkey.body)
+                    || binding_signature.is_key_expired(subkey.body, current_time)
+                {
+                    return None;
+                }
+
+                Some((key_creation_time(subkey.body), parse_public_key(subkey.body)?))
+            })
+            .max_by_key(|(creation_time, _)| *creation_time);
+
+        match newest_subkey {
+            Some((_, subkey)) => Ok(subkey),
+            None if primary_signature.allows_encryption(primary_body) => Ok(primary_key),
+            None => Err("No valid encryption key found!".into()),
+        }
+    }
+}
+
+struct Subkey<'a> {
+    body: &'a [u8],
+    binding_signature: Option<Signature<'a>>,
+    revoked: bool,
+}
+
+/// Version 4 RSA or EdDSA signature, with the hashed subpackets the key selection needs.
+struct Signature<'a> {
+    signature_type: u8,
+    algorithm: u8,
+    hash_algorithm: u8,
+    hashed_data: &'a [u8],
+    /// The RSA signature MPI, or the EdDSA R and S MPIs.
+    values: Vec<&'a [u8]>,
+    creation_time: u64,
+    expiration_time: Option<u64>,
+    key_expiration_time: Option<u64>,
+    key_flags: Option<u8>,
+}
+
+impl<'a> Signature<'a> {
+    fn parse(body: &'a [u8]) -> Option<Self> {
+        if body.len() < 6 || body[0] != 4 {
+            return None;
+        }
+
+        let hashed_end = 6 + read_length(&body[4..], 2)?;
+        let unhashed_end = hashed_end + 2 + read_length(body.get(hashed_end..)?, 2)?;
+        // The left 16 bits of the hash come before the signature MPIs
+        let (value, remaining) = parse_mpi(body.get(unhashed_end + 2..)?)?;
+        let values = match body[2] {
+            ALGORITHM_RSA | ALGORITHM_RSA_SIGN_ONLY => vec![value],
+            ALGORITHM_EDDSA => vec![value, parse_mpi(remaining)?.0],
+            _ => return None,
+        };
+        let hashed_data = body.get(..hashed_end)?;
+        let mut signature = Self {
+            signature_type: body[1],
+            algorithm: body[2],
+            hash_algorithm: body[3],
+            hashed_data,
+            values,
+            creation_time: 0,
+            expiration_time: None,
+            key_expiration_time: None,
+            key_flags: None,
+        };
+        let mut subpackets = &hashed_data[6..];
+
+        while !subpackets.is_empty() {
+            let (length_size, length) = match subpackets[0] {
+                length if length < 192 => (1, Some(length as usize)),
+                length if length < 255 => (
+                    2,
+                    subpackets
+                        .get(1)
+                        .map(|next| ((length as usize - 192) << 8) + *next as usize + 192),
+                ),
+                _ => (5, read_length(&subpackets[1..], 4)),
+            };
+            let subpacket = subpackets.get(length_size..length_size + length?)?;
+            let (subpacket_type, data) = subpacket.split_first()?;
+
+            match subpacket_type & 0x7F {
+                SUBPACKET_SIGNATURE_CREATION_TIME => {
+                    signature.creation_time = read_length(data, 4)? as u64;
+                }
+                SUBPACKET_SIGNATURE_EXPIRATION_TIME => {
+                    signature.expiration_time = Some(read_length(data, 4)? as u64);
+                }
+                SUBPACKET_KEY_EXPIRATION_TIME => {
+                    signature.key_expiration_time = Some(read_length(data, 4)? as u64);
+                }
+                SUBPACKET_KEY_FLAGS => signature.key_flags = data.first().copied(),
+                _ => {}
+            }
+
+            subpackets = &subpackets[length_size + length?..];
+        }
+
+        Some(signature)
+    }
+
+    /// Checks the signature over `signed_packets` made by `signer`, and that it has not expired.
+    fn verify(
+        &self,
+        signer: &PgpRecipientKey,
+        signed_packets: &[&[u8]],
+        current_time: u64,
+    ) -> bool {
+        let is_expired = match self.expiration_time {
+            Some(expiration_time) if expiration_time > 0 => {
+                self.creation_time + expiration_time <= current_time
+            }
+            _ => false,
+        };
+        let message_digest = match self.hash_algorithm {
+            2 => MessageDigest::sha1(),
+            8 => MessageDigest::sha256(),
+            9 => MessageDigest::sha384(),
+            10 => MessageDigest::sha512(),
+            11 => MessageDigest::sha224(),
+            _ => return false,
+        };
+
+        if is_expired || self.creation_time > current_time {
+            return false;
+        }
+
+        let mut trailer = vec![4, 0xFF];
+        trailer.extend(&(self.hashed_data.len() as u32).to_be_bytes());
+
+        let verify_result = match (&signer.key_material, self.algorithm) {
+            (KeyMaterial::Rsa(public_key), ALGORITHM_RSA)
+            | (KeyMaterial::Rsa(public_key), ALGORITHM_RSA_SIGN_ONLY) => {
+                // The signature MPI drops leading zeros, which PKCS#1 needs back
+                let value = match pad_value(self.values[0], public_key.size() as usize) {
+                    None => return false,
+                    Some(value) => value,
+                };
+
+                PKey::from_rsa(public_key.clone()).and_then(|signer_key| {
+                    let mut verifier = Verifier::new(message_digest, &signer_key)?;
+
+                    for signed_packet in signed_packets {
+                        verifier.update(signed_packet)?;
+                    }
+
+                    verifier.update(self.hashed_data)?;
+                    verifier.update(&trailer)?;
+                    verifier.verify(&value)
+                })
+            }
+            (KeyMaterial::Ed25519(public_key), ALGORITHM_EDDSA) => {
+                // EdDSA signs the digest, its R and S MPIs drop leading zeros too
+                let value = match (pad_value(self.values[0], 32), pad_value(self.values[1], 32)) {
+                    (Some(mut value), Some(s)) => {
+                        value.extend(s);
+                        value
+                    }
+                    _ => return false,
+                };
+
+                Hasher::new(message_digest).and_then(|mut hasher| {
+                    for signed_packet in signed_packets {
+                        hasher.update(signed_packet)?;
+                    }
+
+                    hasher.update(self.hashed_data)?;
+                    hasher.update(&trailer)?;
+
+                    let digest = hasher.finish()?;
+
+                    Verifier::new_without_digest(public_key)?.verify_oneshot(&value, &digest)
+                })
+            }
+            _ => return false,
+        };
+
+        verify_result.unwrap_or(false)
+    }
+
+    fn newest(self, other: Option<Self>) -> Self {
+        match other {
+            Some(other) if other.creation_time > self.creation_time => other,
+            _ => self,
+        }
+    }
+
+    /// Keys without key flags may encrypt when their algorithm can.
+    fn allows_encryption(&self, key_body: &[u8]) -> bool {
+        matches!(
+            key_body.get(5).copied(),
+            Some(ALGORITHM_RSA) | Some(ALGORITHM_RSA_ENCRYPT_ONLY) | Some(ALGORITHM_ECDH)
+        ) && self.key_flags.map_or(true, |key_flags| key_flags & KEY_FLAGS_ENCRYPT != 0)
+    }
+
+    fn is_key_expired(&self, key_body: &[u8], current_time: u64) -> bool {
+        match self.key_expiration_time {
+            Some(key_expiration_time) if key_expiration_time > 0 => {
+                key_creation_time(key_body) + key_expiration_time <= current_time
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Wraps composed messages into PGP/MIME encrypted entities (RFC 3156).
+pub struct PgpEncryptor {
+    keyring_dir: Option<String>,
+}
+
+impl PgpEncryptor {
+    pub fn new(pgp_config: &PgpConfig) -> Self {
+        Self { keyring_dir: pgp_config.keyring_dir.clone() }
+    }
+
+    /// Drafts allowing plaintext are sent unencrypted when the recipient has no key.
+    pub fn compose(&self, draft: &MessageDraft, raw_email: &[u8]) -> Result<Vec<u8>, String> {
+        let recipient = draft.email_to.to_lowercase();
+        let recipient_key = match self.find_recipient_key(&recipient) {
+            Some(key_data) => match PgpRecipientKey::parse(&key_data) {
+                Err(reason) => {
+                    return Err(format!("Invalid OpenPGP key for {}: {}", recipient, reason))
+                }
+                Ok(recipient_key) => recipient_key,
+            },
+            None if draft.pgp_allow_plaintext => {
+                warn!("No OpenPGP key for the recipient of {}, sending it as plaintext", draft.id);
+                return Ok(raw_email.to_vec());
+            }
+            None => return Err(format!("No OpenPGP key for {}!", recipient)),
+        };
+        let (mut pgp_email, entity) = split_entity(raw_email);
+        let encrypted_entity = encrypt_message(&entity, &recipient_key)
+            .map_err(|e| format!("Cannot encrypt OpenPGP message: {}", e))?;
+        let boundary = format!("pgp-{}", Uuid::new_v4().to_simple());
+
+        pgp_email.extend(
+            format!(
+                "MIME-Version: 1.0\r\n\
+                Content-Type: multipart/encrypted; protocol=\"application/pgp-encrypted\";\r\n\
+                \tboundary=\"{boundary}\"\r\n\
+                \r\n\
+                --{boundary}\r\n\
+                Content-Type: application/pgp-encrypted\r\n\
+                Content-Description: PGP/MIME version identification\r\n\
+                \r\n\
+                Version: 1\r\n\
+                \r\n\
+                --{boundary}\r\n\
+                Content-Type: application/octet-stream; name=\"encrypted.asc\"\r\n\
+                Content-Description: OpenPGP encrypted message\r\n\
+                Content-Disposition: inline; filename=\"encrypted.asc\"\r\n\
+                \r\n\
+                {encrypted_entity}\r\n\
+                --{boundary}--\r\n",
+                boundary = boundary,
+                encrypted_entity = armor("PGP MESSAGE", &encrypted_entity),
+            )
+            .into_bytes(),
+        );
+
+        Ok(pgp_email)
+    }
+
+    /// Looks up `<keyring dir>/<recipient>.asc`, `.gpg` or `.pgp`.
+    fn find_recipient_key(&self, recipient: &str) -> Option<Vec<u8>> {
+        let keyring_dir = self.keyring_dir.as_ref()?;
+
+        if recipient.contains(|c| c == '/' || c == '\\') {
+            return None;
+        }
+
+        KEY_FILE_EXTENSIONS.iter().find_map(|extension| {
+            read(Path::new(keyring_dir).join(format!("{}.{}", recipient, extension))).ok()
+        })
+    }
+}
+
+fn parse_public_key(body: &[u8]) -> Option<PgpRecipientKey> {
+    // Only version 4 keys: version, creation time, algorithm, then the key material
+    if body.len() < 6 || body[0] != 4 {
+        return None;
+    }
+
+    let key_material = match body[5] {
+        ALGORITHM_RSA | ALGORITHM_RSA_ENCRYPT_ONLY | ALGORITHM_RSA_SIGN_ONLY => {
+            let (n, remaining) = parse_mpi(&body[6..])?;
+            let (e, _) = parse_mpi(remaining)?;
+            let n = BigNum::from_slice(n).ok()?;
+            let e = BigNum::from_slice(e).ok()?;
+
+            KeyMaterial::Rsa(Rsa::from_public_components(n, e).ok()?)
+        }
+        ALGORITHM_EDDSA => {
+            let (point, _) = parse_curve_point(&body[6..], OID_ED25519)?;
+
+            KeyMaterial::Ed25519(parse_raw_public_key(DER_PREFIX_ED25519, point)?)
+        }
+        ALGORITHM_ECDH => {
+            let (point, remaining) = parse_curve_point(&body[6..], OID_CV25519)?;
+
+            // KDF parameters: their size, a reserved octet, the hash and the key wrap algorithm
+            match remaining.get(..4)? {
+                [3, 1, kdf_hash, key_wrap_algorithm] => KeyMaterial::Cv25519 {
+                    public_key: parse_raw_public_key(DER_PREFIX_X25519, point)?,
+                    kdf_hash: *kdf_hash,
+                    key_wrap_algorithm: *key_wrap_algorithm,
+                },
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    let fingerprint = sha1(&hash_key_packet(body));
+    let mut key_id = [0; 8];
+    key_id.copy_from_slice(&fingerprint[12..]);
+
+    Some(PgpRecipientKey { fingerprint, key_id, key_material })
+}
+
+/// Parses the curve OID, which must be `oid`, and the native point MPI prefixed with 0x40 that
+/// follows it. Returns the 32 octet point and the remaining data.
+fn parse_curve_point<'a>(data: &'a [u8], oid: &[u8]) -> Option<(&'a [u8], &'a [u8])> {
+    let (oid_size, data) = data.split_first()?;
+
+    if data.get(..*oid_size as usize)? != oid {
+        return None;
+    }
+
+    match parse_mpi(&data[*oid_size as usize..])? {
+        (point, remaining) if point.len() == 33 && point[0] == 0x40 => {
+            Some((&point[1..], remaining))
+        }
+        _ => None,
+    }
+}
+
+fn parse_raw_public_key(der_prefix: &[u8], raw_key: &[u8]) -> Option<PKey<Public>> {
+    let mut der = der_prefix.to_vec();
+    der.extend(raw_key);
+
+    PKey::public_key_from_der(&der).ok()
+}
+
+/// Left pads an MPI value with the zeros it dropped, up to `size` octets.
+fn pad_value(value: &[u8], size: usize) -> Option<Vec<u8>> {
+    if value.len() > size {
+        return None;
+    }
+
+    let mut padded = vec![0; size - value.len()];
+    padded.extend(value);
+
+    Some(padded)
+}
+
+/// Key packet as hashed by fingerprints and signatures.
+fn hash_key_packet(body: &[u8]) -> Vec<u8> {
+    let mut key_packet = vec![0x99, (body.len() >> 8) as u8, body.len() as u8];
+    key_packet.extend(body);
+
+    key_packet
+}
+
+/// Truncated keys have no creation time.
+fn key_creation_time(body: &[u8]) -> u64 {
+    body.get(1..).and_then(|data| read_length(data, 4)).unwrap_or_default() as u64
+}
+
+fn encrypt_message(data: &[u8], recipient_key: &PgpRecipientKey) -> Result<Vec<u8>, String> {
+    let mut session_key = [0; 32];
+    rand_bytes(&mut session_key).map_err(|e| e.to_string())?;
+
+    // Session key is prefixed with its algorithm and followed by its checksum
+    let checksum = session_key.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+    let mut session_key_data = vec![ALGORITHM_AES_256];
+    session_key_data.extend(&session_key);
+    session_key_data.extend(&checksum.to_be_bytes());
+
+    let mut session_key_packet = vec![3];
+    session_key_packet.extend(&recipient_key.key_id);
+
+    match &recipient_key.key_material {
+        KeyMaterial::Rsa(public_key) => {
+            let mut encrypted_session_key = vec![0; public_key.size() as usize];
+            let encrypted_size = public_key
+                .public_encrypt(&session_key_data, &mut encrypted_session_key, Padding::PKCS1)
+                .map_err(|e| e.to_string())?;
+
+            session_key_packet.push(ALGORITHM_RSA);
+            session_key_packet.extend(encode_mpi(&encrypted_session_key[..encrypted_size]));
+        }
+        KeyMaterial::Cv25519 { public_key, kdf_hash, key_wrap_algorithm } => {
+            let (ephemeral_key, wrapped_session_key) = wrap_session_key(
+                &session_key_data,
+                public_key,
+                *kdf_hash,
+                *key_wrap_algorithm,
+                &recipient_key.fingerprint,
+            )?;
+
+            session_key_packet.push(ALGORITHM_ECDH);
+            session_key_packet.extend(encode_mpi(&ephemeral_key));
+            session_key_packet.push(wrapped_session_key.len() as u8);
+            session_key_packet.extend(wrapped_session_key);
+        }
+        KeyMaterial::Ed25519(_) => return Err("Ed25519 keys cannot encrypt!".into()),
+    }
+
+    // Binary literal data without file name and date
+    let mut literal_data = vec![b'b', 0, 0, 0, 0, 0];
+    literal_data.extend(data);
+
+    // Random block prefix with its last two octets repeated, then the data and its SHA-1 MDC
+    let mut plaintext = vec![0; 18];
+    rand_bytes(&mut plaintext[..16]).map_err(|e| e.to_string())?;
+    plaintext[16] = plaintext[14];
+    plaintext[17] = plaintext[15];
+    plaintext.extend(encode_packet(TAG_LITERAL_DATA, &literal_data));
+    plaintext.extend(&[0xC0 | TAG_MODIFICATION_DETECTION_CODE, 20]);
+    let mdc = sha1(&plaintext);
+    plaintext.extend(&mdc);
+
+    let mut encrypted_data = vec![1];
+    encrypted_data.extend(
+        encrypt(Cipher::aes_256_cfb128(), &session_key, Some(&[0; 16]), &plaintext)
+            .map_err(|e| e.to_string())?,
+    );
+
+    let mut message = encode_packet(TAG_PUBLIC_KEY_ENCRYPTED_SESSION_KEY, &session_key_packet);
+    message.extend(encode_packet(TAG_SYM_ENCRYPTED_INTEGRITY_PROTECTED_DATA, &encrypted_data));
+
+    Ok(message)
+}
+
+/// Wraps the session key with a key agreed with an ephemeral X25519 key (RFC 6637). Returns the
+/// ephemeral public point as the recipient expects it, and the wrapped session key.
+fn wrap_session_key(
+    session_key_data: &[u8],
+    public_key: &PKey<Public>,
+    kdf_hash: u8,
+    key_wrap_algorithm: u8,
+    fingerprint: &[u8; 20],
+) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let ephemeral_key = PKey::generate_x25519().map_err(|e| e.to_string())?;
+    let shared_secret = Deriver::new(&ephemeral_key)
+        .and_then(|mut deriver| {
+            deriver.set_peer(public_key)?;
+            deriver.derive_to_vec()
+        })
+        .map_err(|e| e.to_string())?;
+    let ephemeral_der = ephemeral_key.public_key_to_der().map_err(|e| e.to_string())?;
+    let mut ephemeral_point = vec![0x40];
+    ephemeral_point.extend(&ephemeral_der[DER_PREFIX_X25519.len()..]);
+
+    let key_wrap_key =
+        derive_key_wrap_key(&shared_secret, kdf_hash, key_wrap_algorithm, fingerprint)?;
+    let key_wrap_key =
+        AesKey::new_encrypt(&key_wrap_key).map_err(|_| "Invalid key wrap key!".to_string())?;
+
+    // Padded to 8 octet blocks as in PKCS#5
+    let padding = 8 - session_key_data.len() % 8;
+    let mut padded_session_key = session_key_data.to_vec();
+    padded_session_key.extend(vec![padding as u8; padding]);
+
+    let mut wrapped_session_key = vec![0; padded_session_key.len() + 8];
+    wrap_key(&key_wrap_key, None, &mut wrapped_session_key, &padded_session_key)
+        .map_err(|_| "Cannot wrap the session key!".to_string())?;
+
+    Ok((ephemeral_point, wrapped_session_key))
+}
+
+/// Derives the key wrapping the session key from the ECDH shared secret and the parameters of the
+/// recipient key.
+fn derive_key_wrap_key(
+    shared_secret: &[u8],
+    kdf_hash: u8,
+    key_wrap_algorithm: u8,
+    fingerprint: &[u8; 20],
+) -> Result<Vec<u8>, String> {
+    let message_digest = match kdf_hash {
+        8 => MessageDigest::sha256(),
+        9 => MessageDigest::sha384(),
+        10 => MessageDigest::sha512(),
+        _ => return Err(format!("Unsupported KDF hash algorithm {}!", kdf_hash)),
+    };
+    let key_wrap_key_size = match key_wrap_algorithm {
+        ALGORITHM_AES_128 => 16,
+        ALGORITHM_AES_192 => 24,
+        ALGORITHM_AES_256 => 32,
+        _ => return Err(format!("Unsupported key wrap algorithm {}!", key_wrap_algorithm)),
+    };
+
+    // A counter, the shared secret, then the parameters of the recipient key
+    let mut kdf_input = vec![0, 0, 0, 1];
+    kdf_input.extend(shared_secret);
+    kdf_input.push(OID_CV25519.len() as u8);
+    kdf_input.extend(OID_CV25519);
+    kdf_input.extend(&[ALGORITHM_ECDH, 3, 1, kdf_hash, key_wrap_algorithm]);
+    kdf_input.extend(b"Anonymous Sender    ");
+    kdf_input.extend(fingerprint);
+
+    let kdf_output = hash(message_digest, &kdf_input).map_err(|e| e.to_string())?;
+
+    Ok(kdf_output[..key_wrap_key_size].to_vec())
+}
+
+fn parse_mpi(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    if data.len() < 2 {
+        return None;
+    }
+
+    let bit_count = ((data[0] as usize) << 8) | data[1] as usize;
+    let length = (bit_count + 7) / 8;
+
+    if data.len() < 2 + length {
+        return None;
+    }
+
+    Some((&data[2..2 + length], &data[2 + length..]))
+}
+
+fn encode_mpi(value: &[u8]) -> Vec<u8> {
+    let leading_zeros = value.iter().take_while(|byte| **byte == 0).count();
+    let value = &value[leading_zeros..];
+    let bit_count =
+        value.first().map_or(0, |first| value.len() * 8 - first.leading_zeros() as usize);
+    let mut mpi = vec![(bit_count >> 8) as u8, bit_count as u8];
+    mpi.extend(value);
+
+    mpi
+}
+
+/// Returns the tag and body of every packet, in both the old and the new packet format.
+fn parse_packets(mut data: &[u8]) -> Result<Vec<(u8, &[u8])>, String> {
+    let mut packets = Vec::new();
+
+    while !data.is_empty() {
+        let header = data[0];
+
+        if header & 0x80 == 0 {
+            return Err("Invalid packet header!".into());
+        }
+
+        let (tag, length_size, length) = if header & 0x40 != 0 {
+            match data.get(1).copied() {
+                Some(length) if length < 192 => (header & 0x3F, 1, Some(length as usize)),
+                Some(length) if length < 224 => (
+                    header & 0x3F,
+                    2,
+                    data.get(2).map(|next| ((length as usize - 192) << 8) + *next as usize + 192),
+                ),
+                Some(255) => (header & 0x3F, 5, read_length(&data[2..], 4)),
+                _ => return Err("Unsupported packet length!".into()),
+            }
+        } else {
+            match header & 0x03 {
+                0 => ((header >> 2) & 0x0F, 1, read_length(&data[1..], 1)),
+                1 => ((header >> 2) & 0x0F, 2, read_length(&data[1..], 2)),
+                2 => ((header >> 2) & 0x0F, 4, read_length(&data[1..], 4)),
+                _ => ((header >> 2) & 0x0F, 0, Some(data.len() - 1)),
+            }
+        };
+        let body_start = 1 + length_size;
+
+        match length {
+            Some(length) if data.len() >= body_start + length => {
+                packets.push((tag, &data[body_start..body_start + length]));
+                data = &data[body_start + length..];
+            }
+            _ => return Err("Truncated packet!".into()),
+        }
+    }
+
+    Ok(packets)
+}
+
+fn read_length(data: &[u8], size: usize) -> Option<usize> {
+    data.get(..size)
+        .map(|bytes| bytes.iter().fold(0, |length, byte| (length << 8) | *byte as usize))
+}
+
+fn encode_packet(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0xC0 | tag];
+
+    if body.len() < 192 {
+        packet.push(body.len() as u8);
+    } else if body.len() < 8384 {
+        packet.push((((body.len() - 192) >> 8) + 192) as u8);
+        packet.push((body.len() - 192) as u8);
+    } else {
+        packet.push(255);
+        packet.extend(&(body.len() as u32).to_be_bytes());
+    }
+
+    packet.extend(body);
+
+    packet
+}
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc = 0xB7_04CE_u32;
+
+    for byte in data {
+        crc ^= (*byte as u32) << 16;
+
+        for _ in 0..8 {
+            crc <<= 1;
+
+            if crc & 0x100_0000 != 0 {
+                crc ^= 0x186_4CFB;
+            }
+        }
+    }
+
+    crc & 0xFF_FFFF
+}
+
+fn armor(label: &str, data: &[u8]) -> String {
+    let encoded = encode_block(data);
+    let mut armored = format!("-----BEGIN {}-----\r\n\r\n", label);
+
+    for line in encoded.as_bytes().chunks(64) {
+        armored.push_str(&String::from_utf8_lossy(line));
+        armored.push_str("\r\n");
+    }
+
+    armored.push('=');
+    armored.push_str(&encode_block(&crc24(data).to_be_bytes()[1..]));
+    armored.push_str(&format!("\r\n-----END {}-----", label));
+
+    armored
+}
+
+fn dearmor(armored: &[u8]) -> Result<Vec<u8>, String> {
+    let armored = String::from_utf8_lossy(armored);
+    let mut lines = armored.lines().map(str::trim).skip(1);
+    let mut encoded = String::new();
+
+    // Armor headers end at the first empty line
+    for line in lines.by_ref() {
+        if line.is_empty() {
+            break;
+        }
+    }
+
+    for line in lines {
+        if line.starts_with('=') || line.starts_with("-----END") {
+            break;
+        }
+
+        encoded.push_str(line);
+    }
+
+    decode_block(&encoded).map_err(|_| "Invalid armor!".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::aes::unwrap_key;
+    use openssl::bn::BigNumContext;
+    use openssl::pkey::Private;
+    use openssl::symm::decrypt;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    const TAG_SECRET_SUBKEY: u8 = 7;
+    /// Generated by gpg: a sign-only primary key, an encryption subkey (the one to pick), an
+    /// expired and a revoked encryption subkey created after it, then a signing subkey.
+    const RECIPIENT_KEY: &[u8] = include_bytes!("fixtures/pgp_recipient.asc");
+    /// The unprotected secret of the encryption subkey, exported by gpg.
+    const RECIPIENT_SECRET_KEY: &[u8] = include_bytes!("fixtures/pgp_recipient_secret.asc");
+    /// Encrypted by `encrypt_message` to `RECIPIENT_KEY`, `gpg --decrypt` gives `RAW_EMAIL`'s
+    /// entity back.
+    const ENCRYPTED_MESSAGE: &[u8] = include_bytes!("fixtures/pgp_message.asc");
+    const ENCRYPTION_KEY_ID: [u8; 8] = [0x27, 0x34, 0x05, 0xFC, 0x4D, 0x4F, 0x18, 0x43];
+    const EXPIRED_KEY_ID: [u8; 8] = [0x4C, 0x38, 0x9E, 0x34, 0x36, 0x26, 0xD9, 0x5C];
+    /// 2021-01-01 12:00 UTC, before the expired subkey expires and the revoked one is created.
+    const PAST_TIME: u64 = 1_609_502_400;
+    /// Generated by gpg: an Ed25519 primary key and a Cv25519 encryption subkey.
+    const CURVE25519_KEY: &[u8] = include_bytes!("fixtures/pgp_curve25519_recipient.asc");
+    /// The unprotected secret of the Cv25519 subkey, exported by gpg.
+    const CURVE25519_SECRET_KEY: &[u8] =
+        include_bytes!("fixtures/pgp_curve25519_recipient_secret.asc");
+    /// Encrypted by `encrypt_message` to `CURVE25519_KEY`, `gpg --decrypt` gives `RAW_EMAIL`'s
+    /// entity back.
+    const CURVE25519_MESSAGE: &[u8] = include_bytes!("fixtures/pgp_curve25519_message.asc");
+    const CURVE25519_ENCRYPTION_KEY_ID: [u8; 8] = [0x54, 0xD8, 0x92, 0xE8, 0x43, 0x04, 0x1A, 0x9C];
+    /// PKCS#8 prefix of an X25519 private key (RFC 8410), followed by the raw 32 octet key.
+    const DER_PREFIX_X25519_PRIVATE: &[u8] = &[
+        0x30, 0x2E, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2B, 0x65, 0x6E, 0x04, 0x22, 0x04,
+        0x20,
+    ];
+
+    const RAW_EMAIL: &[u8] = b"From: noreply@example.com\r\n\
+        To: admin@example.com\r\n\
+        Subject: Security Notification\r\n\
+        MIME-Version: 1.0\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        \r\n\
+        Hello!! Your key was rotated.\r\n";
+
+    fn create_draft(pgp_allow_plaintext: bool) -> MessageDraft {
+        MessageDraft { pgp_encrypt: true, pgp_allow_plaintext, ..MessageDraft::default() }
+    }
+
+    fn read_secret_subkey() -> Rsa<Private> {
+        let key_data = dearmor(RECIPIENT_SECRET_KEY).unwrap();
+        let packets = parse_packets(&key_data).unwrap();
+        let (_, body) = packets.iter().find(|(tag, _)| *tag == TAG_SECRET_SUBKEY).unwrap();
+        let (modulus, remaining) = parse_mpi(&body[6..]).unwrap();
+        let (exponent, remaining) = parse_mpi(remaining).unwrap();
+
+        // Unprotected secret keys have a zero S2K usage, then the d, p, q and u MPIs
+        assert_eq!(remaining[0], 0);
+
+        let (private_exponent, remaining) = parse_mpi(&remaining[1..]).unwrap();
+        let (first_prime, remaining) = parse_mpi(remaining).unwrap();
+        let (second_prime, _) = parse_mpi(remaining).unwrap();
+        let to_big_num = |value: &[u8]| BigNum::from_slice(value).unwrap();
+        let private_exponent = to_big_num(private_exponent);
+        let first_prime = to_big_num(first_prime);
+        let second_prime = to_big_num(second_prime);
+        let one = BigNum::from_u32(1).unwrap();
+        let mut context = BigNumContext::new().unwrap();
+        let mut first_exponent = BigNum::new().unwrap();
+        let mut second_exponent = BigNum::new().unwrap();
+        let mut coefficient = BigNum::new().unwrap();
+
+        first_exponent
+            .checked_rem(&private_exponent, &(&first_prime - &one), &mut context)
+            .unwrap();
+        second_exponent
+            .checked_rem(&private_exponent, &(&second_prime - &one), &mut context)
+            .unwrap();
+        coefficient.mod_inverse(&second_prime, &first_prime, &mut context).unwrap();
+
+        Rsa::from_private_components(
+            to_big_num(modulus),
+            to_big_num(exponent),
+            private_exponent,
+            first_prime,
+            second_prime,
+            first_exponent,
+            second_exponent,
+            coefficient,
+        )
+        .unwrap()
+    }
+
+    fn decrypt_rsa_session_key(session_key_packet: &[u8]) -> Vec<u8> {
+        let private_key = read_secret_subkey();
+        let (encrypted_session_key, _) = parse_mpi(&session_key_packet[10..]).unwrap();
+        let mut session_key_data = vec![0; private_key.size() as usize];
+        let session_key_size = private_key
+            .private_decrypt(encrypted_session_key, &mut session_key_data, Padding::PKCS1)
+            .unwrap();
+
+        assert_eq!(&session_key_packet[1..9], &ENCRYPTION_KEY_ID);
+        assert_eq!(session_key_packet[9], ALGORITHM_RSA);
+        session_key_data.truncate(session_key_size);
+
+        session_key_data
+    }
+
+    fn decrypt_curve25519_session_key(session_key_packet: &[u8]) -> Vec<u8> {
+        let key_data = dearmor(CURVE25519_SECRET_KEY).unwrap();
+        let packets = parse_packets(&key_data).unwrap();
+        let (_, body) = packets.iter().find(|(tag, _)| *tag == TAG_SECRET_SUBKEY).unwrap();
+        // The public part ends with the 4 octets of KDF parameters
+        let (_, remaining) = parse_curve_point(&body[6..], OID_CV25519).unwrap();
+        let recipient_key = parse_public_key(&body[..body.len() - remaining.len() + 4]).unwrap();
+        let (kdf_hash, key_wrap_algorithm) = match recipient_key.key_material {
+            KeyMaterial::Cv25519 { kdf_hash, key_wrap_algorithm, .. } => {
+                (kdf_hash, key_wrap_algorithm)
+            }
+            _ => panic!("Not a Cv25519 subkey!"),
+        };
+
+        // Unprotected secret keys have a zero S2K usage, then the scalar MPI in reverse order
+        assert_eq!(remaining[4], 0);
+
+        let (secret, _) = parse_mpi(&remaining[5..]).unwrap();
+        let mut private_der = DER_PREFIX_X25519_PRIVATE.to_vec();
+        private_der.extend(pad_value(secret, 32).unwrap().iter().rev());
+
+        let private_key = PKey::private_key_from_der(&private_der).unwrap();
+
+        assert_eq!(&session_key_packet[1..9], &CURVE25519_ENCRYPTION_KEY_ID);
+        assert_eq!(session_key_packet[9], ALGORITHM_ECDH);
+
+        let (ephemeral_point, remaining) = parse_mpi(&session_key_packet[10..]).unwrap();
+        let ephemeral_key = parse_raw_public_key(DER_PREFIX_X25519, &ephemeral_point[1..]).unwrap();
+        let mut deriver = Deriver::new(&private_key).unwrap();
+        deriver.set_peer(&ephemeral_key).unwrap();
+
+        let key_wrap_key = derive_key_wrap_key(
+            &deriver.derive_to_vec().unwrap(),
+            kdf_hash,
+            key_wrap_algorithm,
+            &recipient_key.fingerprint,
+        )
+        .unwrap();
+        let wrapped_session_key = &remaining[1..];
+        let mut session_key_data = vec![0; wrapped_session_key.len() - 8];
+
+        assert_eq!(remaining[0] as usize, wrapped_session_key.len());
+        unwrap_key(
+            &AesKey::new_decrypt(&key_wrap_key).unwrap(),
+            None,
+            &mut session_key_data,
+            wrapped_session_key,
+        )
+        .unwrap();
+
+        let padding = *session_key_data.last().unwrap() as usize;
+        session_key_data.truncate(session_key_data.len() - padding);
+
+        session_key_data
+    }
+
+    fn decrypt_message(message: &[u8], decrypt_session_key: fn(&[u8]) -> Vec<u8>) -> Vec<u8> {
+        let packets = parse_packets(message).unwrap();
+        let (_, session_key_packet) = packets[0];
+        let (_, encrypted_data) = packets[1];
+        let session_key_data = decrypt_session_key(session_key_packet);
+
+        assert_eq!(session_key_data.len(), 35);
+        assert_eq!(session_key_data[0], ALGORITHM_AES_256);
+
+        let session_key = &session_key_data[1..33];
+        let plaintext =
+            decrypt(Cipher::aes_256_cfb128(), session_key, Some(&[0; 16]), &encrypted_data[1..])
+                .unwrap();
+        let (protected_data, mdc) = plaintext.split_at(plaintext.len() - 20);
+
+        assert_eq!(&plaintext[14..16], &plaintext[16..18]);
+        assert_eq!(sha1(protected_data), mdc);
+
+        let packets = parse_packets(&protected_data[18..protected_data.len() - 2]).unwrap();
+        let (tag, literal_data) = packets[0];
+
+        assert_eq!(tag, TAG_LITERAL_DATA);
+
+        literal_data[6..].to_vec()
+    }
+
+    #[test]
+    fn test_armor_round_trip() {
+        let armored = armor("PGP MESSAGE", b"Hello!!");
+
+        // Same checksum as `gpg --enarmor`
+        assert_eq!(
+            armored,
+            "-----BEGIN PGP MESSAGE-----\r\n\r\nSGVsbG8hIQ==\r\n=aLJR\r\n-----END PGP MESSAGE-----"
+        );
+        assert_eq!(dearmor(armored.as_bytes()).unwrap(), b"Hello!!");
+    }
+
+    #[test]
+    fn test_select_valid_encryption_subkey() {
+        let recipient_key = PgpRecipientKey::parse(RECIPIENT_KEY).unwrap();
+        let past_recipient_key = PgpRecipientKey::parse_at(RECIPIENT_KEY, PAST_TIME).unwrap();
+
+        assert_eq!(recipient_key.key_id, ENCRYPTION_KEY_ID);
+        assert_eq!(past_recipient_key.key_id, EXPIRED_KEY_ID);
+    }
+
+    /// Flips the last bit of the packet at `packet_index`, a signature ending with its value.
+    fn tamper_signature(key: &[u8], packet_index: usize) -> Vec<u8> {
+        let key_data = dearmor(key).unwrap();
+        let mut tampered_key_data = Vec::new();
+
+        for (index, (tag, body)) in parse_packets(&key_data).unwrap().into_iter().enumerate() {
+            let mut body = body.to_vec();
+
+            if index == packet_index {
+                assert_eq!(tag, TAG_SIGNATURE);
+                *body.last_mut().unwrap() ^= 1;
+            }
+
+            tampered_key_data.extend(encode_packet(tag, &body));
+        }
+
+        tampered_key_data
+    }
+
+    #[test]
+    fn test_skip_subkey_with_invalid_binding_signature() {
+        // The binding signatures of the encryption subkeys
+        assert_eq!(
+            PgpRecipientKey::parse(&tamper_signature(RECIPIENT_KEY, 4)).err(),
+            Some("No valid encryption key found!".into())
+        );
+        assert_eq!(
+            PgpRecipientKey::parse(&tamper_signature(CURVE25519_KEY, 4)).err(),
+            Some("No valid encryption key found!".into())
+        );
+    }
+
+    #[test]
+    fn test_select_curve25519_encryption_subkey() {
+        let recipient_key = PgpRecipientKey::parse(CURVE25519_KEY).unwrap();
+
+        assert_eq!(recipient_key.key_id, CURVE25519_ENCRYPTION_KEY_ID);
+        assert!(matches!(recipient_key.key_material, KeyMaterial::Cv25519 { .. }));
+    }
+
+    #[test]
+    fn test_reject_truncated_packets() {
+        for key in [RECIPIENT_KEY, CURVE25519_KEY].iter() {
+            let key_data = dearmor(key).unwrap();
+            let packets = parse_packets(&key_data).unwrap();
+
+            // Every packet cut short, which must fail without panicking
+            for truncated_index in 0..packets.len() {
+                for truncated_size in 0..packets[truncated_index].1.len() {
+                    let mut truncated_key_data = Vec::new();
+
+                    for (index, (tag, body)) in packets.iter().enumerate() {
+                        let body =
+                            if index == truncated_index { &body[..truncated_size] } else { body };
+
+                        truncated_key_data.extend(encode_packet(*tag, body));
+                    }
+
+                    let _ = PgpRecipientKey::parse(&truncated_key_data);
+                }
+            }
+
+            for truncated_size in 0..key_data.len() {
+                let _ = PgpRecipientKey::parse(&key_data[..truncated_size]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encrypt_to_keyring_key() {
+        let keyring_dir = std::env::temp_dir().join(format!("pgp-{}", Uuid::new_v4()));
+        create_dir_all(&keyring_dir).unwrap();
+        write(keyring_dir.join("admin@example.com.asc"), RECIPIENT_KEY).unwrap();
+
+        let encryptor = PgpEncryptor { keyring_dir: Some(keyring_dir.to_string_lossy().into()) };
+        let pgp_email = encryptor.compose(&create_draft(false), RAW_EMAIL);
+        remove_dir_all(&keyring_dir).unwrap();
+
+        let pgp_email = String::from_utf8(pgp_email.unwrap()).unwrap();
+        let armored_start = pgp_email.find("-----BEGIN PGP MESSAGE-----").unwrap();
+        let armored_end = pgp_email.find("-----END PGP MESSAGE-----").unwrap() + 25;
+        let message = dearmor(pgp_email[armored_start..armored_end].as_bytes()).unwrap();
+        let (_, entity) = split_entity(RAW_EMAIL);
+
+        assert!(pgp_email.starts_with("From: noreply@example.com\r\n"));
+        assert!(pgp_email.contains("\r\nContent-Type: multipart/encrypted;"));
+        assert!(!pgp_email.contains("Hello!!"));
+        assert_eq!(decrypt_message(&message, decrypt_rsa_session_key), entity);
+    }
+
+    #[test]
+    fn test_encrypt_to_curve25519_key() {
+        let recipient_key = PgpRecipientKey::parse(CURVE25519_KEY).unwrap();
+        let (_, entity) = split_entity(RAW_EMAIL);
+        let message = encrypt_message(&entity, &recipient_key).unwrap();
+
+        assert_eq!(decrypt_message(&message, decrypt_curve25519_session_key), entity);
+    }
+
+    #[test]
+    fn test_decrypt_message_checked_by_gpg() {
+        let (_, entity) = split_entity(RAW_EMAIL);
+        let message = dearmor(ENCRYPTED_MESSAGE).unwrap();
+
+        assert_eq!(decrypt_message(&message, decrypt_rsa_session_key), entity);
+
+        let message = dearmor(CURVE25519_MESSAGE).unwrap();
+
+        assert_eq!(decrypt_message(&message, decrypt_curve25519_session_key), entity);
+    }
+
+    #[test]
+    fn test_missing_key_fails_or_falls_back_to_plaintext() {
+        let encryptor = PgpEncryptor { keyring_dir: Some("/nonexistent".into()) };
+
+        assert_eq!(
+            encryptor.compose(&create_draft(false), RAW_EMAIL),
+            Err("No OpenPGP key for admin@example.com!".into())
+        );
+        assert_eq!(encryptor.compose(&create_draft(true), RAW_EMAIL), Ok(RAW_EMAIL.to_vec()));
+    }
+}
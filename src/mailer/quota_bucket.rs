@@ -0,0 +1,248 @@
+use super::rate_limiter::{create_rate_limiter, RateLimiter};
+use crate::config::QuotaLimit;
+use crate::messages::MessageDraftPriority;
+use chrono_tz::Tz;
+use tokio::time::{Duration, Instant};
+
+/// Global quota where `reserved_percent` of it can only be taken by high priority drafts.
+pub struct QuotaBucket {
+    max: usize,
+    normal_max: usize,
+    shared_limiter: Box<dyn RateLimiter + Send>,
+    normal_limiter: Option<Box<dyn RateLimiter + Send>>,
+}
+
+impl QuotaBucket {
+    pub fn new(
+        quota_limit: &QuotaLimit,
+        interval: Duration,
+        quota_timezone: Option<&Tz>,
+        reserved_percent: usize,
+    ) -> Self {
+        let reserved = quota_limit.max * reserved_percent.min(100) / 100;
+        let mut normal_limiter = None;
+
+        if reserved > 0 {
+            let normal_quota_limit = QuotaLimit { max: quota_limit.max - reserved, ..*quota_limit };
+
+            normal_limiter =
+                Some(create_rate_limiter(&normal_quota_limit, interval, quota_timezone));
+        }
+
+        Self {
+            max: quota_limit.max,
+            normal_max: quota_limit.max - reserved,
+            shared_limiter: create_rate_limiter(quota_limit, interval, quota_timezone),
+            normal_limiter,
+        }
+    }
+
+    /// Charges `self` with the permits `previous_bucket` has used, so a quota changed on reload
+    /// does not start over.
+    pub fn carry_over(&mut self, previous_bucket: &QuotaBucket, current_instant: &Instant) {
+        let shared_used =
+            previous_bucket.max.saturating_sub(previous_bucket.remaining(current_instant));
+        let shared_instants = previous_bucket.shared_limiter.taken_instants(current_instant);
+
+        self.shared_limiter.take_used(shared_used, &shared_instants, current_instant);
+
+        if let Some(normal_limiter) = self.normal_limiter.as_mut() {
+            // Without a reservation before, every used permit may have been a normal one
+            let (normal_used, normal_instants) = match previous_bucket.normal_limiter.as_ref() {
+                None => (shared_used, shared_instants),
+                Some(previous_normal_limiter) => (
+                    previous_bucket
+                        .normal_max
+                        .saturating_sub(previous_normal_limiter.remaining(current_instant)),
+                    previous_normal_limiter.taken_instants(current_instant),
+                ),
+            };
+
+            normal_limiter.take_used(normal_used, &normal_instants, current_instant);
+        }
+    }
+
+    /// Returns how long to wait when no permit is left for `priority`, without taking one.
+    pub fn check(
+        &mut self,
+        priority: MessageDraftPriority,
+        current_instant: &Instant,
+    ) -> Option<Duration> {
+        if priority == MessageDraftPriority::Normal {
+            if let Some(normal_limiter) = self.normal_limiter.as_mut() {
+                if let Some(duration_to_wait) = normal_limiter.check(current_instant) {
+                    return Some(duration_to_wait);
+                }
+            }
+        }
+
+        self.shared_limiter.check(current_instant)
+    }
+
+    /// Takes a permit from every limiter `priority` is counted in, or from none of them.
+    pub fn try_take(
+        &mut self,
+        priority: MessageDraftPriority,
+        current_instant: &Instant,
+    ) -> Option<Duration> {
+        if let Some(duration_to_wait) = self.check(priority, current_instant) {
+            return Some(duration_to_wait);
+        }
+
+        if priority == MessageDraftPriority::Normal {
+            if let Some(normal_limiter) = self.normal_limiter.as_mut() {
+                normal_limiter.try_take(current_instant);
+            }
+        }
+
+        self.shared_limiter.try_take(current_instant)
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Permits left in the whole quota, including the share reserved for high priority drafts.
+    pub fn remaining(&self, current_instant: &Instant) -> usize {
+        self.shared_limiter.remaining(current_instant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RateLimiterKind;
+    use crate::mailer::MINUTE_IN_SECONDS;
+
+    fn create_bucket(max: usize, reserved_percent: usize) -> QuotaBucket {
+        create_bucket_with_kind(max, reserved_percent, RateLimiterKind::SlidingWindow)
+    }
+
+    fn create_bucket_with_kind(
+        max: usize,
+        reserved_percent: usize,
+        limiter_kind: RateLimiterKind,
+    ) -> QuotaBucket {
+        let quota_limit = QuotaLimit { max, limiter_kind };
+
+        QuotaBucket::new(
+            &quota_limit,
+            Duration::from_secs(MINUTE_IN_SECONDS),
+            None,
+            reserved_percent,
+        )
+    }
+
+    #[test]
+    fn test_normal_priority_cannot_take_reserved_share() {
+        let mut bucket = create_bucket(10, 20);
+        let current_instant = Instant::now();
+
+        for _ in 0..8 {
+            assert!(bucket.try_take(MessageDraftPriority::Normal, &current_instant).is_none());
+        }
+
+        assert!(bucket.try_take(MessageDraftPriority::Normal, &current_instant).is_some());
+        assert!(bucket.try_take(MessageDraftPriority::High, &current_instant).is_none());
+        assert!(bucket.try_take(MessageDraftPriority::High, &current_instant).is_none());
+        assert!(bucket.try_take(MessageDraftPriority::High, &current_instant).is_some());
+    }
+
+    #[test]
+    fn test_refused_take_keeps_normal_permit() {
+        let mut bucket = create_bucket(10, 20);
+        let current_instant = Instant::now();
+        let retry_instant = current_instant + Duration::from_secs(30);
+        let later_instant = current_instant + Duration::from_secs(MINUTE_IN_SECONDS);
+
+        for _ in 0..10 {
+            assert!(bucket.try_take(MessageDraftPriority::High, &current_instant).is_none());
+        }
+
+        // Refused by the shared limiter, so the normal one must not be charged for the retries
+        for _ in 0..3 {
+            assert!(bucket.try_take(MessageDraftPriority::Normal, &retry_instant).is_some());
+        }
+
+        for _ in 0..8 {
+            assert!(bucket.try_take(MessageDraftPriority::Normal, &later_instant).is_none());
+        }
+
+        assert!(bucket.try_take(MessageDraftPriority::Normal, &later_instant).is_some());
+    }
+
+    #[test]
+    fn test_high_priority_can_take_whole_quota() {
+        let mut bucket = create_bucket(10, 20);
+        let current_instant = Instant::now();
+
+        for _ in 0..10 {
+            assert!(bucket.try_take(MessageDraftPriority::High, &current_instant).is_none());
+        }
+
+        assert!(bucket.try_take(MessageDraftPriority::High, &current_instant).is_some());
+        assert!(bucket.try_take(MessageDraftPriority::Normal, &current_instant).is_some());
+    }
+
+    #[test]
+    fn test_carry_over_used_permits() {
+        let mut previous_bucket = create_bucket(10, 0);
+        let current_instant = Instant::now();
+
+        for _ in 0..6 {
+            assert!(previous_bucket
+                .try_take(MessageDraftPriority::Normal, &current_instant)
+                .is_none());
+        }
+
+        let mut bucket = create_bucket(8, 25);
+
+        bucket.carry_over(&previous_bucket, &current_instant);
+
+        assert_eq!(bucket.remaining(&current_instant), 2);
+        assert!(bucket.try_take(MessageDraftPriority::Normal, &current_instant).is_some());
+        assert!(bucket.try_take(MessageDraftPriority::High, &current_instant).is_none());
+
+        let mut smaller_bucket = create_bucket(4, 0);
+
+        smaller_bucket.carry_over(&previous_bucket, &current_instant);
+        assert_eq!(smaller_bucket.remaining(&current_instant), 0);
+    }
+
+    #[test]
+    fn test_carry_over_fixed_window() {
+        let mut previous_bucket = create_bucket_with_kind(10, 0, RateLimiterKind::FixedWindow);
+        let window_start = Instant::now();
+
+        for _ in 0..6 {
+            assert!(previous_bucket
+                .try_take(MessageDraftPriority::Normal, &window_start)
+                .is_none());
+        }
+
+        // Taken before the new bucket is built, as on reload
+        let reload_instant = Instant::now();
+        let mut bucket = create_bucket_with_kind(8, 25, RateLimiterKind::FixedWindow);
+
+        bucket.carry_over(&previous_bucket, &reload_instant);
+
+        assert_eq!(bucket.remaining(&reload_instant), 2);
+        assert!(bucket.try_take(MessageDraftPriority::Normal, &reload_instant).is_some());
+        assert!(bucket.try_take(MessageDraftPriority::High, &reload_instant).is_none());
+
+        // The window still ends a minute after it started, not after the carry over
+        let next_window = window_start + Duration::from_secs(MINUTE_IN_SECONDS);
+
+        assert_eq!(bucket.remaining(&next_window), 8);
+    }
+
+    #[test]
+    fn test_no_reservation_shares_whole_quota() {
+        let mut bucket = create_bucket(2, 0);
+        let current_instant = Instant::now();
+
+        assert!(bucket.try_take(MessageDraftPriority::Normal, &current_instant).is_none());
+        assert!(bucket.try_take(MessageDraftPriority::Normal, &current_instant).is_none());
+        assert!(bucket.try_take(MessageDraftPriority::High, &current_instant).is_some());
+    }
+}
@@ -0,0 +1,67 @@
+use super::calendar_bucket::{CalendarBucket, CalendarPeriod};
+use super::resettable_bucket::ResettableBucket;
+use super::sliding_window::SlidingWindow;
+use super::token_bucket::TokenBucket;
+use crate::config::{QuotaLimit, RateLimiterKind};
+use chrono_tz::Tz;
+use tokio::time::{Duration, Instant};
+
+pub trait RateLimiter {
+    /// Takes one permit, or returns how long to wait until one becomes available.
+    fn try_take(&mut self, current_instant: &Instant) -> Option<Duration>;
+
+    /// Number of permits that could be taken at `current_instant`.
+    fn remaining(&self, current_instant: &Instant) -> usize;
+
+    /// Returns how long to wait when no permit is left, without taking one.
+    fn check(&mut self, current_instant: &Instant) -> Option<Duration> {
+        if self.remaining(current_instant) > 0 {
+            return None;
+        }
+
+        // Refused takes leave the limiter unchanged
+        self.try_take(current_instant)
+    }
+
+    /// Instants the permits counted at `current_instant` were taken at, oldest first, for
+    /// limiters keeping them.
+    fn taken_instants(&self, _current_instant: &Instant) -> Vec<Instant> {
+        Vec::new()
+    }
+
+    /// Takes `permits` at once, so a limiter replacing another one starts with its usage.
+    /// `taken_instants` are the instants some of them were taken at, when known.
+    fn take_used(
+        &mut self,
+        permits: usize,
+        _taken_instants: &[Instant],
+        current_instant: &Instant,
+    ) {
+        for _ in 0..permits {
+            if self.try_take(current_instant).is_some() {
+                break;
+            }
+        }
+    }
+}
+
+/// Fixed windows of a minute, an hour or a day are aligned to wall-clock boundaries when
+/// `quota_timezone` is set.
+pub fn create_rate_limiter(
+    quota_limit: &QuotaLimit,
+    interval: Duration,
+    quota_timezone: Option<&Tz>,
+) -> Box<dyn RateLimiter + Send> {
+    match quota_limit.limiter_kind {
+        RateLimiterKind::FixedWindow => {
+            match (quota_timezone, CalendarPeriod::from_interval(interval)) {
+                (Some(timezone), Some(period)) => {
+                    Box::new(CalendarBucket::new(quota_limit.max, period, *timezone))
+                }
+                _ => Box::new(ResettableBucket::new(quota_limit.max, interval)),
+            }
+        }
+        RateLimiterKind::SlidingWindow => Box::new(SlidingWindow::new(quota_limit.max, interval)),
+        RateLimiterKind::TokenBucket => Box::new(TokenBucket::new(quota_limit.max, interval)),
+    }
+}
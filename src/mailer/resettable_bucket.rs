@@ -1,3 +1,4 @@
+use super::rate_limiter::RateLimiter;
 use tokio::time::{Duration, Instant};
 
 pub struct ResettableBucket {
@@ -16,11 +17,13 @@ impl ResettableBucket {
             current_bucket_size: bucket_size,
         }
     }
+}
 
-    pub fn try_take(&mut self, current_instant: &Instant) -> Option<Duration> {
-        if current_instant.duration_since(self.last_reset) >= self.bucket_interval {
+impl RateLimiter for ResettableBucket {
+    fn try_take(&mut self, current_instant: &Instant) -> Option<Duration> {
+        if current_instant.saturating_duration_since(self.last_reset) >= self.bucket_interval {
             self.current_bucket_size = self.bucket_size;
-            self.last_reset = Instant::now();
+            self.last_reset = *current_instant;
         }
 
         if self.current_bucket_size > 0 {
@@ -31,6 +34,27 @@ impl ResettableBucket {
             Some((self.last_reset + self.bucket_interval) - *current_instant)
         }
     }
+
+    fn remaining(&self, current_instant: &Instant) -> usize {
+        if current_instant.saturating_duration_since(self.last_reset) >= self.bucket_interval {
+            self.bucket_size
+        } else {
+            self.current_bucket_size
+        }
+    }
+
+    /// The permits of the current window all count as taken when it started.
+    fn taken_instants(&self, current_instant: &Instant) -> Vec<Instant> {
+        let taken = self.bucket_size.saturating_sub(self.remaining(current_instant));
+
+        vec![self.last_reset; taken]
+    }
+
+    /// The window starts with the oldest known permit, so a carry over does not restart it.
+    fn take_used(&mut self, permits: usize, taken_instants: &[Instant], current_instant: &Instant) {
+        self.last_reset = taken_instants.first().copied().unwrap_or(*current_instant);
+        self.current_bucket_size = self.bucket_size.saturating_sub(permits);
+    }
 }
 
 #[cfg(test)]
@@ -83,6 +107,41 @@ mod tests {
         assert_eq!(next_period_take_out, true);
     }
 
+    #[test]
+    fn test_carry_over_keeps_window_start() {
+        let one_day = Duration::from_secs(DAY_IN_SECONDS);
+        let mut previous_bucket = ResettableBucket::new(10, one_day);
+        let window_start = previous_bucket.last_reset;
+
+        for _ in 0..4 {
+            assert!(previous_bucket.try_take(&Instant::now()).is_none());
+        }
+
+        // Built after the carry over instant, like a bucket replaced on reload
+        let reload_instant = window_start + Duration::from_secs(HOUR_IN_SECONDS);
+        let taken_instants = previous_bucket.taken_instants(&reload_instant);
+        let mut bucket = ResettableBucket::new(5, one_day);
+
+        bucket.take_used(4, &taken_instants, &reload_instant);
+
+        assert_eq!(bucket.remaining(&reload_instant), 1);
+        assert!(bucket.try_take(&reload_instant).is_none());
+        assert_eq!(
+            bucket.try_take(&reload_instant),
+            Some(one_day - (reload_instant - window_start))
+        );
+        assert!(bucket.try_take(&(window_start + one_day)).is_none());
+    }
+
+    #[test]
+    fn test_take_before_creation_does_not_panic() {
+        let earlier_instant = Instant::now();
+        let mut bucket = ResettableBucket::new(1, Duration::from_secs(MINUTE_IN_SECONDS));
+
+        assert!(bucket.try_take(&earlier_instant).is_none());
+        assert_eq!(bucket.remaining(&earlier_instant), 0);
+    }
+
     #[test]
     fn test_return_false_on_exhausted_day() {
         let one_day = Duration::from_secs(DAY_IN_SECONDS);
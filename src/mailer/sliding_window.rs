@@ -0,0 +1,157 @@
+use super::rate_limiter::RateLimiter;
+use std::collections::VecDeque;
+use std::iter::repeat;
+use tokio::time::{Duration, Instant};
+
+/// Sliding log limiter, never allows more than `window_size` takes within any `window_interval`.
+pub struct SlidingWindow {
+    window_size: usize,
+    window_interval: Duration,
+    taken_instants: VecDeque<Instant>,
+}
+
+impl SlidingWindow {
+    pub fn new(window_size: usize, window_interval: Duration) -> Self {
+        Self { window_size, window_interval, taken_instants: VecDeque::with_capacity(window_size) }
+    }
+}
+
+impl RateLimiter for SlidingWindow {
+    fn try_take(&mut self, current_instant: &Instant) -> Option<Duration> {
+        while let Some(oldest_instant) = self.taken_instants.front() {
+            if current_instant.saturating_duration_since(*oldest_instant) >= self.window_interval {
+                self.taken_instants.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if self.taken_instants.len() < self.window_size {
+            self.taken_instants.push_back(*current_instant);
+
+            None
+        } else {
+            match self.taken_instants.front() {
+                Some(oldest_instant) => {
+                    Some((*oldest_instant + self.window_interval) - *current_instant)
+                }
+                None => Some(self.window_interval),
+            }
+        }
+    }
+
+    fn remaining(&self, current_instant: &Instant) -> usize {
+        let taken = self
+            .taken_instants
+            .iter()
+            .filter(|taken_instant| {
+                current_instant.saturating_duration_since(**taken_instant) < self.window_interval
+            })
+            .count();
+
+        self.window_size.saturating_sub(taken)
+    }
+
+    fn taken_instants(&self, current_instant: &Instant) -> Vec<Instant> {
+        self.taken_instants
+            .iter()
+            .filter(|taken_instant| {
+                current_instant.saturating_duration_since(**taken_instant) < self.window_interval
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Known permits expire a window after they were taken, not after the carry over.
+    fn take_used(&mut self, permits: usize, taken_instants: &[Instant], current_instant: &Instant) {
+        let known_permits = taken_instants.len().min(permits);
+        let known_instants = taken_instants[taken_instants.len() - known_permits..].iter().copied();
+
+        self.taken_instants.extend(known_instants);
+        self.taken_instants.extend(repeat(*current_instant).take(permits - known_permits));
+
+        while self.taken_instants.len() > self.window_size {
+            self.taken_instants.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailer::MINUTE_IN_SECONDS;
+    use tokio::time::{Duration, Instant};
+
+    #[test]
+    fn test_return_false_on_exhausted_window() {
+        let one_minute = Duration::from_secs(MINUTE_IN_SECONDS);
+        let mut window = SlidingWindow::new(1, one_minute);
+        let current_instant = Instant::now();
+
+        assert_eq!(window.try_take(&current_instant), None);
+        assert_eq!(window.try_take(&current_instant), Some(one_minute));
+        assert_eq!(window.try_take(&(current_instant + one_minute)), None);
+    }
+
+    #[test]
+    fn test_no_double_burst_across_window_boundary() {
+        let one_second = Duration::from_secs(1);
+        let half_second = Duration::from_millis(500);
+        let mut window = SlidingWindow::new(2, one_second);
+        let start_instant = Instant::now();
+        let late_instant = start_instant + Duration::from_millis(900);
+        let boundary_instant = start_instant + one_second + Duration::from_millis(100);
+
+        assert_eq!(window.try_take(&start_instant), None);
+        assert_eq!(window.try_take(&late_instant), None);
+        assert_eq!(window.try_take(&boundary_instant), None);
+        // A fixed window would have refilled here, the sliding log still counts `late_instant`
+        assert_eq!(window.try_take(&boundary_instant), Some(Duration::from_millis(800)));
+        assert_eq!(window.try_take(&(late_instant + one_second)), None);
+        assert_eq!(window.try_take(&(late_instant + one_second)), Some(Duration::from_millis(200)));
+        assert_eq!(window.try_take(&(boundary_instant + one_second + half_second)), None);
+    }
+
+    #[test]
+    fn test_zero_sized_window_always_waits() {
+        let one_second = Duration::from_secs(1);
+        let mut window = SlidingWindow::new(0, one_second);
+
+        assert_eq!(window.try_take(&Instant::now()), Some(one_second));
+    }
+
+    #[test]
+    fn test_carried_over_permits_expire_when_taken() {
+        let one_minute = Duration::from_secs(MINUTE_IN_SECONDS);
+        let half_minute = one_minute / 2;
+        let mut previous_window = SlidingWindow::new(3, one_minute);
+        let start_instant = Instant::now();
+        let reload_instant = start_instant + half_minute;
+
+        assert_eq!(previous_window.try_take(&start_instant), None);
+        assert_eq!(previous_window.try_take(&reload_instant), None);
+
+        let mut window = SlidingWindow::new(2, one_minute);
+        let taken_instants = previous_window.taken_instants(&reload_instant);
+
+        window.take_used(2, &taken_instants, &reload_instant);
+
+        assert_eq!(window.remaining(&reload_instant), 0);
+        assert_eq!(window.try_take(&reload_instant), Some(half_minute));
+        // The first permit expires a minute after it was taken, not after the reload
+        assert_eq!(window.try_take(&(start_instant + one_minute)), None);
+        assert_eq!(window.remaining(&(reload_instant + one_minute)), 1);
+    }
+
+    #[test]
+    fn test_carry_over_without_instants_counts_from_now() {
+        let one_minute = Duration::from_secs(MINUTE_IN_SECONDS);
+        let mut window = SlidingWindow::new(3, one_minute);
+        let current_instant = Instant::now();
+
+        window.take_used(2, &[], &current_instant);
+
+        assert_eq!(window.remaining(&current_instant), 1);
+        assert_eq!(window.remaining(&(current_instant + one_minute)), 3);
+    }
+}
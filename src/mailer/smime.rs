@@ -0,0 +1,241 @@
+use super::mime::split_entity;
+use crate::config::SmimeConfig;
+use crate::messages::MessageDraft;
+use openssl::asn1::Asn1Time;
+use openssl::pkcs7::{Pkcs7, Pkcs7Flags};
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::symm::Cipher;
+use openssl::x509::X509;
+use std::fs::read;
+use std::path::Path;
+
+/// Wraps composed messages into S/MIME signed and/or enveloped entities (RFC 8551).
+pub struct SmimeComposer {
+    signer: Option<(X509, PKey<Private>)>,
+    cert_dir: Option<String>,
+}
+
+impl SmimeComposer {
+    pub fn new(smime_config: &SmimeConfig) -> Self {
+        Self {
+            signer: smime_config
+                .signer
+                .as_ref()
+                .map(|signer| (signer.certificate.clone(), signer.private_key.clone())),
+            cert_dir: smime_config.cert_dir.clone(),
+        }
+    }
+
+    /// Signs first so the signature is encrypted along with the content.
+    pub fn compose(&self, draft: &MessageDraft, raw_email: &[u8]) -> Result<Vec<u8>, String> {
+        let (mut smime_email, mut entity) = split_entity(raw_email);
+
+        if draft.smime_sign {
+            entity = self.sign_entity(&entity)?;
+        }
+
+        if draft.smime_encrypt {
+            let recipient_certificate = self.find_recipient_certificate(draft)?;
+            entity = encrypt_entity(&entity, recipient_certificate)?;
+        }
+
+        smime_email.extend(entity);
+
+        Ok(smime_email)
+    }
+
+    fn sign_entity(&self, entity: &[u8]) -> Result<Vec<u8>, String> {
+        let (certificate, private_key) = match self.signer.as_ref() {
+            None => return Err("S/MIME signing is not configured!".into()),
+            Some(signer) => signer,
+        };
+        let flags = Pkcs7Flags::DETACHED | Pkcs7Flags::BINARY;
+        let extra_certificates = Stack::new().map_err(|e| e.to_string())?;
+        let signed_data = Pkcs7::sign(certificate, private_key, &extra_certificates, entity, flags)
+            .map_err(|e| format!("Cannot sign S/MIME message: {}", e))?;
+        let signed_entity = signed_data
+            .to_smime(entity, flags | Pkcs7Flags::CRLFEOL)
+            .map_err(|e| format!("Cannot sign S/MIME message: {}", e))?;
+
+        Ok(to_crlf(&signed_entity))
+    }
+
+    /// The draft certificate takes precedence over `<cert dir>/<recipient>.pem`.
+    fn find_recipient_certificate(&self, draft: &MessageDraft) -> Result<X509, String> {
+        let recipient = draft.email_to.to_lowercase();
+        let certificate_pem = match (draft.smime_recipient_cert.as_ref(), self.cert_dir.as_ref()) {
+            (Some(certificate_pem), _) => certificate_pem.clone().into_bytes(),
+            (None, Some(cert_dir)) if !recipient.contains(|c| c == '/' || c == '\\') => {
+                match read(Path::new(cert_dir).join(format!("{}.pem", recipient))) {
+                    Err(_) => return Err(format!("No S/MIME certificate for {}!", recipient)),
+                    Ok(certificate_pem) => certificate_pem,
+                }
+            }
+            _ => return Err(format!("No S/MIME certificate for {}!", recipient)),
+        };
+        let certificate = match X509::from_pem(&certificate_pem) {
+            Err(_) => return Err(format!("Invalid S/MIME certificate for {}!", recipient)),
+            Ok(certificate) => certificate,
+        };
+        let now = Asn1Time::days_from_now(0).map_err(|e| e.to_string())?;
+
+        if certificate.not_after() < now {
+            return Err(format!("S/MIME certificate for {} has expired!", recipient));
+        }
+
+        if certificate.not_before() > now {
+            return Err(format!("S/MIME certificate for {} is not valid yet!", recipient));
+        }
+
+        Ok(certificate)
+    }
+}
+
+fn encrypt_entity(entity: &[u8], recipient_certificate: X509) -> Result<Vec<u8>, String> {
+    let mut recipient_certificates = Stack::new().map_err(|e| e.to_string())?;
+    recipient_certificates.push(recipient_certificate).map_err(|e| e.to_string())?;
+
+    let enveloped_data =
+        Pkcs7::encrypt(&recipient_certificates, entity, Cipher::aes_256_cbc(), Pkcs7Flags::BINARY)
+            .map_err(|e| format!("Cannot encrypt S/MIME message: {}", e))?;
+    let enveloped_entity = enveloped_data
+        .to_smime(&[], Pkcs7Flags::CRLFEOL)
+        .map_err(|e| format!("Cannot encrypt S/MIME message: {}", e))?;
+
+    Ok(to_crlf(&enveloped_entity))
+}
+
+/// OpenSSL ends base64 lines with a bare LF, which SMTP does not allow.
+fn to_crlf(data: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(data.len() + data.len() / 64);
+
+    for (index, byte) in data.iter().enumerate() {
+        if *byte == b'\n' && (index == 0 || data[index - 1] != b'\r') {
+            converted.push(b'\r');
+        }
+
+        converted.push(*byte);
+    }
+
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailer::mime::split_message;
+    use openssl::asn1::Asn1Integer;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::rsa::Rsa;
+    use openssl::x509::store::X509StoreBuilder;
+    use openssl::x509::X509Name;
+
+    const RAW_EMAIL: &[u8] = b"From: noreply@example.com\r\n\
+        To: admin@example.com\r\n\
+        Subject: Statement\r\n\
+        MIME-Version: 1.0\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        \r\n\
+        Hello!! This is your statement.\r\n";
+
+    fn create_certificate(not_after: &Asn1Time) -> (X509, PKey<Private>) {
+        let private_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_text("CN", "admin@example.com").unwrap();
+        let name = name.build();
+        let serial_number = Asn1Integer::from_bn(&BigNum::from_u32(1).unwrap()).unwrap();
+        let mut certificate = X509::builder().unwrap();
+        certificate.set_version(2).unwrap();
+        certificate.set_serial_number(&serial_number).unwrap();
+        certificate.set_subject_name(&name).unwrap();
+        certificate.set_issuer_name(&name).unwrap();
+        certificate.set_pubkey(&private_key).unwrap();
+        certificate.set_not_before(&Asn1Time::from_unix(0).unwrap()).unwrap();
+        certificate.set_not_after(not_after).unwrap();
+        certificate.sign(&private_key, MessageDigest::sha256()).unwrap();
+
+        (certificate.build(), private_key)
+    }
+
+    fn create_draft(smime_sign: bool, smime_encrypt: bool) -> MessageDraft {
+        MessageDraft { smime_sign, smime_encrypt, ..MessageDraft::default() }
+    }
+
+    fn get_entity(smime_email: &[u8]) -> &[u8] {
+        let (message_headers, _) = split_entity(RAW_EMAIL);
+
+        assert!(smime_email.starts_with(&message_headers));
+
+        &smime_email[message_headers.len()..]
+    }
+
+    #[test]
+    fn test_sign_message() {
+        let (certificate, private_key) = create_certificate(&Asn1Time::days_from_now(1).unwrap());
+        let composer =
+            SmimeComposer { signer: Some((certificate.clone(), private_key)), cert_dir: None };
+        let smime_email = composer.compose(&create_draft(true, false), RAW_EMAIL).unwrap();
+        let (signed_data, content) = Pkcs7::from_smime(get_entity(&smime_email)).unwrap();
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(certificate).unwrap();
+
+        assert!(!smime_email.windows(2).any(|window| window[1] == b'\n' && window[0] != b'\r'));
+        assert!(signed_data
+            .verify(
+                &Stack::new().unwrap(),
+                &store.build(),
+                content.as_deref(),
+                None,
+                Pkcs7Flags::BINARY
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_encrypt_message_to_draft_certificate() {
+        let (certificate, private_key) = create_certificate(&Asn1Time::days_from_now(1).unwrap());
+        let composer = SmimeComposer { signer: None, cert_dir: None };
+        let mut draft = create_draft(false, true);
+        draft.smime_recipient_cert =
+            Some(String::from_utf8(certificate.to_pem().unwrap()).unwrap());
+        let smime_email = composer.compose(&draft, RAW_EMAIL).unwrap();
+        let (enveloped_data, _) = Pkcs7::from_smime(get_entity(&smime_email)).unwrap();
+        let entity = enveloped_data.decrypt(&private_key, &certificate, Pkcs7Flags::empty());
+        let (_, body) = split_message(RAW_EMAIL);
+
+        assert!(entity.unwrap().ends_with(body));
+    }
+
+    #[test]
+    fn test_reject_missing_or_invalid_certificate() {
+        let (certificate, _) = create_certificate(&Asn1Time::from_unix(1).unwrap());
+        let composer = SmimeComposer { signer: None, cert_dir: Some("/nonexistent".into()) };
+        let mut draft = create_draft(false, true);
+
+        assert_eq!(
+            composer.compose(&draft, RAW_EMAIL),
+            Err("No S/MIME certificate for admin@example.com!".into())
+        );
+
+        draft.smime_recipient_cert = Some("-----BEGIN CERTIFICATE-----".into());
+        assert_eq!(
+            composer.compose(&draft, RAW_EMAIL),
+            Err("Invalid S/MIME certificate for admin@example.com!".into())
+        );
+
+        draft.smime_recipient_cert =
+            Some(String::from_utf8(certificate.to_pem().unwrap()).unwrap());
+        assert_eq!(
+            composer.compose(&draft, RAW_EMAIL),
+            Err("S/MIME certificate for admin@example.com has expired!".into())
+        );
+
+        let composer = SmimeComposer { signer: None, cert_dir: None };
+        assert_eq!(
+            composer.compose(&create_draft(true, false), RAW_EMAIL),
+            Err("S/MIME signing is not configured!".into())
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use super::rate_limiter::RateLimiter;
+use tokio::time::{Duration, Instant};
+
+/// Smooth token bucket (GCRA), refills one token every `bucket_interval / bucket_size`.
+pub struct TokenBucket {
+    emission_interval: Option<Duration>,
+    burst_tolerance: Duration,
+    theoretical_arrival: Option<Instant>,
+    bucket_interval: Duration,
+}
+
+impl TokenBucket {
+    pub fn new(bucket_size: usize, bucket_interval: Duration) -> Self {
+        let mut emission_interval = None;
+        let mut burst_tolerance = Duration::from_secs(0);
+
+        if bucket_size > 0 {
+            let single_emission = bucket_interval / bucket_size as u32;
+
+            emission_interval = Some(single_emission);
+            burst_tolerance = single_emission * (bucket_size - 1) as u32;
+        }
+
+        Self { emission_interval, burst_tolerance, bucket_interval, theoretical_arrival: None }
+    }
+}
+
+impl RateLimiter for TokenBucket {
+    fn try_take(&mut self, current_instant: &Instant) -> Option<Duration> {
+        let emission_interval = match self.emission_interval {
+            Some(emission_interval) => emission_interval,
+            None => return Some(self.bucket_interval),
+        };
+        let theoretical_arrival = match self.theoretical_arrival {
+            Some(arrival) if arrival > *current_instant => arrival,
+            _ => *current_instant,
+        };
+        let time_ahead = theoretical_arrival - *current_instant;
+
+        if time_ahead > self.burst_tolerance {
+            Some(time_ahead - self.burst_tolerance)
+        } else {
+            self.theoretical_arrival = Some(theoretical_arrival + emission_interval);
+
+            None
+        }
+    }
+
+    fn remaining(&self, current_instant: &Instant) -> usize {
+        let emission_interval = match self.emission_interval {
+            Some(emission_interval) => emission_interval,
+            None => return 0,
+        };
+        let time_ahead = match self.theoretical_arrival {
+            Some(arrival) if arrival > *current_instant => arrival - *current_instant,
+            _ => Duration::from_secs(0),
+        };
+
+        if time_ahead > self.burst_tolerance {
+            return 0;
+        }
+
+        ((self.burst_tolerance - time_ahead).as_nanos() / emission_interval.as_nanos()) as usize + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailer::HOUR_IN_SECONDS;
+    use tokio::time::{Duration, Instant};
+
+    #[test]
+    fn test_return_false_on_exhausted_bucket() {
+        let one_hour = Duration::from_secs(HOUR_IN_SECONDS);
+        let mut bucket = TokenBucket::new(1, one_hour);
+        let current_instant = Instant::now();
+
+        assert_eq!(bucket.try_take(&current_instant), None);
+        assert_eq!(bucket.try_take(&current_instant), Some(one_hour));
+        assert_eq!(bucket.try_take(&(current_instant + one_hour)), None);
+    }
+
+    #[test]
+    fn test_refill_is_smooth_after_burst() {
+        let one_second = Duration::from_secs(1);
+        let quarter_second = Duration::from_millis(250);
+        let mut bucket = TokenBucket::new(4, one_second);
+        let start_instant = Instant::now();
+
+        for _ in 0..4 {
+            assert_eq!(bucket.try_take(&start_instant), None);
+        }
+
+        assert_eq!(bucket.try_take(&start_instant), Some(quarter_second));
+        assert_eq!(bucket.try_take(&(start_instant + quarter_second)), None);
+        assert_eq!(bucket.try_take(&(start_instant + quarter_second)), Some(quarter_second));
+    }
+
+    #[test]
+    fn test_remaining_follows_refill() {
+        let one_second = Duration::from_secs(1);
+        let quarter_second = Duration::from_millis(250);
+        let mut bucket = TokenBucket::new(4, one_second);
+        let start_instant = Instant::now();
+
+        assert_eq!(bucket.remaining(&start_instant), 4);
+        assert_eq!(bucket.try_take(&start_instant), None);
+        assert_eq!(bucket.remaining(&start_instant), 3);
+
+        for _ in 0..3 {
+            assert_eq!(bucket.try_take(&start_instant), None);
+        }
+
+        assert_eq!(bucket.remaining(&start_instant), 0);
+        assert_eq!(bucket.remaining(&(start_instant + quarter_second)), 1);
+        assert_eq!(bucket.remaining(&(start_instant + one_second)), 4);
+    }
+
+    #[test]
+    fn test_idle_bucket_does_not_exceed_size() {
+        let one_second = Duration::from_secs(1);
+        let mut bucket = TokenBucket::new(2, one_second);
+        let idle_instant = Instant::now() + Duration::from_secs(10);
+
+        assert_eq!(bucket.try_take(&idle_instant), None);
+        assert_eq!(bucket.try_take(&idle_instant), None);
+        assert_eq!(bucket.try_take(&idle_instant), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_zero_sized_bucket_always_waits() {
+        let one_second = Duration::from_secs(1);
+        let mut bucket = TokenBucket::new(0, one_second);
+
+        assert_eq!(bucket.try_take(&Instant::now()), Some(one_second));
+    }
+}
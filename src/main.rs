@@ -1,33 +1,89 @@
+mod bounce;
+mod cli;
 mod config;
+mod consumer;
+mod control;
+mod email_address;
+mod health;
+mod http;
+mod ingestion;
+mod logging;
 mod mailer;
 mod messages;
+mod metrics;
+mod suppression_list;
+mod telemetry;
+mod tracking;
 mod utils;
 
 pub use log::{debug, error, info, log, warn};
 
 use anyhow::{anyhow as anyerror, Result as AnyResult};
-use bytes::Bytes;
-use config::{MQConfig, MailerConfig};
+use bounce::BounceProcessor;
+use cli::{Command, USAGE};
+use config::{ConfigSource, IngestionMode, LogConfig, MQConfig, MailerConfig};
+use consumer::{DraftEmailConsumer, DraftLane, DraftRequeuer};
+use control::Control;
+use futures::executor::block_on;
+use health::{Health, HealthServer};
+use ingestion::{IngestionDelivery, IngestionServer};
+use logging::init_logger;
 use mailer::{EmailSendingResult, Mailer};
-use messages::{MessageDraft, MessageFail, MessageFailType};
-use std::sync::atomic::AtomicBool;
+use messages::{
+    MessageControl, MessageControlCommand, MessageControlReply, MessageDraftPriority,
+    MessageSuppression,
+};
+use metrics::{Metrics, MetricsServer};
+use std::env::args;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use suppression_list::SuppressionList;
 use tapa_cgloop_nats::{CGLoop, NatsMessage, NatsMessageHandler, NatsOptions, ProcessResult};
 use tapa_trait_serde::IJsonSerializable;
-use tokio::runtime::Runtime;
+use telemetry::Tracer;
+use tokio::signal::unix::Signal;
+use tokio::sync::Mutex;
+use tokio::task::spawn_blocking;
+use tokio::time::delay_for;
 use tokio::{join as wait_for_all, main as async_main};
-use utils::{init_logger, wait_for_stop_signals};
+use tracking::{EngagementTracker, TrackingServer};
+use utils::{create_reload_signals, wait_for_reload_signal, wait_for_stop_signals};
 
-fn create_nats_options(instance_name: &str) -> NatsOptions {
-    NatsOptions::new().max_reconnects(None).with_name(instance_name)
+fn create_nats_options(instance_name: &str, metrics: &Metrics) -> NatsOptions {
+    let metrics = metrics.clone();
+
+    NatsOptions::new()
+        .max_reconnects(None)
+        .with_name(instance_name)
+        .reconnect_callback(move || metrics.record_nats_reconnect())
 }
 
-fn create_cg_loop(mq_config: &MQConfig) -> CGLoop {
+/// Only the consumer connections report NATS connectivity to `health`.
+fn create_consumer_nats_options(
+    instance_name: &str,
+    metrics: &Metrics,
+    health: &Health,
+) -> NatsOptions {
+    let metrics = metrics.clone();
+    let reconnect_health = health.clone();
+    let disconnect_health = health.clone();
+
+    NatsOptions::new()
+        .max_reconnects(None)
+        .with_name(instance_name)
+        .reconnect_callback(move || {
+            metrics.record_nats_reconnect();
+            reconnect_health.set_nats_connected(true);
+        })
+        .disconnect_callback(move || disconnect_health.set_nats_connected(false))
+}
+
+fn create_cg_loop(mq_config: &MQConfig, mq_topic_source: &str) -> CGLoop {
     CGLoop::new(
         &mq_config.mq_url,
-        &mq_config.mq_topic_source,
+        mq_topic_source,
         &mq_config.mq_topic_success,
         &mq_config.mq_topic_failure,
         &mq_config.mq_consumer_group,
@@ -36,66 +92,217 @@ fn create_cg_loop(mq_config: &MQConfig) -> CGLoop {
     )
 }
 
-struct DraftEmailConsumer {
-    mailer: Mailer,
-    config: MailerConfig,
-    async_runtime: Runtime,
+fn create_draft_requeuer(
+    mq_url: &str,
+    mq_topic_source: &str,
+    nats_options: NatsOptions,
+) -> AnyResult<DraftRequeuer> {
+    let connection = nats_options.connect(mq_url)?;
+    let mq_topic_source = mq_topic_source.to_string();
+
+    Ok(Arc::new(move |draft_bytes| {
+        connection.publish(&mq_topic_source, draft_bytes)?;
+
+        Ok(())
+    }))
 }
 
 impl NatsMessageHandler for DraftEmailConsumer {
     fn handle_message(&mut self, message: &NatsMessage) -> AnyResult<ProcessResult> {
-        let ten_seconds = Duration::from_secs(10);
-        let service_instance_name = &self.config.instance_name;
-
-        if let Ok(message_draft) = MessageDraft::from_json_bytes(&message.data[..]) {
-            debug!("Got new message draft: {}", message_draft.to_json_string_pretty());
-
-            loop {
-                let retry_draft = message_draft.clone();
-
-                match self.async_runtime.block_on(self.mailer.compose_and_send(
-                    None,
-                    service_instance_name,
-                    retry_draft,
-                )) {
-                    EmailSendingResult::Fail(message_fail) => match &message_fail.fail_reason {
-                        MessageFailType::Unknown => {
-                            return Err(anyerror!("MessageFailType::Unknown should never occur!"));
-                        }
-                        MessageFailType::QuotaExhausted(duration_to_wait, error_string) => {
-                            warn!("{}", error_string);
-                            sleep(*duration_to_wait);
-                            continue;
-                        }
-                        MessageFailType::Other(reason) | MessageFailType::BadDraft(reason) => {
-                            error!("{}", reason);
-                            let message_fail = Bytes::from(message_fail.to_json_bytes_pretty());
-                            sleep(ten_seconds);
-                            return Ok(ProcessResult::Failure(message_fail));
-                        }
-                    },
-                    EmailSendingResult::Sent(message_success) => {
-                        let message_success = Bytes::from(message_success.to_json_bytes_pretty());
+        self.handle_draft(&message.data[..])
+    }
+}
+
+/// Every instance keeps its own suppression list, so commands are consumed by all instances
+/// instead of once per consumer group.
+fn run_suppression_control(
+    mq_url: &str,
+    mq_topic_suppression: &str,
+    nats_options: NatsOptions,
+    suppression_list: SuppressionList,
+    shutdown_flag: Arc<AtomicBool>,
+) -> AnyResult<()> {
+    let connection = nats_options.connect(mq_url)?;
+    let subscription = connection.subscribe(mq_topic_suppression)?;
+
+    while !shutdown_flag.load(Ordering::Relaxed) {
+        if let Ok(message) = subscription.next_timeout(Duration::from_secs(1)) {
+            match MessageSuppression::from_json_bytes(&message.data[..]) {
+                Err(e) => error!("Cannot parse suppression command: {}", e),
+                Ok(message_suppression) => {
+                    let action = message_suppression.action;
+                    let email_address = message_suppression.email_address.clone();
 
-                        return Ok(ProcessResult::Success(message_success));
+                    match suppression_list.apply(message_suppression) {
+                        Err(e) => error!("Cannot apply suppression command: {}", e),
+                        Ok(_) => info!("Suppression {:?} applied to {}", action, email_address),
                     }
                 }
             }
-        } else {
-            let error_message = format!(
-                "Cannot parse to correct JSON format, draft message length is {}",
-                message.data.len()
-            );
-            error!("{}", error_message);
-            let message_fail = MessageFail::new(
-                None,
-                service_instance_name,
-                error_message.clone(),
-                MessageFailType::BadDraft(error_message),
-            );
-            let message_fail = Bytes::from(message_fail.to_json_bytes_pretty());
-
-            Ok(ProcessResult::Failure(message_fail))
+        }
+    }
+
+    Ok(())
+}
+
+/// Every addressed instance answers on the reply subject of a command, so a request with
+/// several replies gathers the state of the whole consumer group.
+fn run_control(
+    mq_url: &str,
+    mq_topic_control: &str,
+    nats_options: NatsOptions,
+    instance_name: &str,
+    control: Control,
+    mailer: Arc<Mutex<Mailer>>,
+    shutdown_flag: Arc<AtomicBool>,
+) -> AnyResult<()> {
+    let connection = nats_options.connect(mq_url)?;
+    let subscription = connection.subscribe(mq_topic_control)?;
+
+    while !shutdown_flag.load(Ordering::Relaxed) {
+        if let Ok(message) = subscription.next_timeout(Duration::from_secs(1)) {
+            let message_control = match MessageControl::from_json_bytes(&message.data[..]) {
+                Err(e) => {
+                    error!("Cannot parse control command: {}", e);
+                    continue;
+                }
+                Ok(message_control) => message_control,
+            };
+
+            if let Some(addressed_instance_name) = message_control.instance_name.as_ref() {
+                if addressed_instance_name != instance_name {
+                    continue;
+                }
+            }
+
+            let message_control_reply =
+                apply_control(&message_control, instance_name, &control, &mailer);
+
+            if message.reply.is_some() {
+                if let Err(e) = message.respond(message_control_reply.to_json_bytes_pretty()) {
+                    error!("Cannot reply to control command: {}", e);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_control(
+    message_control: &MessageControl,
+    instance_name: &str,
+    control: &Control,
+    mailer: &Mutex<Mailer>,
+) -> MessageControlReply {
+    let mut mailer = block_on(mailer.lock());
+    let mut message_control_reply =
+        MessageControlReply::new(instance_name, message_control.command);
+
+    match message_control.command {
+        MessageControlCommand::Pause => control.pause(),
+        MessageControlCommand::Resume => control.resume(),
+        MessageControlCommand::Drain => control.drain(),
+        MessageControlCommand::SetQuota => match message_control.quota_period {
+            None => message_control_reply.error = Some("SET_QUOTA needs a quota_period!".into()),
+            Some(quota_period) => {
+                mailer.set_quota(quota_period, message_control.quota_max);
+                control.notify_quota_changed();
+            }
+        },
+        MessageControlCommand::Status => {}
+    }
+
+    match message_control_reply.error.as_ref() {
+        Some(error) => warn!("Control command {:?} rejected: {}", message_control.command, error),
+        None => info!("Control command {:?} applied", message_control.command),
+    }
+
+    message_control_reply.paused = control.is_paused();
+    message_control_reply.draining = control.is_draining();
+    message_control_reply.in_flight = control.in_flight();
+    message_control_reply.quota_buckets = mailer.quota_state();
+
+    message_control_reply
+}
+
+fn run_bounce_processor(
+    mq_url: &str,
+    mq_topic_bounce: &str,
+    nats_options: NatsOptions,
+    mut bounce_processor: BounceProcessor,
+    shutdown_flag: Arc<AtomicBool>,
+) -> AnyResult<()> {
+    let connection = nats_options.connect(mq_url)?;
+    let mut next_poll = Instant::now();
+
+    while !shutdown_flag.load(Ordering::Relaxed) {
+        if Instant::now() >= next_poll {
+            if let Err(e) = bounce_processor.poll(|message_bounce| {
+                debug!("Got new bounce: {}", message_bounce.to_json_string_pretty());
+                connection.publish(mq_topic_bounce, message_bounce.to_json_bytes_pretty())?;
+
+                Ok(())
+            }) {
+                warn!("Cannot process the bounce mailbox: {}", e);
+            }
+
+            next_poll = Instant::now() + bounce_processor.poll_interval();
+        }
+
+        sleep(Duration::from_secs(1));
+    }
+
+    Ok(())
+}
+
+async fn run_smtp_check(
+    mailer: Arc<Mutex<Mailer>>,
+    health: Health,
+    check_interval: Duration,
+    shutdown_flag: Arc<AtomicBool>,
+) {
+    let mut next_check = Instant::now();
+
+    while !shutdown_flag.load(Ordering::Relaxed) {
+        if Instant::now() >= next_check {
+            // The mailer is only held to share its delivery, not while the server answers
+            let delivery = mailer.lock().await.delivery();
+            let smtp_reachable = delivery.is_reachable().await;
+
+            health.set_smtp_reachable(smtp_reachable);
+            next_check = Instant::now() + check_interval;
+        }
+
+        delay_for(Duration::from_secs(1)).await;
+    }
+}
+
+/// Invalid config is rejected as a whole and the current one is kept.
+async fn run_config_reload(
+    mailer: Arc<Mutex<Mailer>>,
+    control: Control,
+    mut reload_signals: Signal,
+    shutdown_flag: Arc<AtomicBool>,
+) {
+    while wait_for_reload_signal(&mut reload_signals, shutdown_flag.clone()).await {
+        info!("Got SIGHUP, reloading config");
+
+        // The log config is only read so that its keys are known, the logger is kept
+        let reloaded_config = ConfigSource::load().and_then(|config_source| {
+            LogConfig::load(&config_source);
+            MailerConfig::load(&config_source)
+        });
+
+        match reloaded_config {
+            Err(e) => error!("Rejected the reloaded config, keeping the current one: {}", e),
+            Ok(config) => match mailer.lock().await.reload(&config) {
+                Err(e) => error!("Cannot apply the reloaded config: {}", e),
+                Ok(_) => {
+                    control.notify_quota_changed();
+                    info!("Reloaded Mailer Config:\n{:#?}", config);
+                }
+            },
         }
     }
 }
@@ -103,32 +310,360 @@ impl NatsMessageHandler for DraftEmailConsumer {
 async fn run_mailer(config: MailerConfig) -> AnyResult<()> {
     let shutdown_flag = Arc::new(AtomicBool::new(false));
     let shutdown_flag_clone = shutdown_flag.clone();
-    let cg_loop = create_cg_loop(&config.mq_config);
-    let nats_options = create_nats_options(&config.instance_name);
-    let mailer = Mailer::new(&config.smtp_config)?;
-    let message_handler =
-        Box::new(DraftEmailConsumer { config, mailer, async_runtime: Runtime::new()? });
+    let priority_shutdown_flag = shutdown_flag.clone();
+    let suppression_shutdown_flag = shutdown_flag.clone();
+    let control_shutdown_flag = shutdown_flag.clone();
+    let bounce_shutdown_flag = shutdown_flag.clone();
+    let tracking_shutdown_flag = shutdown_flag.clone();
+    let ingestion_shutdown_flag = shutdown_flag.clone();
+    let metrics_shutdown_flag = shutdown_flag.clone();
+    let health_shutdown_flag = shutdown_flag.clone();
+    let smtp_check_shutdown_flag = shutdown_flag.clone();
+    let exporter_shutdown_flag = shutdown_flag.clone();
+    let reload_shutdown_flag = shutdown_flag.clone();
+    let reload_signals = create_reload_signals()?;
+    let metrics = Metrics::new()?;
+    let (tracer, otlp_exporter) = Tracer::new(config.telemetry_config.as_ref());
+    let exporter_instance_name = config.instance_name.clone();
+    let health = Health::new();
+    let control = Control::new(shutdown_flag.clone());
+    let reload_control = control.clone();
+    let mq_config = &config.mq_config;
+    let cg_loop = create_cg_loop(mq_config, &mq_config.mq_topic_source);
+    let nats_options = create_consumer_nats_options(&config.instance_name, &metrics, &health);
+    let suppression_list = SuppressionList::open(&config.suppression_config)?;
+    let engagement_tracker =
+        config.tracking_config.as_ref().map(EngagementTracker::new).transpose()?;
+    let mailer = Arc::new(Mutex::new(
+        Mailer::new(
+            &config,
+            suppression_list.clone(),
+            engagement_tracker.clone(),
+            metrics.clone(),
+            tracer.clone(),
+        )
+        .await?,
+    ));
+    let default_lane = DraftLane::new(
+        None,
+        create_draft_requeuer(
+            &mq_config.mq_url,
+            &mq_config.mq_topic_source,
+            create_nats_options(&config.instance_name, &metrics),
+        )?,
+    );
+    let default_deferral_control = control.clone();
+    let message_handler = Box::new(DraftEmailConsumer::new(
+        mailer.clone(),
+        &config.instance_name,
+        default_lane.clone(),
+        metrics.clone(),
+        health.clone(),
+        tracer.clone(),
+        control.clone(),
+    )?);
+    let mut priority_lane = None;
+    let mut suppression_control = None;
+    let mut runtime_control = None;
+    let mut bounce_processing = None;
+    let mut tracking = None;
+    let mut ingestion = None;
+    let health_listen_addr =
+        config.health_config.as_ref().map(|health_config| health_config.listen_addr);
+    let mut metrics_serving = None;
+    let mut shared_metrics_server = None;
 
-    let results = wait_for_all! {
+    // Metrics share the listener of the health endpoints when both are on the same address
+    if let Some(metrics_config) = config.metrics_config.as_ref() {
+        let metrics_server = MetricsServer::new(metrics.clone());
+
+        if Some(metrics_config.listen_addr) == health_listen_addr {
+            shared_metrics_server = Some(metrics_server);
+        } else {
+            metrics_serving = Some((metrics_server, metrics_config.listen_addr));
+        }
+    }
+
+    let health_serving = config.health_config.as_ref().map(|health_config| {
+        (
+            HealthServer::new(health.clone(), shared_metrics_server),
+            health_config.listen_addr,
+            health_config.smtp_check_interval,
+        )
+    });
+
+    if let Some(ingestion_config) = config.ingestion_config.as_ref() {
+        let connection =
+            create_nats_options(&config.instance_name, &metrics).connect(&mq_config.mq_url)?;
+        let ingestion_delivery = match ingestion_config.mode {
+            IngestionMode::Queue => {
+                let mq_topic_source = mq_config.mq_topic_source.clone();
+                let mq_topic_source_priority = mq_config.mq_topic_source_priority.clone();
+
+                IngestionDelivery::Queue(Box::new(move |message_draft| {
+                    let mq_topic = match (message_draft.priority, &mq_topic_source_priority) {
+                        (MessageDraftPriority::High, Some(mq_topic_source_priority)) => {
+                            mq_topic_source_priority
+                        }
+                        _ => &mq_topic_source,
+                    };
+
+                    connection.publish(mq_topic, message_draft.to_json_bytes_pretty())?;
+
+                    Ok(())
+                }))
+            }
+            IngestionMode::Send => {
+                let mq_topic_success = mq_config.mq_topic_success.clone();
+                let mq_topic_failure = mq_config.mq_topic_failure.clone();
+
+                IngestionDelivery::Send(
+                    mailer.clone(),
+                    Box::new(move |email_sending_result| {
+                        match email_sending_result {
+                            EmailSendingResult::Sent(message_success) => connection.publish(
+                                &mq_topic_success,
+                                message_success.to_json_bytes_pretty(),
+                            )?,
+                            EmailSendingResult::Fail(message_fail) => connection
+                                .publish(&mq_topic_failure, message_fail.to_json_bytes_pretty())?,
+                        }
+
+                        Ok(())
+                    }),
+                )
+            }
+        };
+
+        ingestion = Some((
+            IngestionServer::new(
+                ingestion_config,
+                mailer.lock().await.draft_validator(),
+                &config.instance_name,
+                ingestion_delivery,
+            ),
+            ingestion_config.listen_addr,
+        ));
+    }
+
+    // High priority drafts get their own consumer, so they never queue behind the bulk backlog
+    if let Some(mq_topic_source_priority) = mq_config.mq_topic_source_priority.as_ref() {
+        let lane = DraftLane::new(
+            Some(MessageDraftPriority::High),
+            create_draft_requeuer(
+                &mq_config.mq_url,
+                mq_topic_source_priority,
+                create_nats_options(&config.instance_name, &metrics),
+            )?,
+        );
+
+        priority_lane = Some((
+            create_cg_loop(mq_config, mq_topic_source_priority),
+            create_consumer_nats_options(&config.instance_name, &metrics, &health),
+            lane.clone(),
+            control.clone(),
+            Box::new(DraftEmailConsumer::new(
+                mailer.clone(),
+                &config.instance_name,
+                lane,
+                metrics.clone(),
+                health.clone(),
+                tracer,
+                control.clone(),
+            )?),
+        ));
+    }
+
+    if let (Some(bounce_config), Some(mq_topic_bounce)) =
+        (config.bounce_config, mq_config.mq_topic_bounce.as_ref())
+    {
+        bounce_processing = Some((
+            mq_config.mq_url.clone(),
+            mq_topic_bounce.clone(),
+            create_nats_options(&config.instance_name, &metrics),
+            BounceProcessor::new(bounce_config, suppression_list.clone(), &config.instance_name),
+        ));
+    }
+
+    if let (Some(engagement_tracker), Some(tracking_config), Some(mq_topic_engagement)) =
+        (engagement_tracker, config.tracking_config.as_ref(), mq_config.mq_topic_engagement.clone())
+    {
+        let connection =
+            create_nats_options(&config.instance_name, &metrics).connect(&mq_config.mq_url)?;
+        let tracking_server = TrackingServer::new(
+            engagement_tracker,
+            &config.instance_name,
+            Box::new(move |message_engagement| {
+                connection
+                    .publish(&mq_topic_engagement, message_engagement.to_json_bytes_pretty())?;
+
+                Ok(())
+            }),
+        );
+
+        tracking = Some((tracking_server, tracking_config.listen_addr));
+    }
+
+    if let Some(mq_topic_suppression) = mq_config.mq_topic_suppression.as_ref() {
+        suppression_control = Some((
+            mq_config.mq_url.clone(),
+            mq_topic_suppression.clone(),
+            create_nats_options(&config.instance_name, &metrics),
+            suppression_list,
+        ));
+    }
+
+    if let Some(mq_topic_control) = mq_config.mq_topic_control.as_ref() {
+        runtime_control = Some((
+            mq_config.mq_url.clone(),
+            mq_topic_control.clone(),
+            create_nats_options(&config.instance_name, &metrics),
+            config.instance_name.clone(),
+            control,
+            mailer.clone(),
+        ));
+    }
+
+    wait_for_all! {
+        async move {
+            let deferral =
+                spawn_blocking(move || default_lane.run_deferral(&default_deferral_control));
+
+            cg_loop.run(nats_options, shutdown_flag_clone, message_handler).await.unwrap();
+            deferral.await.unwrap();
+        },
+        async move {
+            if let Some((cg_loop, nats_options, lane, deferral_control, message_handler)) =
+                priority_lane
+            {
+                let deferral = spawn_blocking(move || lane.run_deferral(&deferral_control));
+
+                cg_loop.run(nats_options, priority_shutdown_flag, message_handler).await.unwrap();
+                deferral.await.unwrap();
+            }
+        },
         async move {
-            cg_loop.run(nats_options, shutdown_flag_clone, message_handler).await
+            if let Some((mq_url, mq_topic_suppression, nats_options, suppression_list)) =
+                suppression_control
+            {
+                spawn_blocking(move || {
+                    run_suppression_control(
+                        &mq_url,
+                        &mq_topic_suppression,
+                        nats_options,
+                        suppression_list,
+                        suppression_shutdown_flag,
+                    )
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            }
+        },
+        async move {
+            if let Some((mq_url, mq_topic_control, nats_options, instance_name, control, mailer)) =
+                runtime_control
+            {
+                spawn_blocking(move || {
+                    run_control(
+                        &mq_url,
+                        &mq_topic_control,
+                        nats_options,
+                        &instance_name,
+                        control,
+                        mailer,
+                        control_shutdown_flag,
+                    )
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            }
+        },
+        async move {
+            if let Some((mq_url, mq_topic_bounce, nats_options, bounce_processor)) =
+                bounce_processing
+            {
+                spawn_blocking(move || {
+                    run_bounce_processor(
+                        &mq_url,
+                        &mq_topic_bounce,
+                        nats_options,
+                        bounce_processor,
+                        bounce_shutdown_flag,
+                    )
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            }
+        },
+        async move {
+            if let Some((tracking_server, listen_addr)) = tracking {
+                tracking_server.run(listen_addr, tracking_shutdown_flag).await.unwrap();
+            }
+        },
+        async move {
+            if let Some((ingestion_server, listen_addr)) = ingestion {
+                ingestion_server.run(listen_addr, ingestion_shutdown_flag).await.unwrap();
+            }
+        },
+        async move {
+            if let Some((metrics_server, listen_addr)) = metrics_serving {
+                metrics_server.run(listen_addr, metrics_shutdown_flag).await.unwrap();
+            }
+        },
+        run_config_reload(mailer.clone(), reload_control, reload_signals, reload_shutdown_flag),
+        async move {
+            if let Some((health_server, listen_addr, smtp_check_interval)) = health_serving {
+                let smtp_check = run_smtp_check(
+                    mailer,
+                    health,
+                    smtp_check_interval,
+                    smtp_check_shutdown_flag,
+                );
+                let (serving_result, _) = wait_for_all!(
+                    health_server.run(listen_addr, health_shutdown_flag),
+                    smtp_check
+                );
+
+                serving_result.unwrap();
+            }
+        },
+        async move {
+            if let Some(otlp_exporter) = otlp_exporter {
+                otlp_exporter.run(exporter_instance_name, exporter_shutdown_flag).await;
+            }
         },
         async move {
             wait_for_stop_signals(shutdown_flag).await
         }
     };
 
-    results.0.unwrap();
-
     Ok(())
 }
 
 #[async_main]
 async fn main() -> AnyResult<()> {
-    init_logger();
+    let command = Command::parse(args().skip(1))?;
+    let config_source = ConfigSource::load()?;
 
-    let mailer_config = MailerConfig::load_from_env()?;
-    info!("Mailer Config:\n{:#?}", mailer_config);
+    init_logger(&LogConfig::load(&config_source));
 
-    run_mailer(mailer_config).await
+    match command {
+        Command::Run => {
+            let mailer_config = MailerConfig::load(&config_source)?;
+            info!("Mailer Config:\n{:#?}", mailer_config);
+
+            run_mailer(mailer_config).await
+        }
+        Command::Send(draft_path) => cli::send_draft(&config_source, &draft_path).await,
+        Command::Validate(draft_paths) => cli::validate_drafts(&config_source, &draft_paths),
+        Command::CheckConfig => cli::check_config(&config_source),
+        Command::SmtpTest => cli::smtp_test(&config_source).await,
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,45 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use tapa_trait_serde::IJsonSerializable;
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, IJsonSerializable)]
+pub enum MessageBounceType {
+    #[serde(rename = "PERMANENT")]
+    Permanent,
+    #[serde(rename = "TRANSIENT")]
+    Transient,
+}
+
+/// Delivery failure reported asynchronously by a delivery status notification (RFC 3464).
+#[derive(Deserialize, Serialize, Clone, IJsonSerializable)]
+pub struct MessageBounce {
+    pub service_instance_name: String,
+    pub draft_id: Option<Uuid>,
+    pub email_address: String,
+    pub bounce_type: MessageBounceType,
+    pub status: String,
+    pub diagnostic_code: Option<String>,
+    pub timestamp: DateTime<FixedOffset>,
+}
+
+impl MessageBounce {
+    pub fn new(
+        service_instance_name: &str,
+        draft_id: Option<Uuid>,
+        email_address: String,
+        bounce_type: MessageBounceType,
+        status: String,
+        diagnostic_code: Option<String>,
+    ) -> Self {
+        Self {
+            service_instance_name: service_instance_name.into(),
+            draft_id,
+            email_address,
+            bounce_type,
+            status,
+            diagnostic_code,
+            timestamp: Utc::now().into(),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use tapa_trait_serde::IJsonSerializable;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, IJsonSerializable)]
+pub enum MessageControlCommand {
+    #[serde(rename = "PAUSE")]
+    Pause,
+    #[serde(rename = "RESUME")]
+    Resume,
+    #[serde(rename = "DRAIN")]
+    Drain,
+    #[serde(rename = "SET_QUOTA")]
+    SetQuota,
+    #[serde(rename = "STATUS")]
+    Status,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, IJsonSerializable)]
+pub enum QuotaPeriod {
+    #[serde(rename = "SECOND")]
+    Second,
+    #[serde(rename = "MINUTE")]
+    Minute,
+    #[serde(rename = "HOUR")]
+    Hour,
+    #[serde(rename = "DAY")]
+    Day,
+}
+
+/// Runtime command consumed from the control topic, by every instance unless `instance_name`
+/// picks one of them.
+#[derive(Deserialize, Serialize, Clone, IJsonSerializable)]
+pub struct MessageControl {
+    pub command: MessageControlCommand,
+    pub instance_name: Option<String>,
+    /// Quota changed by `SET_QUOTA`.
+    pub quota_period: Option<QuotaPeriod>,
+    /// New maximum of `quota_period`, unlimited when missing.
+    pub quota_max: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, IJsonSerializable)]
+pub struct QuotaBucketState {
+    pub period: QuotaPeriod,
+    pub max: usize,
+    pub remaining: usize,
+}
+
+/// Answer of one instance to a `MessageControl`, sent to its reply subject.
+#[derive(Deserialize, Serialize, Clone, IJsonSerializable)]
+pub struct MessageControlReply {
+    pub service_instance_name: String,
+    pub command: MessageControlCommand,
+    pub error: Option<String>,
+    pub paused: bool,
+    pub draining: bool,
+    pub in_flight: usize,
+    pub quota_buckets: Vec<QuotaBucketState>,
+    pub timestamp: DateTime<FixedOffset>,
+}
+
+impl MessageControlReply {
+    pub fn new(service_instance_name: &str, command: MessageControlCommand) -> Self {
+        Self {
+            command,
+            service_instance_name: service_instance_name.into(),
+            error: None,
+            paused: false,
+            draining: false,
+            in_flight: 0,
+            quota_buckets: Vec::new(),
+            timestamp: Utc::now().into(),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::utils::is_valid_email_string;
+use crate::email_address::EmailAddress;
 use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
 use tapa_trait_serde::IJsonSerializable;
@@ -12,6 +12,20 @@ pub enum MessageDraftBodyType {
     Html,
 }
 
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, IJsonSerializable)]
+pub enum MessageDraftPriority {
+    #[serde(rename = "HIGH")]
+    High,
+    #[serde(rename = "NORMAL")]
+    Normal,
+}
+
+impl Default for MessageDraftPriority {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, IJsonSerializable)]
 pub struct MessageDraft {
     pub id: Uuid,
@@ -22,16 +36,31 @@ pub struct MessageDraft {
     pub subject: String,
     pub body_type: MessageDraftBodyType,
     pub body: String,
+    #[serde(default)]
+    pub priority: MessageDraftPriority,
+    #[serde(default)]
+    pub smime_sign: bool,
+    #[serde(default)]
+    pub smime_encrypt: bool,
+    pub smime_recipient_cert: Option<String>,
+    #[serde(default)]
+    pub pgp_encrypt: bool,
+    #[serde(default)]
+    pub pgp_allow_plaintext: bool,
+    #[serde(default)]
+    pub track_engagement: bool,
+    /// W3C `traceparent` of the producer, the send is traced as its child.
+    pub trace_context: Option<String>,
     pub timestamp: DateTime<FixedOffset>,
 }
 
 impl MessageDraft {
-    pub fn has_invalid_sender(&self) -> bool {
-        !is_valid_email_string(&self.email_from)
+    pub fn parse_sender(&self) -> Result<EmailAddress, String> {
+        EmailAddress::parse(&self.email_from)
     }
 
-    pub fn has_invalid_destination(&self) -> bool {
-        !is_valid_email_string(&self.email_to)
+    pub fn parse_destination(&self) -> Result<EmailAddress, String> {
+        EmailAddress::parse(&self.email_to)
     }
 
     pub fn has_empty_body(&self) -> bool {
@@ -42,3 +71,29 @@ impl MessageDraft {
         self.subject.is_empty()
     }
 }
+
+/// A valid draft, tests override the fields they are about.
+#[cfg(test)]
+impl Default for MessageDraft {
+    fn default() -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            email_to: "admin@example.com".into(),
+            email_to_name: Some("Tapalogi Administrator".into()),
+            email_from: "noreply@example.com".into(),
+            email_from_name: Some("Tapalogi System".into()),
+            subject: "Tapa Micro Mailer - Test".into(),
+            body_type: MessageDraftBodyType::Ascii,
+            body: "Hello!! This is from example.com".into(),
+            priority: MessageDraftPriority::Normal,
+            smime_sign: false,
+            smime_encrypt: false,
+            smime_recipient_cert: None,
+            pgp_encrypt: false,
+            pgp_allow_plaintext: false,
+            track_engagement: false,
+            trace_context: None,
+            timestamp: chrono::Utc::now().into(),
+        }
+    }
+}
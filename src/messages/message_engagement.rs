@@ -0,0 +1,42 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use tapa_trait_serde::IJsonSerializable;
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, IJsonSerializable)]
+pub enum MessageEngagementType {
+    #[serde(rename = "OPEN")]
+    Open,
+    #[serde(rename = "CLICK")]
+    Click,
+}
+
+/// Open or click recorded by the tracking endpoint, `url` is the clicked link.
+#[derive(Deserialize, Serialize, Clone, IJsonSerializable)]
+pub struct MessageEngagement {
+    pub service_instance_name: String,
+    pub draft_id: Uuid,
+    pub engagement_type: MessageEngagementType,
+    pub url: Option<String>,
+    pub user_agent: Option<String>,
+    pub timestamp: DateTime<FixedOffset>,
+}
+
+impl MessageEngagement {
+    pub fn new(
+        service_instance_name: &str,
+        draft_id: Uuid,
+        engagement_type: MessageEngagementType,
+        url: Option<String>,
+        user_agent: Option<String>,
+    ) -> Self {
+        Self {
+            service_instance_name: service_instance_name.into(),
+            draft_id,
+            engagement_type,
+            url,
+            user_agent,
+            timestamp: Utc::now().into(),
+        }
+    }
+}
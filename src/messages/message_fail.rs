@@ -11,16 +11,39 @@ pub enum MessageFailType {
     BadDraft(String),
     #[serde(rename = "QUOTA_EXHAUSTED")]
     QuotaExhausted(Duration, String),
+    #[serde(rename = "DOMAIN_THROTTLED")]
+    DomainThrottled(Duration, String),
+    #[serde(rename = "UNDELIVERABLE_DOMAIN")]
+    UndeliverableDomain(String),
+    #[serde(rename = "SUPPRESSED")]
+    Suppressed(String),
     #[serde(rename = "UNKNOWN")]
     Unknown, // This kind of error should not exist
 }
 
+impl MessageFailType {
+    /// Same as the serialized variant name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Other(_) => "OTHER",
+            Self::BadDraft(_) => "BAD_DRAFT",
+            Self::QuotaExhausted(..) => "QUOTA_EXHAUSTED",
+            Self::DomainThrottled(..) => "DOMAIN_THROTTLED",
+            Self::UndeliverableDomain(_) => "UNDELIVERABLE_DOMAIN",
+            Self::Suppressed(_) => "SUPPRESSED",
+            Self::Unknown => "UNKNOWN",
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, IJsonSerializable)]
 pub struct MessageFail {
     pub origin_offset: Option<i64>,
     pub service_instance_name: String,
     pub message_copy: String,
     pub fail_reason: MessageFailType,
+    /// W3C `traceparent` of the send, so consumers can continue the trace.
+    pub trace_context: Option<String>,
     pub timestamp: DateTime<FixedOffset>,
 }
 
@@ -36,6 +59,7 @@ impl MessageFail {
             service_instance_name: service_instance_name.into(),
             message_copy,
             fail_reason,
+            trace_context: None,
             timestamp: Utc::now().into(),
         }
     }
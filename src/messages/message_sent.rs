@@ -8,15 +8,25 @@ pub struct MessageSent {
     pub origin_offset: Option<i64>,
     pub service_instance_name: String,
     pub draft_id: Uuid,
+    pub message_id: String,
+    /// W3C `traceparent` of the send, so consumers can continue the trace.
+    pub trace_context: Option<String>,
     pub timestamp: DateTime<FixedOffset>,
 }
 
 impl MessageSent {
-    pub fn new(origin_offset: Option<i64>, service_instance_name: &str, draft_id: Uuid) -> Self {
+    pub fn new(
+        origin_offset: Option<i64>,
+        service_instance_name: &str,
+        draft_id: Uuid,
+        message_id: String,
+    ) -> Self {
         Self {
             origin_offset,
             draft_id,
+            message_id,
             service_instance_name: service_instance_name.into(),
+            trace_context: None,
             timestamp: Utc::now().into(),
         }
     }
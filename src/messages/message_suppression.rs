@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use tapa_trait_serde::IJsonSerializable;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, IJsonSerializable)]
+pub enum SuppressionReason {
+    #[serde(rename = "HARD_BOUNCE")]
+    HardBounce,
+    #[serde(rename = "UNSUBSCRIBED")]
+    Unsubscribed,
+    #[serde(rename = "COMPLAINT")]
+    Complaint,
+    #[serde(rename = "MANUAL")]
+    Manual,
+}
+
+impl Default for SuppressionReason {
+    fn default() -> Self {
+        Self::Manual
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, IJsonSerializable)]
+pub enum MessageSuppressionAction {
+    #[serde(rename = "ADD")]
+    Add,
+    #[serde(rename = "REMOVE")]
+    Remove,
+}
+
+/// Manual change to the suppression list, consumed from the suppression control topic.
+#[derive(Deserialize, Serialize, Clone, IJsonSerializable)]
+pub struct MessageSuppression {
+    pub action: MessageSuppressionAction,
+    pub email_address: String,
+    #[serde(default)]
+    pub reason: SuppressionReason,
+    pub detail: Option<String>,
+}
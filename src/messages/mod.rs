@@ -1,7 +1,17 @@
+mod message_bounce;
+mod message_control;
 mod message_draft;
+mod message_engagement;
 mod message_fail;
 mod message_sent;
+mod message_suppression;
 
-pub use message_draft::{MessageDraft, MessageDraftBodyType};
+pub use message_bounce::{MessageBounce, MessageBounceType};
+pub use message_control::{
+    MessageControl, MessageControlCommand, MessageControlReply, QuotaBucketState, QuotaPeriod,
+};
+pub use message_draft::{MessageDraft, MessageDraftBodyType, MessageDraftPriority};
+pub use message_engagement::{MessageEngagement, MessageEngagementType};
 pub use message_fail::{MessageFail, MessageFailType};
 pub use message_sent::MessageSent;
+pub use message_suppression::{MessageSuppression, MessageSuppressionAction, SuppressionReason};
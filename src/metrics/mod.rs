@@ -0,0 +1,148 @@
+mod server;
+
+pub use server::MetricsServer;
+
+use crate::messages::MessageFailType;
+use crate::AnyResult;
+use prometheus::{
+    Counter, Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::time::Duration;
+
+/// SMTP round trips go from a few milliseconds on a local relay up to the transport timeout.
+const SMTP_SEND_BUCKETS: [f64; 12] =
+    [0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Handle to every metric of the mailer, cheap to clone and share between tasks.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    drafts_consumed: IntCounterVec,
+    drafts_sent: IntCounter,
+    drafts_failed: IntCounterVec,
+    smtp_send_duration: Histogram,
+    quota_remaining: IntGaugeVec,
+    quota_wait: Counter,
+    nats_reconnects: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> AnyResult<Self> {
+        let registry = Registry::new();
+        let drafts_consumed = IntCounterVec::new(
+            Opts::new(
+                "mailer_drafts_consumed_total",
+                "Drafts consumed from NATS with a result, by lane.",
+            ),
+            &["lane"],
+        )?;
+        let drafts_sent = IntCounter::new("mailer_drafts_sent_total", "Drafts sent.")?;
+        let drafts_failed = IntCounterVec::new(
+            Opts::new("mailer_drafts_failed_total", "Failed sending attempts, by fail type."),
+            &["fail_type"],
+        )?;
+        let smtp_send_duration = Histogram::with_opts(
+            HistogramOpts::new("mailer_smtp_send_duration_seconds", "SMTP send latency.")
+                .buckets(SMTP_SEND_BUCKETS.to_vec()),
+        )?;
+        let quota_remaining = IntGaugeVec::new(
+            Opts::new("mailer_quota_remaining", "Permits left in each global quota bucket."),
+            &["bucket"],
+        )?;
+        let quota_wait = Counter::new(
+            "mailer_quota_wait_seconds_total",
+            "Time consumers spent waiting for an exhausted quota.",
+        )?;
+        let nats_reconnects =
+            IntCounter::new("mailer_nats_reconnects_total", "Reconnections to NATS.")?;
+
+        registry.register(Box::new(drafts_consumed.clone()))?;
+        registry.register(Box::new(drafts_sent.clone()))?;
+        registry.register(Box::new(drafts_failed.clone()))?;
+        registry.register(Box::new(smtp_send_duration.clone()))?;
+        registry.register(Box::new(quota_remaining.clone()))?;
+        registry.register(Box::new(quota_wait.clone()))?;
+        registry.register(Box::new(nats_reconnects.clone()))?;
+
+        Ok(Self {
+            registry,
+            drafts_consumed,
+            drafts_sent,
+            drafts_failed,
+            smtp_send_duration,
+            quota_remaining,
+            quota_wait,
+            nats_reconnects,
+        })
+    }
+
+    pub fn record_consumed(&self, lane: &str) {
+        self.drafts_consumed.with_label_values(&[lane]).inc();
+    }
+
+    pub fn record_sent(&self) {
+        self.drafts_sent.inc();
+    }
+
+    pub fn record_failed(&self, fail_type: &MessageFailType) {
+        self.drafts_failed.with_label_values(&[fail_type.name()]).inc();
+    }
+
+    pub fn observe_smtp_send(&self, duration: Duration) {
+        self.smtp_send_duration.observe(duration.as_secs_f64());
+    }
+
+    pub fn set_quota_remaining(&self, bucket: &str, remaining: usize) {
+        self.quota_remaining.with_label_values(&[bucket]).set(remaining as i64);
+    }
+
+    pub fn record_quota_wait(&self, duration: Duration) {
+        self.quota_wait.inc_by(duration.as_secs_f64());
+    }
+
+    pub fn record_nats_reconnect(&self) {
+        self.nats_reconnects.inc();
+    }
+
+    /// Prometheus text exposition format.
+    pub fn encode(&self) -> AnyResult<Vec<u8>> {
+        let mut buffer = Vec::new();
+
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_metrics() {
+        let metrics = Metrics::new().unwrap();
+
+        metrics.record_consumed("priority");
+        metrics.record_sent();
+        metrics.record_failed(&MessageFailType::QuotaExhausted(Duration::from_secs(1), "".into()));
+        metrics.record_failed(&MessageFailType::Suppressed("".into()));
+        metrics.observe_smtp_send(Duration::from_millis(30));
+        metrics.set_quota_remaining("day", 199);
+        metrics.record_quota_wait(Duration::from_millis(1500));
+        metrics.record_nats_reconnect();
+
+        let exposition = String::from_utf8(metrics.encode().unwrap()).unwrap();
+
+        assert!(exposition.contains("mailer_drafts_consumed_total{lane=\"priority\"} 1\n"));
+        assert!(exposition.contains("mailer_drafts_sent_total 1\n"));
+        assert!(
+            exposition.contains("mailer_drafts_failed_total{fail_type=\"QUOTA_EXHAUSTED\"} 1\n")
+        );
+        assert!(exposition.contains("mailer_drafts_failed_total{fail_type=\"SUPPRESSED\"} 1\n"));
+        assert!(exposition.contains("mailer_smtp_send_duration_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(exposition.contains("mailer_quota_remaining{bucket=\"day\"} 199\n"));
+        assert!(exposition.contains("mailer_quota_wait_seconds_total 1.5\n"));
+        assert!(exposition.contains("mailer_nats_reconnects_total 1\n"));
+    }
+}
@@ -0,0 +1,51 @@
+use super::Metrics;
+use crate::http::{create_empty_response, serve};
+use crate::{warn, AnyResult};
+use futures::future::ready;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use prometheus::{Encoder, TextEncoder};
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+const METRICS_PATH: &str = "/metrics";
+
+/// Serves `Metrics` to Prometheus on `GET /metrics`, every other request gets a 404.
+pub struct MetricsServer {
+    metrics: Metrics,
+}
+
+impl MetricsServer {
+    pub fn new(metrics: Metrics) -> Self {
+        Self { metrics }
+    }
+
+    pub async fn run(
+        self,
+        listen_addr: SocketAddr,
+        shutdown_flag: Arc<AtomicBool>,
+    ) -> AnyResult<()> {
+        let metrics_server = Arc::new(self);
+
+        serve(listen_addr, shutdown_flag, move |request| ready(metrics_server.handle(request)))
+            .await
+    }
+
+    pub fn handle(&self, request: Request<Body>) -> Response<Body> {
+        if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+            return create_empty_response(StatusCode::NOT_FOUND);
+        }
+
+        match self.metrics.encode() {
+            Err(e) => {
+                warn!("Cannot encode metrics: {}", e);
+                create_empty_response(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            Ok(exposition) => Response::builder()
+                .header(CONTENT_TYPE, TextEncoder::new().format_type())
+                .body(Body::from(exposition))
+                .unwrap(),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::config::SuppressionConfig;
+use crate::email_address::EmailAddress;
+use crate::messages::{MessageSuppression, MessageSuppressionAction, SuppressionReason};
+use crate::{anyerror, AnyResult};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use tapa_trait_serde::IJsonSerializable;
+
+/// SMTP reply codes for a mailbox that does not exist or cannot be used (RFC 5321 4.2.3).
+const RECIPIENT_REJECTION_CODES: [&str; 3] = ["550", "551", "553"];
+/// Enhanced statuses for a bad, moved or disabled destination mailbox, a bad destination system
+/// or a null MX (RFC 3463, RFC 7505). Other 5.1.x statuses, e.g. a bad sender address, do
+/// not mean the recipient is gone.
+const RECIPIENT_REJECTION_STATUSES: [&str; 6] =
+    ["5.1.1", "5.1.2", "5.1.3", "5.1.6", "5.1.10", "5.2.1"];
+
+#[derive(Deserialize, Serialize, Clone, Debug, IJsonSerializable)]
+pub struct SuppressionEntry {
+    pub reason: SuppressionReason,
+    pub detail: Option<String>,
+    pub timestamp: DateTime<FixedOffset>,
+}
+
+/// Addresses that must not be emailed again, kept in an embedded database so they survive
+/// restarts. Clones share the same database.
+#[derive(Clone)]
+pub struct SuppressionList {
+    db: Db,
+}
+
+impl SuppressionList {
+    pub fn open(suppression_config: &SuppressionConfig) -> AnyResult<Self> {
+        Ok(Self { db: sled::open(&suppression_config.db_path)? })
+    }
+
+    pub fn get(&self, email_address: &EmailAddress) -> AnyResult<Option<SuppressionEntry>> {
+        match self.db.get(suppression_key(email_address))? {
+            None => Ok(None),
+            Some(entry_bytes) => Ok(Some(SuppressionEntry::from_json_bytes(&entry_bytes)?)),
+        }
+    }
+
+    pub fn add(
+        &self,
+        email_address: &EmailAddress,
+        reason: SuppressionReason,
+        detail: Option<String>,
+    ) -> AnyResult<()> {
+        let entry = SuppressionEntry { reason, detail, timestamp: Utc::now().into() };
+
+        self.db.insert(suppression_key(email_address), entry.to_json_bytes())?;
+        self.db.flush()?;
+
+        Ok(())
+    }
+
+    /// Returns whether the address was suppressed.
+    pub fn remove(&self, email_address: &EmailAddress) -> AnyResult<bool> {
+        let removed = self.db.remove(suppression_key(email_address))?.is_some();
+        self.db.flush()?;
+
+        Ok(removed)
+    }
+
+    pub fn apply(&self, message_suppression: MessageSuppression) -> AnyResult<()> {
+        let email_address = EmailAddress::parse(&message_suppression.email_address)
+            .map_err(|reason| anyerror!("Invalid suppression address: {}", reason))?;
+
+        match message_suppression.action {
+            MessageSuppressionAction::Add => {
+                self.add(&email_address, message_suppression.reason, message_suppression.detail)
+            }
+            MessageSuppressionAction::Remove => self.remove(&email_address).map(|_| ()),
+        }
+    }
+}
+
+/// Local parts are matched case-insensitively, like nearly every mail server does.
+fn suppression_key(email_address: &EmailAddress) -> String {
+    email_address.to_string().to_lowercase()
+}
+
+/// Whether an SMTP rejection means the recipient mailbox itself is unusable, as opposed to a
+/// permanent rejection of the content or the sender. Enhanced status codes (RFC 3463) take
+/// precedence over the reply code, since servers also use 550 for policy rejections.
+pub fn is_recipient_rejection(reply_code: &str, reply_text: &str) -> bool {
+    let enhanced_status = reply_text.split_whitespace().next().unwrap_or_default();
+    let status_parts: Vec<&str> = enhanced_status.split('.').collect();
+    let is_enhanced_status = status_parts.len() == 3
+        && status_parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+
+    if is_enhanced_status {
+        return RECIPIENT_REJECTION_STATUSES.contains(&enhanced_status);
+    }
+
+    RECIPIENT_REJECTION_CODES.contains(&reply_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sled::Config as SledConfig;
+
+    fn create_suppression_list() -> SuppressionList {
+        SuppressionList { db: SledConfig::new().temporary(true).open().unwrap() }
+    }
+
+    #[test]
+    fn test_add_and_remove_addresses() {
+        let suppression_list = create_suppression_list();
+        let address = EmailAddress::parse("Admin@Example.com").unwrap();
+        let same_address = EmailAddress::parse("admin@example.COM").unwrap();
+
+        assert!(suppression_list.get(&address).unwrap().is_none());
+
+        suppression_list
+            .add(&address, SuppressionReason::HardBounce, Some("550 5.1.1 No such user".into()))
+            .unwrap();
+        let entry = suppression_list.get(&same_address).unwrap().unwrap();
+
+        assert_eq!(entry.reason, SuppressionReason::HardBounce);
+        assert_eq!(entry.detail.as_deref(), Some("550 5.1.1 No such user"));
+        assert!(suppression_list.remove(&same_address).unwrap());
+        assert!(!suppression_list.remove(&address).unwrap());
+        assert!(suppression_list.get(&address).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_apply_control_messages() {
+        let suppression_list = create_suppression_list();
+        let address = EmailAddress::parse("admin@example.com").unwrap();
+        let add = MessageSuppression::from_json_string(
+            r#"{"action": "ADD", "email_address": "admin@example.com", "detail": null}"#,
+        )
+        .unwrap();
+        let remove = MessageSuppression::from_json_string(
+            r#"{"action": "REMOVE", "email_address": "ADMIN@example.com", "detail": null}"#,
+        )
+        .unwrap();
+        let invalid = MessageSuppression::from_json_string(
+            r#"{"action": "ADD", "email_address": "admin", "detail": null}"#,
+        )
+        .unwrap();
+
+        suppression_list.apply(add).unwrap();
+        assert_eq!(
+            suppression_list.get(&address).unwrap().unwrap().reason,
+            SuppressionReason::Manual
+        );
+        suppression_list.apply(remove).unwrap();
+        assert!(suppression_list.get(&address).unwrap().is_none());
+        assert!(suppression_list.apply(invalid).is_err());
+    }
+
+    #[test]
+    fn test_classify_recipient_rejections() {
+        assert!(is_recipient_rejection("550", "5.1.1 <admin@example.com>: user unknown"));
+        assert!(is_recipient_rejection("550", "5.2.1 Mailbox disabled"));
+        assert!(is_recipient_rejection("553", "mailbox name not allowed"));
+        assert!(is_recipient_rejection("550", "Requested action not taken: mailbox unavailable"));
+        assert!(is_recipient_rejection("550", "5.1.10 Recipient address has null MX"));
+        assert!(!is_recipient_rejection("550", "5.7.1 Message rejected as spam"));
+        assert!(!is_recipient_rejection("550", "5.1.7 Bad sender's mailbox address syntax"));
+        assert!(!is_recipient_rejection("550", "5.1.8 Bad sender's system address"));
+        assert!(!is_recipient_rejection("552", "5.2.2 Mailbox full"));
+        assert!(!is_recipient_rejection("554", "Transaction failed"));
+    }
+}
@@ -0,0 +1,208 @@
+use super::{encode_hex, FinishedSpan, SpanKind};
+use crate::config::TelemetryConfig;
+use crate::{anyerror, debug, warn, AnyResult};
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Method, Request};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::timeout;
+
+const TRACES_PATH: &str = "/v1/traces";
+const MAX_BATCH_SIZE: usize = 512;
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// Batches finished spans and posts them as OTLP/HTTP JSON to the collector.
+pub struct OtlpExporter {
+    traces_url: String,
+    service_name: String,
+    export_interval: Duration,
+    span_receiver: UnboundedReceiver<FinishedSpan>,
+    http_client: Client<HttpConnector>,
+}
+
+impl OtlpExporter {
+    pub(super) fn new(
+        telemetry_config: &TelemetryConfig,
+        span_receiver: UnboundedReceiver<FinishedSpan>,
+    ) -> Self {
+        Self {
+            traces_url: format!("{}{}", telemetry_config.otlp_endpoint, TRACES_PATH),
+            service_name: telemetry_config.service_name.clone(),
+            export_interval: telemetry_config.export_interval,
+            span_receiver,
+            http_client: Client::new(),
+        }
+    }
+
+    /// Exports every `export_interval` or full batch, the last batch is flushed on shutdown.
+    pub async fn run(mut self, instance_name: String, shutdown_flag: Arc<AtomicBool>) {
+        let mut batch = Vec::with_capacity(MAX_BATCH_SIZE);
+
+        loop {
+            let is_shutdown = shutdown_flag.load(Ordering::Relaxed);
+
+            while batch.len() < MAX_BATCH_SIZE {
+                match timeout(self.export_interval, self.span_receiver.recv()).await {
+                    Ok(Some(finished_span)) => batch.push(finished_span),
+                    // Every tracer is gone or the interval has passed
+                    Ok(None) | Err(_) => break,
+                }
+            }
+
+            if !batch.is_empty() {
+                let export_request =
+                    create_export_request(&self.service_name, &instance_name, &batch);
+
+                match self.export(export_request).await {
+                    Err(e) => warn!("Cannot export {} spans: {}", batch.len(), e),
+                    Ok(_) => debug!("Exported {} spans", batch.len()),
+                }
+
+                batch.clear();
+            }
+
+            if is_shutdown {
+                break;
+            }
+        }
+    }
+
+    async fn export(&self, export_request: Value) -> AnyResult<()> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.traces_url.as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(export_request.to_string()))?;
+        let response = self.http_client.request(request).await?;
+
+        if !response.status().is_success() {
+            return Err(anyerror!("Collector answered {}", response.status()));
+        }
+
+        Ok(())
+    }
+}
+
+fn create_export_request(service_name: &str, instance_name: &str, batch: &[FinishedSpan]) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    create_attribute("service.name", service_name),
+                    create_attribute("service.instance.id", instance_name),
+                ]
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "spans": batch.iter().map(create_span).collect::<Vec<_>>()
+            }]
+        }]
+    })
+}
+
+fn create_span(finished_span: &FinishedSpan) -> Value {
+    let mut span = json!({
+        "traceId": encode_hex(&finished_span.context.trace_id),
+        "spanId": encode_hex(&finished_span.context.span_id),
+        "name": finished_span.name,
+        "kind": match finished_span.kind {
+            SpanKind::Internal => 1,
+            SpanKind::Client => 3,
+        },
+        "startTimeUnixNano": to_unix_nanos(finished_span.start_time),
+        "endTimeUnixNano": to_unix_nanos(finished_span.end_time),
+        "attributes": finished_span
+            .attributes
+            .iter()
+            .map(|(key, value)| create_attribute(key, value))
+            .collect::<Vec<_>>(),
+    });
+
+    if let Some(parent_span_id) = finished_span.parent_span_id.as_ref() {
+        span["parentSpanId"] = encode_hex(parent_span_id).into();
+    }
+
+    if let Some(error) = finished_span.error.as_ref() {
+        span["status"] = json!({ "code": STATUS_CODE_ERROR, "message": error });
+    }
+
+    span
+}
+
+fn create_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// OTLP JSON carries 64 bit integers as strings.
+fn to_unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{TraceContext, Tracer};
+    use super::*;
+    use hyper::body::to_bytes;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server};
+    use std::convert::Infallible;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[tokio::test]
+    async fn test_export_to_collector() {
+        let (request_sender, mut request_receiver) = unbounded_channel();
+        let make_service = make_service_fn(move |_| {
+            let request_sender = request_sender.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let request_sender = request_sender.clone();
+
+                    async move {
+                        let path = request.uri().path().to_string();
+                        let body = to_bytes(request.into_body()).await.unwrap();
+
+                        request_sender.send((path, body)).unwrap();
+                        Ok::<_, Infallible>(Response::new(Body::empty()))
+                    }
+                }))
+            }
+        });
+        let collector = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let telemetry_config = TelemetryConfig {
+            otlp_endpoint: format!("http://{}", collector.local_addr()),
+            service_name: "tapa-micro-mailer".into(),
+            export_interval: Duration::from_millis(10),
+        };
+        let (tracer, exporter) = Tracer::new(Some(&telemetry_config));
+        let parent =
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        let mut span = tracer.start_span("smtp_send", SpanKind::Client, Some(&parent));
+
+        span.set_error("Connection refused");
+        drop(span);
+        tokio::spawn(collector);
+        tokio::spawn(
+            exporter.unwrap().run("MAILER-TEST_host".into(), Arc::new(AtomicBool::new(true))),
+        );
+
+        let (path, body) = request_receiver.recv().await.unwrap();
+        let export_request: Value = serde_json::from_slice(&body).unwrap();
+        let resource_spans = &export_request["resourceSpans"][0];
+        let span = &resource_spans["scopeSpans"][0]["spans"][0];
+
+        assert_eq!(path, TRACES_PATH);
+        assert_eq!(
+            resource_spans["resource"]["attributes"][1]["value"]["stringValue"],
+            "MAILER-TEST_host"
+        );
+        assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(span["kind"], 3);
+        assert_eq!(span["status"]["code"], STATUS_CODE_ERROR);
+    }
+}
@@ -0,0 +1,241 @@
+mod exporter;
+
+pub use exporter::OtlpExporter;
+
+use crate::config::TelemetryConfig;
+use std::time::SystemTime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use uuid::Uuid;
+
+/// W3C trace context of a span, carried as a `traceparent` like
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let mut fields = traceparent.trim().split('-');
+        let version = fields.next()?;
+        let mut trace_id = [0; 16];
+        let mut span_id = [0; 8];
+
+        if version.len() != 2 || version == "ff" {
+            return None;
+        }
+
+        decode_hex(fields.next()?, &mut trace_id)?;
+        decode_hex(fields.next()?, &mut span_id)?;
+
+        let mut flags = [0; 1];
+        decode_hex(fields.next()?, &mut flags)?;
+
+        // Future versions may append fields, version 00 must not
+        if (version == "00" && fields.next().is_some()) || trace_id == [0; 16] || span_id == [0; 8]
+        {
+            return None;
+        }
+
+        Some(Self { trace_id, span_id, sampled: flags[0] & 1 == 1 })
+    }
+
+    pub fn to_traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            encode_hex(&self.trace_id),
+            encode_hex(&self.span_id),
+            self.sampled as u8
+        )
+    }
+
+    fn new_root() -> Self {
+        Self { trace_id: *Uuid::new_v4().as_bytes(), span_id: new_span_id(), sampled: true }
+    }
+
+    fn new_child(&self) -> Self {
+        Self { span_id: new_span_id(), ..*self }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    Internal,
+    Client,
+}
+
+#[derive(Debug, Clone)]
+pub struct FinishedSpan {
+    pub name: &'static str,
+    pub kind: SpanKind,
+    pub context: TraceContext,
+    pub parent_span_id: Option<[u8; 8]>,
+    pub start_time: SystemTime,
+    pub end_time: SystemTime,
+    pub attributes: Vec<(&'static str, String)>,
+    pub error: Option<String>,
+}
+
+/// Ends, and is queued for export, when dropped.
+pub struct Span {
+    span_sender: Option<UnboundedSender<FinishedSpan>>,
+    finished_span: FinishedSpan,
+}
+
+impl Span {
+    pub fn context(&self) -> &TraceContext {
+        &self.finished_span.context
+    }
+
+    pub fn set_attribute(&mut self, key: &'static str, value: impl ToString) {
+        self.finished_span.attributes.push((key, value.to_string()));
+    }
+
+    pub fn set_error(&mut self, message: impl ToString) {
+        self.finished_span.error = Some(message.to_string());
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(span_sender) = self.span_sender.as_ref() {
+            if self.finished_span.context.sampled {
+                let mut finished_span = self.finished_span.clone();
+                finished_span.end_time = SystemTime::now();
+
+                // The exporter is only gone once the mailer shuts down
+                let _ = span_sender.send(finished_span);
+            }
+        }
+    }
+}
+
+/// Creates spans, which are only exported when tracing is configured. Trace contexts are
+/// propagated either way.
+#[derive(Clone)]
+pub struct Tracer {
+    span_sender: Option<UnboundedSender<FinishedSpan>>,
+}
+
+impl Tracer {
+    pub fn new(telemetry_config: Option<&TelemetryConfig>) -> (Self, Option<OtlpExporter>) {
+        match telemetry_config {
+            None => (Self { span_sender: None }, None),
+            Some(telemetry_config) => {
+                let (span_sender, span_receiver) = unbounded_channel();
+
+                (
+                    Self { span_sender: Some(span_sender) },
+                    Some(OtlpExporter::new(telemetry_config, span_receiver)),
+                )
+            }
+        }
+    }
+
+    /// Starts a new trace without `parent`.
+    pub fn start_span(
+        &self,
+        name: &'static str,
+        kind: SpanKind,
+        parent: Option<&TraceContext>,
+    ) -> Span {
+        let context = parent.map_or_else(TraceContext::new_root, TraceContext::new_child);
+        let now = SystemTime::now();
+
+        Span {
+            span_sender: self.span_sender.clone(),
+            finished_span: FinishedSpan {
+                name,
+                kind,
+                context,
+                parent_span_id: parent.map(|parent| parent.span_id),
+                start_time: now,
+                end_time: now,
+                attributes: Vec::new(),
+                error: None,
+            },
+        }
+    }
+}
+
+fn new_span_id() -> [u8; 8] {
+    let mut span_id = [0; 8];
+    span_id.copy_from_slice(&Uuid::new_v4().as_bytes()[8..]);
+
+    span_id
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str, bytes: &mut [u8]) -> Option<()> {
+    if hex.len() != bytes.len() * 2 || !hex.is_ascii() {
+        return None;
+    }
+
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_traceparent() {
+        let trace_context = TraceContext::parse(TRACEPARENT).unwrap();
+
+        assert_eq!(trace_context.span_id, [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]);
+        assert!(trace_context.sampled);
+        assert_eq!(trace_context.to_traceparent(), TRACEPARENT);
+        assert_eq!(
+            TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-ext")
+                .map(|trace_context| trace_context.sampled),
+            Some(false)
+        );
+        assert_eq!(TraceContext::parse(&format!("{}-ext", TRACEPARENT)), None);
+        assert_eq!(
+            TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902-01"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_export_finished_spans() {
+        let (span_sender, mut span_receiver) = unbounded_channel();
+        let tracer = Tracer { span_sender: Some(span_sender) };
+        let parent = TraceContext::parse(TRACEPARENT).unwrap();
+        let mut span = tracer.start_span("smtp_send", SpanKind::Client, Some(&parent));
+
+        span.set_attribute("draft.id", "320b0555-4c73-4abf-aaf0-461b84860046");
+        span.set_error("Connection refused");
+
+        let context = *span.context();
+        drop(span);
+        drop(tracer.start_span(
+            "quota_wait",
+            SpanKind::Internal,
+            Some(&TraceContext { sampled: false, ..parent }),
+        ));
+
+        let finished_span = span_receiver.try_recv().unwrap();
+
+        assert_eq!(context.trace_id, parent.trace_id);
+        assert_ne!(context.span_id, parent.span_id);
+        assert_eq!(finished_span.parent_span_id, Some(parent.span_id));
+        assert_eq!(finished_span.error.as_deref(), Some("Connection refused"));
+        assert!(span_receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,246 @@
+mod server;
+
+pub use server::TrackingServer;
+
+use crate::config::TrackingConfig;
+use crate::AnyResult;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::{PKey, Private};
+use openssl::sign::Signer;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const OPEN_PATH: &str = "/open";
+const CLICK_PATH: &str = "/click";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Engagement {
+    Open { draft_id: Uuid },
+    Click { draft_id: Uuid, url: String },
+}
+
+/// Instruments HTML bodies with a tracking pixel and tracked links, each carrying the draft id
+/// and an HMAC token so the endpoint only records (and redirects to) what was actually sent.
+#[derive(Clone)]
+pub struct EngagementTracker {
+    base_url: String,
+    signing_key: PKey<Private>,
+}
+
+impl EngagementTracker {
+    /// The secret is read once, rotating it would break the links of every sent email.
+    pub fn new(tracking_config: &TrackingConfig) -> AnyResult<Self> {
+        Ok(Self {
+            base_url: tracking_config.base_url.clone(),
+            signing_key: PKey::hmac(tracking_config.secret.get().unsecure().as_bytes())?,
+        })
+    }
+
+    pub fn instrument(&self, draft_id: &Uuid, html: &str) -> String {
+        let mut html = self.rewrite_links(draft_id, html);
+        let tracking_pixel = format!(
+            "<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" style=\"border:0\">",
+            escape_attribute(&self.open_url(draft_id))
+        );
+
+        match html.to_ascii_lowercase().rfind("</body>") {
+            Some(body_end) => html.insert_str(body_end, &tracking_pixel),
+            None => html.push_str(&tracking_pixel),
+        }
+
+        html
+    }
+
+    /// Returns the engagement of a tracking request, `None` when it is unknown or forged.
+    pub fn resolve(&self, path: &str, query: Option<&str>) -> Option<Engagement> {
+        let params = parse_query(query.unwrap_or_default());
+        let draft_id = Uuid::parse_str(params.get("d")?).ok()?;
+        let token = params.get("t")?;
+
+        // The base URL may have a path of its own
+        if path.ends_with(OPEN_PATH) && self.verify(&[&draft_id.to_string()], token) {
+            return Some(Engagement::Open { draft_id });
+        }
+
+        if path.ends_with(CLICK_PATH) {
+            let url = params.get("u")?;
+
+            if is_trackable(url) && self.verify(&[&draft_id.to_string(), url], token) {
+                return Some(Engagement::Click { draft_id, url: url.clone() });
+            }
+        }
+
+        None
+    }
+
+    fn open_url(&self, draft_id: &Uuid) -> String {
+        let draft_id = draft_id.to_string();
+
+        format!("{}{}?d={}&t={}", self.base_url, OPEN_PATH, draft_id, self.sign(&[&draft_id]))
+    }
+
+    fn click_url(&self, draft_id: &Uuid, url: &str) -> String {
+        let draft_id = draft_id.to_string();
+
+        format!(
+            "{}{}?d={}&u={}&t={}",
+            self.base_url,
+            CLICK_PATH,
+            draft_id,
+            utf8_percent_encode(url, NON_ALPHANUMERIC),
+            self.sign(&[&draft_id, url])
+        )
+    }
+
+    /// Only `href` values of `<a>` tags are rewritten, stylesheets and the like stay untouched.
+    fn rewrite_links(&self, draft_id: &Uuid, html: &str) -> String {
+        let lowercase_html = html.to_ascii_lowercase();
+        let mut rewritten_html = String::with_capacity(html.len());
+        let mut copied_until = 0;
+        let mut search_from = 0;
+
+        while let Some(offset) = lowercase_html[search_from..].find("href=") {
+            let value_start = search_from + offset + 5;
+            search_from = value_start;
+
+            let quote = match html[value_start..].chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => quote,
+                _ => continue,
+            };
+            let url_start = value_start + 1;
+            let url_end = match html[url_start..].find(quote) {
+                None => break,
+                Some(url_length) => url_start + url_length,
+            };
+            let is_anchor = lowercase_html[..value_start]
+                .rfind('<')
+                .map_or(false, |tag_start| is_anchor_tag(&lowercase_html[tag_start..]));
+            let url = html[url_start..url_end].replace("&amp;", "&");
+
+            search_from = url_end;
+
+            if is_anchor && is_trackable(&url) {
+                rewritten_html.push_str(&html[copied_until..url_start]);
+                rewritten_html.push_str(&escape_attribute(&self.click_url(draft_id, &url)));
+                copied_until = url_end;
+            }
+        }
+
+        rewritten_html.push_str(&html[copied_until..]);
+        rewritten_html
+    }
+
+    fn sign(&self, fields: &[&str]) -> String {
+        // HMAC signing with an in-memory key cannot fail
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.signing_key).unwrap();
+        signer.update(fields.join("\n").as_bytes()).unwrap();
+
+        signer.sign_to_vec().unwrap().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn verify(&self, fields: &[&str], token: &str) -> bool {
+        let expected_token = self.sign(fields);
+
+        expected_token.len() == token.len()
+            && memcmp::eq(expected_token.as_bytes(), token.as_bytes())
+    }
+}
+
+fn is_anchor_tag(tag: &str) -> bool {
+    tag.starts_with("<a") && tag[2..].starts_with(|c: char| c.is_ascii_whitespace())
+}
+
+fn is_trackable(url: &str) -> bool {
+    let lowercase_url = url.to_ascii_lowercase();
+
+    lowercase_url.starts_with("http://") || lowercase_url.starts_with("https://")
+}
+
+fn escape_attribute(value: &str) -> String {
+    value.replace('&', "&amp;").replace('"', "&quot;")
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let equal_index = pair.find('=')?;
+            let value = percent_decode_str(&pair[equal_index + 1..]).decode_utf8().ok()?;
+
+            Some((pair[..equal_index].into(), value.into()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Secret;
+    use secstr::SecUtf8;
+    use std::net::SocketAddr;
+
+    pub(crate) fn create_tracker() -> EngagementTracker {
+        EngagementTracker::new(&TrackingConfig {
+            base_url: "https://t.example.com".into(),
+            secret: Secret::new(SecUtf8::from("secret")),
+            listen_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+        })
+        .unwrap()
+    }
+
+    fn extract_query<'a>(html: &'a str, prefix: &str) -> &'a str {
+        let query_start = html.find(prefix).unwrap() + prefix.len();
+        let query_end = query_start + html[query_start..].find(&['"', '\''][..]).unwrap();
+
+        &html[query_start..query_end]
+    }
+
+    #[test]
+    fn test_instrument_html() {
+        let tracker = create_tracker();
+        let draft_id = Uuid::parse_str("320b0555-4c73-4abf-aaf0-461b84860046").unwrap();
+        let html = tracker.instrument(
+            &draft_id,
+            "<html><head><link href=\"https://example.com/style.css\"></head><BODY>\
+            <A HREF='https://example.com/?a=1&amp;b=2'>Shop</A> \
+            <a href=\"mailto:admin@example.com\">Mail</a></body></html>",
+        );
+
+        assert!(html.contains("<link href=\"https://example.com/style.css\">"));
+        assert!(html.contains("<a href=\"mailto:admin@example.com\">"));
+        assert!(html.contains("<A HREF='https://t.example.com/click?d=320b0555-"));
+        assert!(html
+            .ends_with("\" width=\"1\" height=\"1\" alt=\"\" style=\"border:0\"></body></html>"));
+
+        let click_query =
+            extract_query(&html, "https://t.example.com/click?").replace("&amp;", "&");
+        let open_query = extract_query(&html, "https://t.example.com/open?").replace("&amp;", "&");
+
+        assert_eq!(
+            tracker.resolve(CLICK_PATH, Some(&click_query)),
+            Some(Engagement::Click { draft_id, url: "https://example.com/?a=1&b=2".into() })
+        );
+        assert_eq!(
+            tracker.resolve(OPEN_PATH, Some(&open_query)),
+            Some(Engagement::Open { draft_id })
+        );
+        assert_eq!(tracker.resolve(CLICK_PATH, Some(&open_query)), None);
+    }
+
+    #[test]
+    fn test_reject_forged_requests() {
+        let tracker = create_tracker();
+        let draft_id = Uuid::new_v4();
+        let click_url = tracker.click_url(&draft_id, "https://example.com/");
+        let query = &click_url[click_url.find('?').unwrap() + 1..];
+        let redirected_query = query.replace("example%2Ecom", "evil%2Eexample");
+        let forged_query = format!("d={}&t=00", draft_id);
+
+        assert!(tracker.resolve(CLICK_PATH, Some(query)).is_some());
+        assert_eq!(tracker.resolve(CLICK_PATH, Some(&redirected_query)), None);
+        assert_eq!(tracker.resolve(OPEN_PATH, Some(&forged_query)), None);
+        assert_eq!(tracker.resolve(OPEN_PATH, None), None);
+    }
+}
@@ -0,0 +1,154 @@
+use super::{Engagement, EngagementTracker};
+use crate::http::serve;
+use crate::messages::{MessageEngagement, MessageEngagementType};
+use crate::{warn, AnyResult};
+use futures::future::ready;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION, USER_AGENT};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Transparent 1x1 GIF.
+const TRACKING_PIXEL: [u8; 43] = [
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+pub type EngagementPublisher = Box<dyn Fn(&MessageEngagement) -> AnyResult<()> + Send + Sync>;
+
+/// Records opens and clicks of tracked emails, every other request gets a 404.
+pub struct TrackingServer {
+    tracker: EngagementTracker,
+    service_instance_name: String,
+    publisher: EngagementPublisher,
+}
+
+impl TrackingServer {
+    pub fn new(
+        tracker: EngagementTracker,
+        service_instance_name: &str,
+        publisher: EngagementPublisher,
+    ) -> Self {
+        Self { tracker, service_instance_name: service_instance_name.into(), publisher }
+    }
+
+    pub async fn run(
+        self,
+        listen_addr: SocketAddr,
+        shutdown_flag: Arc<AtomicBool>,
+    ) -> AnyResult<()> {
+        let tracking_server = Arc::new(self);
+
+        serve(listen_addr, shutdown_flag, move |request| ready(tracking_server.handle(request)))
+            .await
+    }
+
+    fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let engagement = match *request.method() {
+            Method::GET => self.tracker.resolve(request.uri().path(), request.uri().query()),
+            _ => None,
+        };
+        let user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let (draft_id, engagement_type, url, response) = match engagement {
+            None => return create_response(StatusCode::NOT_FOUND, None),
+            Some(Engagement::Open { draft_id }) => {
+                (draft_id, MessageEngagementType::Open, None, create_pixel_response())
+            }
+            Some(Engagement::Click { draft_id, url }) => {
+                let response = create_response(StatusCode::FOUND, Some(&url));
+
+                (draft_id, MessageEngagementType::Click, Some(url), response)
+            }
+        };
+        let message_engagement = MessageEngagement::new(
+            &self.service_instance_name,
+            draft_id,
+            engagement_type,
+            url,
+            user_agent,
+        );
+
+        if let Err(e) = (self.publisher)(&message_engagement) {
+            warn!("Cannot publish {:?} of draft {}: {}", engagement_type, draft_id, e);
+        }
+
+        response
+    }
+}
+
+fn create_response(status: StatusCode, location: Option<&str>) -> Response<Body> {
+    let mut response_builder = Response::builder().status(status);
+
+    if let Some(location) = location {
+        response_builder = response_builder.header(LOCATION, location);
+    }
+
+    // Only fails for a location that is not a valid header value
+    response_builder
+        .body(Body::empty())
+        .unwrap_or_else(|_| create_response(StatusCode::BAD_REQUEST, None))
+}
+
+fn create_pixel_response() -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "image/gif")
+        .header(CACHE_CONTROL, "no-store")
+        .body(Body::from(&TRACKING_PIXEL[..]))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracking::tests::create_tracker;
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    fn create_request(uri: &str) -> Request<Body> {
+        Request::get(uri).header(USER_AGENT, "Mozilla/5.0").body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_record_engagements() {
+        let tracker = create_tracker();
+        let draft_id = Uuid::new_v4();
+        let open_url = tracker.open_url(&draft_id);
+        let click_url = tracker.click_url(&draft_id, "https://example.com/?a=1");
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let published_clone = published.clone();
+        let tracking_server = TrackingServer::new(
+            tracker,
+            "MAILER-TEST",
+            Box::new(move |message_engagement: &MessageEngagement| {
+                published_clone.lock().unwrap().push(message_engagement.clone());
+                Ok(())
+            }),
+        );
+
+        let open_response = tracking_server.handle(create_request(&open_url));
+        let click_response = tracking_server.handle(create_request(&click_url));
+        let forged_response =
+            tracking_server.handle(create_request(&format!("/open?d={}&t=00", draft_id)));
+
+        assert_eq!(open_response.status(), StatusCode::OK);
+        assert_eq!(open_response.headers()[CONTENT_TYPE], "image/gif");
+        assert_eq!(click_response.status(), StatusCode::FOUND);
+        assert_eq!(click_response.headers()[LOCATION], "https://example.com/?a=1");
+        assert_eq!(forged_response.status(), StatusCode::NOT_FOUND);
+
+        let published = published.lock().unwrap();
+
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[0].engagement_type, MessageEngagementType::Open);
+        assert_eq!(published[0].user_agent.as_deref(), Some("Mozilla/5.0"));
+        assert_eq!(published[1].engagement_type, MessageEngagementType::Click);
+        assert_eq!(published[1].url.as_deref(), Some("https://example.com/?a=1"));
+        assert!(published.iter().all(|engagement| engagement.draft_id == draft_id));
+    }
+}
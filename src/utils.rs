@@ -1,22 +1,18 @@
+use crate::email_address::EmailAddress;
 use crate::AnyResult;
-use env_logger::builder as log_builder;
-use regex::Regex;
-use std::env::{set_var, var};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::select as wait_for_any;
-use tokio::signal::unix::{signal, SignalKind};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::time::{delay_for, Duration};
 
 pub(crate) const MINUTE_IN_SECONDS: u64 = 60;
 pub(crate) const HOUR_IN_SECONDS: u64 = 60 * MINUTE_IN_SECONDS;
 pub(crate) const DAY_IN_SECONDS: u64 = 24 * HOUR_IN_SECONDS;
 
-const REGEX_VALID_EMAIL: &str =
-    r"^([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})";
-const RUST_LOG: &str = "RUST_LOG";
-
-pub fn is_valid_email_string(email_string: &str) -> bool {
-    Regex::new(REGEX_VALID_EMAIL).unwrap().is_match(email_string)
+/// Returns the lowercased ASCII (punycode) domain part of an email address.
+pub fn get_email_domain(email_string: &str) -> Option<String> {
+    EmailAddress::parse(email_string).ok().map(|email_address| email_address.domain().into())
 }
 
 pub fn get_hostname() -> String {
@@ -26,17 +22,6 @@ pub fn get_hostname() -> String {
     }
 }
 
-pub fn init_logger() {
-    if var(RUST_LOG).is_err() {
-        #[cfg(debug_assertions)]
-        set_var(RUST_LOG, "debug");
-        #[cfg(not(debug_assertions))]
-        set_var(RUST_LOG, "info");
-    }
-
-    log_builder().default_format().format_timestamp_nanos().format_indent(None).init();
-}
-
 async fn wait_for_signal(signal_kind: SignalKind) -> AnyResult<()> {
     let mut stream = signal(signal_kind)?;
     stream.recv().await;
@@ -52,3 +37,27 @@ pub(crate) async fn wait_for_stop_signals(shutdown_flag: Arc<AtomicBool>) {
 
     shutdown_flag.store(true, Ordering::Relaxed);
 }
+
+/// Must be created at startup, SIGHUP would otherwise terminate the process.
+pub(crate) fn create_reload_signals() -> AnyResult<Signal> {
+    Ok(signal(SignalKind::hangup())?)
+}
+
+/// Resolves with `true` on the next SIGHUP, or with `false` once a stop signal has set
+/// `shutdown_flag`.
+pub(crate) async fn wait_for_reload_signal(
+    reload_signals: &mut Signal,
+    shutdown_flag: Arc<AtomicBool>,
+) -> bool {
+    wait_for_any! {
+        received = reload_signals.recv() => received.is_some(),
+        _ = wait_for_shutdown(shutdown_flag) => false,
+    }
+}
+
+/// Resolves once a stop signal has set `shutdown_flag`, for servers with graceful shutdown.
+pub(crate) async fn wait_for_shutdown(shutdown_flag: Arc<AtomicBool>) {
+    while !shutdown_flag.load(Ordering::Relaxed) {
+        delay_for(Duration::from_secs(1)).await;
+    }
+}